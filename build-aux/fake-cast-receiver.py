@@ -0,0 +1,214 @@
+#!/usr/bin/env python3
+#
+# Minimal fake cast receiver, to test the Chromecast output without a real device.
+# It speaks just enough of the CASTv2 protocol (heartbeat, receiver and media namespace)
+# to make Shortwave happy, and prints every received message.
+#
+# Usage:
+#   ./build-aux/fake-cast-receiver.py [port]
+#   SHORTWAVE_CAST_RECEIVER=127.0.0.1:8009 shortwave
+#
+# A self-signed certificate gets generated with openssl on the first run.
+
+import json
+import os
+import socket
+import ssl
+import struct
+import subprocess
+import sys
+import tempfile
+import threading
+
+NS_CONNECTION = "urn:x-cast:com.google.cast.tp.connection"
+NS_HEARTBEAT = "urn:x-cast:com.google.cast.tp.heartbeat"
+NS_RECEIVER = "urn:x-cast:com.google.cast.receiver"
+NS_MEDIA = "urn:x-cast:com.google.cast.media"
+
+APP_ID = "CC1AD845"  # DefaultMediaReceiver
+SESSION_ID = "fake-session-1"
+TRANSPORT_ID = "fake-transport-1"
+
+
+# CastMessage protobuf encoding (only the fields we need)
+def encode_varint(value):
+    out = b""
+    while True:
+        byte = value & 0x7F
+        value >>= 7
+        if value:
+            out += bytes([byte | 0x80])
+        else:
+            return out + bytes([byte])
+
+
+def decode_varint(data, pos):
+    result = shift = 0
+    while True:
+        byte = data[pos]
+        pos += 1
+        result |= (byte & 0x7F) << shift
+        shift += 7
+        if not byte & 0x80:
+            return result, pos
+
+
+def encode_message(source, destination, namespace, payload):
+    def string_field(number, value):
+        value = value.encode()
+        return encode_varint(number << 3 | 2) + encode_varint(len(value)) + value
+
+    return (encode_varint(1 << 3) + encode_varint(0)
+            + string_field(2, source)
+            + string_field(3, destination)
+            + string_field(4, namespace)
+            + encode_varint(5 << 3) + encode_varint(0)
+            + string_field(6, json.dumps(payload)))
+
+
+def decode_message(data):
+    fields = {}
+    pos = 0
+    while pos < len(data):
+        key, pos = decode_varint(data, pos)
+        number, wire_type = key >> 3, key & 7
+        if wire_type == 0:
+            value, pos = decode_varint(data, pos)
+        else:
+            length, pos = decode_varint(data, pos)
+            value = data[pos:pos + length].decode(errors="replace")
+            pos += length
+        fields[number] = value
+    return fields.get(2, ""), fields.get(3, ""), fields.get(4, ""), json.loads(fields.get(6, "{}"))
+
+
+class Receiver:
+    def __init__(self, connection):
+        self.connection = connection
+        self.app_running = False
+        self.media = None
+        self.player_state = "IDLE"
+        self.volume = 1.0
+
+    def send(self, source, destination, namespace, payload):
+        data = encode_message(source, destination, namespace, payload)
+        self.connection.sendall(struct.pack(">I", len(data)) + data)
+
+    def receiver_status(self, request_id):
+        applications = []
+        if self.app_running:
+            applications.append({
+                "appId": APP_ID,
+                "displayName": "Default Media Receiver",
+                "namespaces": [{"name": NS_MEDIA}],
+                "sessionId": SESSION_ID,
+                "statusText": "Fake receiver",
+                "transportId": TRANSPORT_ID,
+            })
+        return {
+            "type": "RECEIVER_STATUS",
+            "requestId": request_id,
+            "status": {"applications": applications, "volume": {"level": self.volume, "muted": False}},
+        }
+
+    def media_status(self, request_id):
+        entries = []
+        if self.media is not None:
+            entries.append({
+                "mediaSessionId": 1,
+                "media": self.media,
+                "playbackRate": 1,
+                "playerState": self.player_state,
+                "currentTime": 0,
+                "supportedMediaCommands": 15,
+                "volume": {"level": self.volume, "muted": False},
+            })
+        return {"type": "MEDIA_STATUS", "requestId": request_id, "status": entries}
+
+    def handle(self, source, destination, namespace, payload):
+        message_type = payload.get("type")
+        request_id = payload.get("requestId", 0)
+
+        if namespace == NS_HEARTBEAT and message_type == "PING":
+            self.send(destination, source, namespace, {"type": "PONG"})
+        elif namespace == NS_RECEIVER:
+            if message_type == "LAUNCH":
+                self.app_running = True
+            elif message_type == "STOP":
+                self.app_running = False
+                self.media = None
+            elif message_type == "SET_VOLUME":
+                self.volume = payload.get("volume", {}).get("level", self.volume)
+            self.send(destination, source, namespace, self.receiver_status(request_id))
+        elif namespace == NS_MEDIA:
+            if message_type == "LOAD":
+                self.media = payload.get("media")
+                self.player_state = "PLAYING"
+                print("Now playing: {}".format(self.media.get("contentId")), flush=True)
+            elif message_type == "PLAY":
+                self.player_state = "PLAYING"
+            elif message_type == "PAUSE":
+                self.player_state = "PAUSED"
+            elif message_type == "STOP":
+                self.media = None
+                self.player_state = "IDLE"
+            self.send(destination, source, namespace, self.media_status(request_id))
+
+    def run(self):
+        while True:
+            header = self.read(4)
+            if header is None:
+                return
+            data = self.read(struct.unpack(">I", header)[0])
+            if data is None:
+                return
+
+            source, destination, namespace, payload = decode_message(data)
+            print("{} -> {} [{}] {}".format(source, destination, namespace, payload), flush=True)
+            self.handle(source, destination, namespace, payload)
+
+    def read(self, length):
+        data = b""
+        while len(data) < length:
+            chunk = self.connection.recv(length - len(data))
+            if not chunk:
+                return None
+            data += chunk
+        return data
+
+
+def create_certificate():
+    directory = tempfile.mkdtemp(prefix="fake-cast-receiver-")
+    cert = os.path.join(directory, "cert.pem")
+    key = os.path.join(directory, "key.pem")
+    subprocess.run(["openssl", "req", "-x509", "-newkey", "rsa:2048", "-nodes", "-subj", "/CN=fake-cast-receiver",
+                    "-keyout", key, "-out", cert, "-days", "1"], check=True, stderr=subprocess.DEVNULL)
+    return cert, key
+
+
+def main():
+    port = int(sys.argv[1]) if len(sys.argv) > 1 else 8009
+    cert, key = create_certificate()
+
+    context = ssl.SSLContext(ssl.PROTOCOL_TLS_SERVER)
+    context.load_cert_chain(cert, key)
+
+    server = socket.socket(socket.AF_INET, socket.SOCK_STREAM)
+    server.setsockopt(socket.SOL_SOCKET, socket.SO_REUSEADDR, 1)
+    server.bind(("127.0.0.1", port))
+    server.listen()
+    print("Fake cast receiver listening on 127.0.0.1:{}".format(port), flush=True)
+
+    while True:
+        connection, address = server.accept()
+        print("Connection from {}".format(address), flush=True)
+        try:
+            connection = context.wrap_socket(connection, server_side=True)
+        except ssl.SSLError as err:
+            print("TLS handshake failed: {}".format(err), flush=True)
+            continue
+        threading.Thread(target=Receiver(connection).run, daemon=True).start()
+
+
+if __name__ == "__main__":
+    main()
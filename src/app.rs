@@ -5,12 +5,23 @@ use rustio::{Station, StationSearch};
 
 use std::cell::RefCell;
 use std::rc::Rc;
+use std::time::Duration;
 
 use crate::config;
 use crate::library::Library;
 use crate::model::{Order, Sorting};
+use crate::player::chromecast::CastReceiver;
+use crate::player::effects::Effects;
+use crate::player::sleep_timer::SleepTimerMode;
 use crate::player::{PlaybackState, Player};
+use crate::scheduler::{Alarm, ScheduledRecording, Scheduler};
 use crate::search::Search;
+use crate::settings::{Key, SettingsManager};
+use crate::title_filter::TitleFilter;
+use crate::widgets::alarm_dialog::AlarmDialog;
+use crate::widgets::history_dialog::HistoryDialog;
+use crate::widgets::recording_schedule_dialog::RecordingScheduleDialog;
+use crate::widgets::settings_dialog::SettingsDialog;
 use crate::window::{View, Window};
 
 #[derive(Debug, Clone)]
@@ -19,15 +30,35 @@ pub enum Action {
     ViewShowLibrary,
     ViewShowNotification(String),
     ViewRaise,
+    ViewShowAlarms,
+    ViewShowScheduledRecordings,
+    ViewShowHistory,
+    ViewShowPreferences,
     ViewSetSorting(Sorting, Order),
     PlaybackSetStation(Station),
     PlaybackStart,
+    PlaybackPause,
     PlaybackStop,
+    PlaybackSetTimeshiftDelay(Duration),
+    PlaybackSetVolume(f64),
+    PlaybackSetAlarmVolume(Option<f64>),
+    PlaybackToggleMute,
+    PlaybackSetCastReceiver(Option<CastReceiver>),
+    PlaybackSetAudioOutput(String),
+    PlaybackSetEffects(Effects),
+    PlaybackSetSleepTimer(SleepTimerMode),
+    PlaybackExtendSleepTimer(Duration),
+    PlaybackCancelSleepTimer,
+    PlaybackUpdateRecordingMode,
     LibraryImport,
     LibraryExport,
     LibraryAddStations(Vec<Station>),
     LibraryRemoveStations(Vec<Station>),
     SearchFor(StationSearch),
+    SchedulerSetAlarms(Vec<Alarm>),
+    SchedulerCheckAlarm,
+    SchedulerStopAlarm,
+    SchedulerSetRecordings(Vec<ScheduledRecording>),
 }
 
 pub struct App {
@@ -40,6 +71,9 @@ pub struct App {
     player: Player,
     library: Library,
     search: Search,
+    scheduler: Scheduler,
+    // Kept alive for the change notifications
+    settings: gio::Settings,
 }
 
 impl App {
@@ -57,6 +91,7 @@ impl App {
         let player = Player::new(sender.clone());
         let library = Library::new(sender.clone());
         let search = Search::new(sender.clone());
+        let scheduler = Scheduler::new(sender.clone());
 
         window.player_box.add(&player.widget);
         window.library_box.add(&library.widget);
@@ -75,6 +110,8 @@ impl App {
             player,
             library,
             search,
+            scheduler,
+            settings: SettingsManager::get_settings(),
         });
 
         glib::set_application_name(config::NAME);
@@ -97,6 +134,7 @@ impl App {
 
         self.gtk_app.run(&[]);
         self.player.shutdown();
+        self.scheduler.shutdown();
     }
 
     fn setup_gaction(&self) {
@@ -118,6 +156,64 @@ impl App {
         });
         self.gtk_app.set_accels_for_action("app.search", &["<primary>f"]);
 
+        // Mute / unmute playback
+        let sender = self.sender.clone();
+        self.add_gaction("toggle-mute", move |_, _| {
+            sender.send(Action::PlaybackToggleMute).unwrap();
+        });
+        self.gtk_app.set_accels_for_action("app.toggle-mute", &["<primary>m"]);
+
+        // Sleep timer, the parameter is the duration in minutes (0 = end of the current song)
+        let sender = self.sender.clone();
+        let sleep_timer_action = gio::SimpleAction::new("sleep-timer", Some(glib::VariantTy::new("u").unwrap()));
+        sleep_timer_action.connect_activate(move |_, parameter| {
+            let minutes = parameter.as_ref().and_then(|p| p.get::<u32>()).unwrap_or(0);
+            let mode = if minutes == 0 {
+                SleepTimerMode::EndOfSong
+            } else {
+                SleepTimerMode::Duration(Duration::from_secs(u64::from(minutes) * 60))
+            };
+            sender.send(Action::PlaybackSetSleepTimer(mode)).unwrap();
+        });
+        self.gtk_app.add_action(&sleep_timer_action);
+
+        // Extend sleep timer
+        let sender = self.sender.clone();
+        self.add_gaction("extend-sleep-timer", move |_, _| {
+            sender.send(Action::PlaybackExtendSleepTimer(Duration::from_secs(15 * 60))).unwrap();
+        });
+
+        // Cancel sleep timer
+        let sender = self.sender.clone();
+        self.add_gaction("cancel-sleep-timer", move |_, _| {
+            sender.send(Action::PlaybackCancelSleepTimer).unwrap();
+        });
+
+        // Alarms
+        let sender = self.sender.clone();
+        self.add_gaction("alarms", move |_, _| {
+            sender.send(Action::ViewShowAlarms).unwrap();
+        });
+
+        // Scheduled recordings
+        let sender = self.sender.clone();
+        self.add_gaction("scheduled-recordings", move |_, _| {
+            sender.send(Action::ViewShowScheduledRecordings).unwrap();
+        });
+
+        // History
+        let sender = self.sender.clone();
+        self.add_gaction("history", move |_, _| {
+            sender.send(Action::ViewShowHistory).unwrap();
+        });
+        self.gtk_app.set_accels_for_action("app.history", &["<primary>h"]);
+
+        // Preferences
+        let sender = self.sender.clone();
+        self.add_gaction("preferences", move |_, _| {
+            sender.send(Action::ViewShowPreferences).unwrap();
+        });
+
         // Import library
         let sender = self.sender.clone();
         self.add_gaction("import-library", move |_, _| {
@@ -189,6 +285,16 @@ impl App {
     fn setup_signals(&self) {
         let window = self.window.widget.clone();
         self.gtk_app.connect_activate(move |app| app.add_window(&window));
+
+        // The title filter keeps its patterns until they get changed
+        self.settings.connect_changed(|_, key| {
+            if key == Key::TitleFilterPatterns.as_str() || key == Key::TitleFilterBuiltin.as_str() {
+                TitleFilter::reload();
+            }
+        });
+        // GSettings only notifies about keys which have been read
+        self.settings.get_string(Key::TitleFilterPatterns.as_str());
+        self.settings.get_boolean(Key::TitleFilterBuiltin.as_str());
     }
 
     fn process_action(&self, action: Action) -> glib::Continue {
@@ -197,18 +303,56 @@ impl App {
             Action::ViewShowLibrary => self.window.set_view(View::Library),
             Action::ViewRaise => self.window.widget.present_with_time((glib::get_monotonic_time() / 1000) as u32),
             Action::ViewShowNotification(text) => self.window.show_notification(text),
+            Action::ViewShowAlarms => {
+                let dialog = AlarmDialog::new(self.sender.clone(), self.scheduler.get_alarms(), self.library.to_vec(), &self.window.widget.clone().upcast());
+                dialog.show();
+            }
+            Action::ViewShowScheduledRecordings => {
+                let dialog = RecordingScheduleDialog::new(self.sender.clone(), self.scheduler.get_recordings(), self.library.to_vec(), &self.window.widget.clone().upcast());
+                dialog.show();
+            }
+            Action::ViewShowHistory => {
+                let dialog = HistoryDialog::new(self.sender.clone(), &self.window.widget.clone().upcast());
+                dialog.show();
+            }
+            Action::ViewShowPreferences => {
+                let dialog = SettingsDialog::new(self.sender.clone(), &self.window.widget.clone().upcast());
+                dialog.show();
+            }
             Action::ViewSetSorting(sorting, order) => self.library.set_sorting(sorting, order),
             Action::PlaybackSetStation(station) => {
                 self.player.set_station(station.clone());
                 self.window.show_sidebar_player(true);
             }
             Action::PlaybackStart => self.player.set_playback(PlaybackState::Playing),
-            Action::PlaybackStop => self.player.set_playback(PlaybackState::Stopped),
+            Action::PlaybackPause => {
+                self.scheduler.stop_ringing();
+                self.player.set_playback(PlaybackState::Paused);
+            }
+            Action::PlaybackStop => {
+                self.scheduler.stop_ringing();
+                self.player.set_playback(PlaybackState::Stopped);
+            }
+            Action::PlaybackSetTimeshiftDelay(delay) => self.player.set_timeshift_delay(delay),
+            Action::PlaybackSetVolume(volume) => self.player.set_volume(volume),
+            Action::PlaybackSetAlarmVolume(volume) => self.player.set_alarm_volume(volume),
+            Action::PlaybackToggleMute => self.player.toggle_mute(),
+            Action::PlaybackSetCastReceiver(receiver) => self.player.set_cast_receiver(receiver),
+            Action::PlaybackSetAudioOutput(id) => self.player.set_audio_output(&id),
+            Action::PlaybackSetEffects(effects) => self.player.set_effects(effects),
+            Action::PlaybackSetSleepTimer(mode) => self.player.set_sleep_timer(mode),
+            Action::PlaybackExtendSleepTimer(duration) => self.player.extend_sleep_timer(duration),
+            Action::PlaybackCancelSleepTimer => self.player.cancel_sleep_timer(),
+            Action::PlaybackUpdateRecordingMode => self.player.update_recording_mode(),
             Action::LibraryImport => self.import_stations(),
             Action::LibraryExport => self.export_stations(),
             Action::LibraryAddStations(stations) => self.library.add_stations(stations),
             Action::LibraryRemoveStations(stations) => self.library.remove_stations(stations),
             Action::SearchFor(data) => self.search.search_for(data),
+            Action::SchedulerSetAlarms(alarms) => self.scheduler.set_alarms(alarms),
+            Action::SchedulerCheckAlarm => self.check_alarm(),
+            Action::SchedulerStopAlarm => self.scheduler.stop_ringing(),
+            Action::SchedulerSetRecordings(recordings) => self.scheduler.set_recordings(recordings),
         }
         glib::Continue(true)
     }
@@ -231,6 +375,27 @@ impl App {
         dialog.show();
     }
 
+    // The station of the ringing alarm doesn't play (e.g. no network), wake up the user with the alarm tone instead
+    fn check_alarm(&self) {
+        if !self.scheduler.is_ringing() || self.player.is_stream_connected() {
+            return;
+        }
+
+        self.player.set_playback(PlaybackState::Stopped);
+        self.scheduler.play_fallback_tone();
+
+        let dialog = gtk::MessageDialog::new(Some(&self.window.widget), gtk::DialogFlags::MODAL, gtk::MessageType::Info, gtk::ButtonsType::None, "Alarm");
+        dialog.set_property_secondary_text(Some("The station could not be played, so the alarm tone is playing instead."));
+        dialog.add_button("Stop alarm", gtk::ResponseType::Accept);
+
+        let sender = self.sender.clone();
+        dialog.connect_response(move |dialog, _| {
+            sender.send(Action::SchedulerStopAlarm).unwrap();
+            dialog.destroy();
+        });
+        dialog.show();
+    }
+
     fn import_stations(&self) {
         let import_dialog = gtk::FileChooserNative::new("Select database to import", &self.window.widget, gtk::FileChooserAction::Open, "Import", "Cancel");
         let filter = gtk::FileFilter::new();
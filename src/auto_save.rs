@@ -0,0 +1,86 @@
+use rustio::Station;
+
+use std::time::Duration;
+
+use crate::json_file::JsonFile;
+use crate::recording_index::RecordingIndex;
+use crate::settings::{Key, SettingsManager};
+use crate::song::Song;
+
+lazy_static! {
+    static ref STATIONS: JsonFile<Vec<String>> = JsonFile::new("auto_save_stations.json");
+}
+
+/// Copies finished recordings into the music folder, so they don't get lost when they drop out of the song list.
+///
+/// A song gets saved if every song gets saved, if its station gets saved, or if its artist is on the watchlist.
+/// In any case the recording has to reach the minimum bitrate and length.
+pub struct AutoSave {}
+
+impl AutoSave {
+    pub fn applies(song: &Song, station: &Station) -> bool {
+        let minimum_bitrate = SettingsManager::get_uint(Key::AutoSaveMinimumBitrate);
+        if song.get_bitrate() < minimum_bitrate {
+            debug!("Don't auto-save \"{}\": below {} kbit/s", song.title, minimum_bitrate);
+            return false;
+        }
+
+        let minimum_duration = Duration::from_secs(SettingsManager::get_uint(Key::AutoSaveMinimumDuration).into());
+        if song.duration < minimum_duration {
+            debug!("Don't auto-save \"{}\": shorter than {:?}", song.title, minimum_duration);
+            return false;
+        }
+
+        // There's no need for a second copy of the same song
+        if song.chapters.is_empty() && RecordingIndex::find(&song.title, &song.artist).is_some() {
+            debug!("Don't auto-save \"{}\": already saved", song.title);
+            return false;
+        }
+
+        SettingsManager::get_boolean(Key::AutoSaveAllSongs) || Self::is_station_enabled(&station.id) || Self::is_watched(&song.artist)
+    }
+
+    /// Saves the song into the music folder and marks it, so the song list can show it.
+    pub fn save(song: &mut Song) {
+        match song.save() {
+            Ok(path) => {
+                debug!("Auto-saved \"{}\" as {:?}", song.title, path);
+                song.auto_saved = true;
+            }
+            Err(err) => warn!("Could not auto-save \"{}\": {}", song.title, err),
+        }
+    }
+
+    // The setting separates the artists with commas
+    fn get_watchlist() -> Vec<String> {
+        let watchlist = SettingsManager::get_string(Key::AutoSaveArtists);
+        watchlist.split(',').map(|artist| RecordingIndex::normalize(artist)).filter(|artist| !artist.is_empty()).collect()
+    }
+
+    // "Daft Punk" also matches "Daft Punk feat. Pharrell Williams", but "Train" doesn't match "Trainwreck"
+    fn is_watched(artist: &Option<String>) -> bool {
+        let artist = match artist.as_ref() {
+            Some(artist) => format!(" {} ", RecordingIndex::normalize(artist)),
+            None => return false,
+        };
+        Self::get_watchlist().iter().any(|watched| artist.contains(&format!(" {} ", watched)))
+    }
+
+    pub fn is_station_enabled(station_id: &str) -> bool {
+        STATIONS.with(|stations| stations.iter().any(|id| id == station_id))
+    }
+
+    pub fn set_station_enabled(station_id: &str, enabled: bool) {
+        let result = STATIONS.update(|stations| {
+            stations.retain(|id| id != station_id);
+            if enabled {
+                stations.push(station_id.to_string());
+            }
+            true
+        });
+
+        if let Err(err) = result {
+            warn!("Could not save auto-save stations: {}", err);
+        }
+    }
+}
@@ -0,0 +1,230 @@
+use chrono::Local;
+
+use std::path::PathBuf;
+
+use crate::settings::{Key, SettingsManager};
+use crate::song::Song;
+
+// File systems allow 255 bytes, this leaves room for " (2)" and ".chapters.txt"
+const MAX_NAME_BYTES: usize = 200;
+
+const PLACEHOLDERS: [&str; 6] = ["{station}", "{artist}", "{title}", "{date}", "{time}", "{ext}"];
+
+/// Names the files of saved songs, e.g. `{station}/{artist} - {title} ({date}).{ext}`.
+/// Every `/` creates a folder, the extension of the recording always gets added.
+pub struct FilenameTemplate {}
+
+impl FilenameTemplate {
+    /// Where `song` gets saved to in the music folder. Existing files get a numbered suffix instead of being overwritten.
+    pub fn get_path(song: &Song) -> PathBuf {
+        let mut path = Self::get_music_dir();
+        path.push(Self::render(&SettingsManager::get_string(Key::RecordingFilenameTemplate), song));
+        Song::get_unique_path(path)
+    }
+
+    /// The music folder of the user. Not every system has one, in that case the home folder gets used.
+    pub fn get_music_dir() -> PathBuf {
+        glib::get_user_special_dir(glib::UserDirectory::Music)
+            .map(PathBuf::from)
+            .or_else(|| glib::get_home_dir().map(PathBuf::from))
+            .unwrap_or_else(|| glib::get_user_data_dir().unwrap())
+    }
+
+    /// The path of `song` relative to the music folder.
+    pub fn render(template: &str, song: &Song) -> PathBuf {
+        let extension = song.path.extension().map(|extension| extension.to_string_lossy().to_string()).unwrap_or_default();
+        let date = song.get_date();
+        let values = [
+            song.station.clone().unwrap_or_default(),
+            song.artist.clone().unwrap_or_default(),
+            song.title.clone(),
+            date.format("%Y-%m-%d").to_string(),
+            date.format("%H-%M").to_string(),
+            extension.clone(),
+        ];
+
+        // The extension gets added at the end anyway
+        let template = template.trim_end_matches(".{ext}");
+        let mut components: Vec<String> = Vec::new();
+        for component in template.split('/') {
+            // Slashes in titles get removed here, so they can't create folders
+            let mut name = component.to_string();
+            for (placeholder, value) in PLACEHOLDERS.iter().zip(values.iter()) {
+                name = name.replace(placeholder, value);
+            }
+            let name = Self::sanitize(&name);
+            if !name.is_empty() {
+                components.push(name);
+            }
+        }
+
+        // An empty template still has to name the file somehow
+        let name = components.pop().unwrap_or_else(|| Self::sanitize(&song.get_display_title()));
+        let name = if name.is_empty() { "Unknown".to_string() } else { name };
+        let mut path: PathBuf = components.iter().collect();
+        match extension.as_str() {
+            "" => path.push(name),
+            extension => path.push(format!("{}.{}", name, extension)),
+        }
+        path
+    }
+
+    /// Makes `text` usable as file name on every common file system.
+    /// Dots at the start and the end get removed, so the name can't be `..` or a hidden file, and can't end in a fake extension.
+    pub fn sanitize(text: &str) -> String {
+        let mut name = String::new();
+        for c in text.chars() {
+            match c {
+                '/' | '\\' | ':' | '<' | '>' | '"' | '|' | '?' | '*' => (),
+                // Tabs, non-breaking spaces, ideographic spaces, ...
+                c if c.is_whitespace() => name.push(' '),
+                c if c.is_control() || Self::is_invisible(c) => (),
+                c => name.push(c),
+            }
+        }
+
+        // A missing artist would leave " - Title"
+        let name = name.split_whitespace().collect::<Vec<&str>>().join(" ");
+        Self::truncate(name.trim_matches(|c: char| c == ' ' || c == '-' || c == '_' || c == '.'))
+    }
+
+    /// Appends `.extension` to the file name. Unlike `PathBuf::set_extension`, this keeps dots in the name (e.g. "R.E.M.").
+    pub fn add_extension(path: &mut PathBuf, extension: &str) {
+        let mut name = path.file_name().map(|name| name.to_os_string()).unwrap_or_default();
+        name.push(".");
+        name.push(extension);
+        path.set_file_name(name);
+    }
+
+    // Zero width characters and bidi controls can hide the real name of a file
+    fn is_invisible(c: char) -> bool {
+        match c {
+            '\u{200B}'..='\u{200F}' | '\u{202A}'..='\u{202E}' | '\u{2060}'..='\u{2064}' | '\u{2066}'..='\u{2069}' | '\u{FEFF}' => true,
+            _ => false,
+        }
+    }
+
+    // The limit is in bytes, so multi-byte characters can't get cut in half
+    fn truncate(name: &str) -> String {
+        if name.len() <= MAX_NAME_BYTES {
+            return name.to_string();
+        }
+
+        let mut end = MAX_NAME_BYTES;
+        while !name.is_char_boundary(end) {
+            end -= 1;
+        }
+        name[..end].trim_end_matches(|c: char| c.is_whitespace() || c == '.').to_string()
+    }
+
+    /// Example path for the settings, so the template can be checked without saving a song.
+    pub fn get_example(template: &str, extension: &str) -> PathBuf {
+        let song = Song {
+            title: "Title".to_string(),
+            artist: Some("Artist".to_string()),
+            station: Some("Station".to_string()),
+            path: PathBuf::from(format!("song.{}", extension)),
+            duration: Default::default(),
+            timestamp: Local::now().timestamp(),
+            chapters: Vec::new(),
+            auto_saved: false,
+        };
+        Self::render(template, &song)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn song(title: &str, artist: Option<&str>) -> Song {
+        Song {
+            title: title.to_string(),
+            artist: artist.map(str::to_string),
+            station: Some("Station".to_string()),
+            path: PathBuf::from("song.ogg"),
+            duration: Default::default(),
+            timestamp: 1_500_000_000,
+            chapters: Vec::new(),
+            auto_saved: false,
+        }
+    }
+
+    #[test]
+    fn render_template() {
+        let song = song("Title", Some("Artist"));
+        assert_eq!(FilenameTemplate::render("{station}/{artist} - {title}.{ext}", &song), PathBuf::from("Station/Artist - Title.ogg"));
+        assert_eq!(FilenameTemplate::render("{artist} - {title}", &song), PathBuf::from("Artist - Title.ogg"));
+        // Slashes within values can't create folders
+        let song = self::song("AC/DC", None);
+        assert_eq!(FilenameTemplate::render("{station}/{title}", &song), PathBuf::from("Station/ACDC.ogg"));
+        // Dots within the name stay, the extension gets added anyway
+        let song = self::song("Man on the Moon", Some("R.E.M."));
+        assert_eq!(FilenameTemplate::render("{artist} - {title}", &song), PathBuf::from("R.E.M. - Man on the Moon.ogg"));
+        assert_eq!(FilenameTemplate::render("{artist}/{title}", &song), PathBuf::from("R.E.M/Man on the Moon.ogg"));
+    }
+
+    #[test]
+    fn unknown_placeholders_stay() {
+        let song = song("Title", Some("Artist"));
+        assert_eq!(FilenameTemplate::render("{album} - {title}", &song), PathBuf::from("{album} - Title.ogg"));
+    }
+
+    #[test]
+    fn empty_result() {
+        // A missing artist leaves neither the separator nor an empty folder
+        let song = song("Title", None);
+        assert_eq!(FilenameTemplate::render("{artist}/{artist} - {title}", &song), PathBuf::from("Title.ogg"));
+        assert_eq!(FilenameTemplate::render("", &song), PathBuf::from("Title.ogg"));
+        assert_eq!(FilenameTemplate::render("{artist}", &song), PathBuf::from("Title.ogg"));
+
+        let song = self::song("", None);
+        assert_eq!(FilenameTemplate::render("{title}", &song), PathBuf::from("Unknown.ogg"));
+    }
+
+    #[test]
+    fn sanitize_dots_and_reserved_characters() {
+        assert_eq!(FilenameTemplate::sanitize("../.."), "");
+        assert_eq!(FilenameTemplate::sanitize(".hidden"), "hidden");
+        assert_eq!(FilenameTemplate::sanitize("Mr. Brightside"), "Mr. Brightside");
+        assert_eq!(FilenameTemplate::sanitize("R.E.M."), "R.E.M");
+        assert_eq!(FilenameTemplate::sanitize("Title.mp3."), "Title.mp3");
+        assert_eq!(FilenameTemplate::sanitize("What? <Yes>: \"No\" | *"), "What Yes No");
+    }
+
+    #[test]
+    fn add_extension_keeps_dots() {
+        let mut path = PathBuf::from("Music/01 R.E.M - Man on the Moon");
+        FilenameTemplate::add_extension(&mut path, "ogg");
+        assert_eq!(path, PathBuf::from("Music/01 R.E.M - Man on the Moon.ogg"));
+    }
+
+    #[test]
+    fn sanitize_control_and_invisible_characters() {
+        assert_eq!(FilenameTemplate::sanitize("Ti\u{0}t\u{7}le\n"), "Title");
+        assert_eq!(FilenameTemplate::sanitize("Ti\u{200B}tle\u{FEFF}"), "Title");
+        // Bidi overrides could make "exe.mp3" look like "3pm.exe"
+        assert_eq!(FilenameTemplate::sanitize("\u{202E}3pm.exe"), "3pm.exe");
+        // Other whitespace becomes a single space
+        assert_eq!(FilenameTemplate::sanitize("Artist\t\u{A0}-\u{3000}Title"), "Artist - Title");
+        assert_eq!(FilenameTemplate::sanitize(" - Title_ "), "Title");
+    }
+
+    #[test]
+    fn sanitize_truncates_on_char_boundary() {
+        let name = "a".repeat(MAX_NAME_BYTES + 10);
+        assert_eq!(FilenameTemplate::sanitize(&name).len(), MAX_NAME_BYTES);
+
+        // "ä" has two bytes, the 200th byte is in the middle of one
+        let name = format!("a{}", "ä".repeat(MAX_NAME_BYTES));
+        let sanitized = FilenameTemplate::sanitize(&name);
+        assert_eq!(sanitized.len(), MAX_NAME_BYTES - 1);
+        assert!(sanitized.ends_with('ä'));
+
+        // No space or dot is left at the end of the cut
+        let name = format!("{} {}", "a".repeat(MAX_NAME_BYTES - 1), "b".repeat(10));
+        assert_eq!(FilenameTemplate::sanitize(&name), "a".repeat(MAX_NAME_BYTES - 1));
+        let name = format!("{}.{}", "a".repeat(MAX_NAME_BYTES - 1), "b".repeat(10));
+        assert_eq!(FilenameTemplate::sanitize(&name), "a".repeat(MAX_NAME_BYTES - 1));
+    }
+}
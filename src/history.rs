@@ -0,0 +1,139 @@
+use chrono::{DateTime, Local, TimeZone};
+use rustio::Station;
+
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use crate::json_file::JsonFile;
+use crate::metadata::SongMetadata;
+
+lazy_static! {
+    static ref HISTORY: JsonFile<Vec<HistoryEntry>> = JsonFile::new("history.json");
+}
+
+// Older entries get removed, so the file can't grow forever
+const MAX_ENTRIES: usize = 5000;
+
+/// A song which has been heard, the newest entry is the last one.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    // Unix timestamp (seconds) of the title change
+    pub timestamp: i64,
+    pub station_id: String,
+    pub station_name: String,
+    pub title: String,
+    pub artist: Option<String>,
+    pub recorded: bool,
+}
+
+impl HistoryEntry {
+    pub fn get_time(&self) -> DateTime<Local> {
+        Local.timestamp(self.timestamp, 0)
+    }
+
+    /// "Artist - Title", or only the title if we don't know the artist.
+    pub fn get_display_title(&self) -> String {
+        match self.artist.as_ref() {
+            Some(artist) => format!("{} - {}", artist, self.title),
+            None => self.title.clone(),
+        }
+    }
+
+    /// Case insensitive search in title, artist and station name.
+    pub fn matches(&self, text: &str) -> bool {
+        let text = text.to_lowercase();
+        self.title.to_lowercase().contains(&text) || self.artist.as_ref().map_or(false, |artist| artist.to_lowercase().contains(&text)) || self.station_name.to_lowercase().contains(&text)
+    }
+
+    fn is_song(&self, station_id: &str, title: &str, artist: &Option<String>) -> bool {
+        self.station_id == station_id && self.title == title && self.artist == *artist
+    }
+}
+
+/// Persistent list of every song which has been heard.
+pub struct History {}
+
+impl History {
+    /// Oldest entry first.
+    pub fn get_entries() -> Vec<HistoryEntry> {
+        HISTORY.with(|entries| entries.clone())
+    }
+
+    /// The last `count` songs of the station, newest entry first.
+    pub fn get_station_entries(station_id: &str, count: usize) -> Vec<HistoryEntry> {
+        Self::get_entries().into_iter().rev().filter(|entry| entry.station_id == station_id).take(count).collect()
+    }
+
+    pub fn add_song(station: &Station, song: &SongMetadata) {
+        let result = HISTORY.update(|entries| {
+            // The title gets sent again after a reconnect, but it's still the same song
+            if entries.last().map_or(false, |entry| entry.is_song(&station.id, &song.title, &song.artist)) {
+                return false;
+            }
+
+            entries.push(HistoryEntry {
+                timestamp: Local::now().timestamp(),
+                station_id: station.id.clone(),
+                station_name: station.name.clone(),
+                title: song.title.clone(),
+                artist: song.artist.clone(),
+                recorded: false,
+            });
+            if entries.len() > MAX_ENTRIES {
+                let removed = entries.len() - MAX_ENTRIES;
+                entries.drain(..removed);
+            }
+            true
+        });
+
+        if let Err(err) = result {
+            warn!("Could not save history: {}", err);
+        }
+    }
+
+    /// Marks the last time the song has been heard on the station as recorded.
+    pub fn set_recorded(station_id: &str, title: &str, artist: &Option<String>) {
+        let result = HISTORY.update(|entries| match entries.iter_mut().rev().find(|entry| entry.is_song(station_id, title, artist)) {
+            Some(entry) => {
+                entry.recorded = true;
+                true
+            }
+            None => false,
+        });
+
+        if let Err(err) = result {
+            warn!("Could not save history: {}", err);
+        }
+    }
+
+    pub fn export_json(entries: &[HistoryEntry], path: PathBuf) -> Result<(), io::Error> {
+        let data = serde_json::to_string_pretty(entries)?;
+        fs::write(path, data)
+    }
+
+    pub fn export_csv(entries: &[HistoryEntry], path: PathBuf) -> Result<(), io::Error> {
+        let mut data = String::from("time,station,artist,title,recorded\n");
+        for entry in entries {
+            let fields = [
+                entry.get_time().to_rfc3339(),
+                entry.station_name.clone(),
+                entry.artist.clone().unwrap_or_default(),
+                entry.title.clone(),
+                entry.recorded.to_string(),
+            ];
+            let fields: Vec<String> = fields.iter().map(|field| Self::escape_csv(field)).collect();
+            data += &format!("{}\n", fields.join(","));
+        }
+        fs::write(path, data)
+    }
+
+    // RFC 4180: fields with separators, quotes or line breaks get quoted, quotes get doubled
+    fn escape_csv(field: &str) -> String {
+        if field.contains(|c: char| c == ',' || c == '"' || c == '\n' || c == '\r') {
+            format!("\"{}\"", field.replace("\"", "\"\""))
+        } else {
+            field.to_string()
+        }
+    }
+}
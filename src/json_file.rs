@@ -0,0 +1,91 @@
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use crate::config;
+
+/// A JSON file in the data folder, e.g. `history.json`. It gets read once, and is kept in memory afterwards.
+///
+/// Changes get written to a temporary file first, which replaces the old file afterwards,
+/// so a crash can't leave a half written file behind. A file which can't be read gets moved aside
+/// (`.broken`), otherwise every following change would fail as well.
+pub struct JsonFile<T> {
+    path: PathBuf,
+    // None until the file has been read
+    data: Mutex<Option<T>>,
+}
+
+impl<T: Serialize + DeserializeOwned + Default> JsonFile<T> {
+    pub fn new(name: &str) -> Self {
+        let mut path = glib::get_user_data_dir().unwrap();
+        path.push(config::NAME);
+        path.push(name);
+
+        Self { path, data: Mutex::new(None) }
+    }
+
+    pub fn with<R, F: FnOnce(&T) -> R>(&self, f: F) -> R {
+        let mut data = self.data.lock().unwrap();
+        f(data.get_or_insert_with(|| self.read()))
+    }
+
+    /// Changes the data, it gets written if `f` returns true.
+    pub fn update<F: FnOnce(&mut T) -> bool>(&self, f: F) -> Result<(), io::Error> {
+        let mut data = self.data.lock().unwrap();
+        let data = data.get_or_insert_with(|| self.read());
+        if f(data) {
+            self.write(data)?;
+        }
+        Ok(())
+    }
+
+    /// Replaces the data, for owners which keep their own copy (e.g. the song model).
+    pub fn set(&self, data: T) -> Result<(), io::Error> {
+        let mut current = self.data.lock().unwrap();
+        let result = self.write(&data);
+        *current = Some(data);
+        result
+    }
+
+    fn read(&self) -> T {
+        if !self.path.exists() {
+            return T::default();
+        }
+
+        debug!("Read {:?}", self.path);
+        match self.read_file() {
+            Ok(data) => data,
+            Err(err) => {
+                warn!("Could not read {:?}: {}", self.path, err);
+                let mut backup = self.path.clone().into_os_string();
+                backup.push(".broken");
+                if let Err(err) = fs::rename(&self.path, &backup) {
+                    warn!("Could not move {:?} aside: {}", self.path, err);
+                }
+                T::default()
+            }
+        }
+    }
+
+    fn read_file(&self) -> Result<T, io::Error> {
+        let data = fs::read_to_string(&self.path)?;
+        Ok(serde_json::from_str(&data)?)
+    }
+
+    fn write(&self, data: &T) -> Result<(), io::Error> {
+        let data = serde_json::to_string(data)?;
+
+        let mut path = self.path.clone();
+        path.pop();
+        fs::create_dir_all(path)?;
+
+        let mut temp_path = self.path.clone().into_os_string();
+        temp_path.push(".tmp");
+        fs::write(&temp_path, data)?;
+        fs::rename(temp_path, &self.path)
+    }
+}
@@ -17,11 +17,20 @@ mod player;
 mod widgets;
 
 mod app;
+mod auto_save;
 mod config;
+mod filename_template;
+mod history;
+mod json_file;
 mod library;
+mod metadata;
+mod recording_index;
+mod scheduler;
 mod search;
+mod settings;
 mod song;
 mod static_resource;
+mod title_filter;
 mod window;
 
 use crate::app::App;
@@ -0,0 +1,247 @@
+use rustio::Station;
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::json_file::JsonFile;
+
+lazy_static! {
+    static ref PATTERNS: JsonFile<HashMap<String, String>> = JsonFile::new("metadata_patterns.json");
+}
+
+// Separators between artist and title, the first one which is found wins
+static SEPARATORS: [&str; 5] = [" - ", " – ", " — ", " | ", " ~ "];
+
+// Placeholders of the per-station patterns, e.g. "{title} by {artist}"
+pub static PATTERN_ARTIST: &str = "{artist}";
+pub static PATTERN_TITLE: &str = "{title}";
+pub static PATTERN_IGNORE: &str = "{*}";
+
+// Changes every time a pattern gets set, so the parsers know that their cached pattern is outdated
+static PATTERNS_GENERATION: AtomicUsize = AtomicUsize::new(0);
+
+/// The song which is currently played, parsed from the stream title.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SongMetadata {
+    // Unchanged title of the stream (ICY StreamTitle)
+    pub stream_title: String,
+    pub title: String,
+    pub artist: Option<String>,
+}
+
+impl SongMetadata {
+    pub fn is_empty(&self) -> bool {
+        self.stream_title.is_empty()
+    }
+
+    /// "Artist - Title", or only the title if we don't know the artist.
+    pub fn get_display_title(&self) -> String {
+        match self.artist.as_ref() {
+            Some(artist) => format!("{} - {}", artist, self.title),
+            None => self.title.clone(),
+        }
+    }
+}
+
+enum Token {
+    Text(String),
+    Artist,
+    Title,
+    Ignore,
+}
+
+/// Splits stream titles into artist and title.
+///
+/// In this order, the parser uses: the pattern of the station (if the user has set one),
+/// the artist tag of the stream (if the stream has one), or the common separators.
+#[derive(Clone)]
+pub struct MetadataParser {
+    // Has to be Send, the GstreamerBackend uses it
+    station_id: Arc<Mutex<Option<String>>>,
+    // Pattern of the station, and the generation of the patterns at which it got read
+    pattern: Arc<Mutex<(usize, Option<String>)>>,
+}
+
+impl MetadataParser {
+    pub fn new() -> Self {
+        Self {
+            station_id: Arc::new(Mutex::new(None)),
+            pattern: Arc::new(Mutex::new((0, None))),
+        }
+    }
+
+    pub fn set_station(&self, station: &Station) {
+        *self.station_id.lock().unwrap() = Some(station.id.clone());
+        self.load_pattern();
+    }
+
+    pub fn parse(&self, stream_title: &str, artist_tag: Option<&str>) -> SongMetadata {
+        let stream_title = stream_title.trim();
+        let mut metadata = SongMetadata {
+            stream_title: stream_title.to_string(),
+            title: stream_title.to_string(),
+            artist: None,
+        };
+
+        // Changes of the pattern apply to the station which is currently played as well
+        if self.pattern.lock().unwrap().0 != PATTERNS_GENERATION.load(Ordering::SeqCst) {
+            self.load_pattern();
+        }
+        let pattern = self.pattern.lock().unwrap().1.clone();
+        if let Some((artist, title)) = pattern.and_then(|pattern| Self::apply_pattern(&pattern, stream_title)) {
+            metadata.artist = artist;
+            metadata.title = title;
+            return metadata;
+        }
+
+        match artist_tag.map(|artist| artist.trim()).filter(|artist| !artist.is_empty()) {
+            Some(artist) => {
+                // Some stations send the artist tag, and the artist in the title anyway
+                for separator in SEPARATORS.iter() {
+                    let prefix = format!("{}{}", artist, separator);
+                    if stream_title.starts_with(&prefix) {
+                        metadata.title = stream_title[prefix.len()..].trim().to_string();
+                    }
+                }
+                metadata.artist = Some(artist.to_string());
+            }
+            None => {
+                let (artist, title) = Self::split(stream_title);
+                metadata.artist = artist;
+                metadata.title = title;
+            }
+        }
+        metadata
+    }
+
+    fn load_pattern(&self) {
+        // Read the generation first, a pattern which gets set in the meantime gets loaded by the next parse
+        let generation = PATTERNS_GENERATION.load(Ordering::SeqCst);
+        let pattern = self.station_id.lock().unwrap().as_ref().and_then(|id| Self::get_pattern(id));
+        *self.pattern.lock().unwrap() = (generation, pattern);
+    }
+
+    fn split(stream_title: &str) -> (Option<String>, String) {
+        for separator in SEPARATORS.iter() {
+            if let Some(index) = stream_title.find(separator) {
+                let artist = stream_title[..index].trim();
+                let title = stream_title[index + separator.len()..].trim();
+                if !artist.is_empty() && !title.is_empty() {
+                    return (Some(artist.to_string()), title.to_string());
+                }
+            }
+        }
+        (None, stream_title.to_string())
+    }
+
+    /// Returns artist and title, or None if the stream title doesn't match the pattern.
+    fn apply_pattern(pattern: &str, stream_title: &str) -> Option<(Option<String>, String)> {
+        let tokens = Self::tokenize(pattern);
+        let mut rest = stream_title;
+        let mut artist = None;
+        let mut title = None;
+
+        for (i, token) in tokens.iter().enumerate() {
+            let value = match token {
+                Token::Text(text) => {
+                    if !rest.starts_with(text.as_str()) {
+                        return None;
+                    }
+                    rest = &rest[text.len()..];
+                    continue;
+                }
+                // A placeholder ends where the following text starts. If that's the last one, it has to be at the end.
+                _ => match tokens.get(i + 1) {
+                    Some(Token::Text(text)) if i + 2 == tokens.len() => {
+                        if !rest.ends_with(text.as_str()) {
+                            return None;
+                        }
+                        let end = rest.len() - text.len();
+                        let value = &rest[..end];
+                        rest = &rest[end..];
+                        value
+                    }
+                    Some(Token::Text(text)) => {
+                        let end = rest.find(text.as_str())?;
+                        let value = &rest[..end];
+                        rest = &rest[end..];
+                        value
+                    }
+                    // Two placeholders next to each other can't be told apart
+                    Some(_) => return None,
+                    None => {
+                        let value = rest;
+                        rest = "";
+                        value
+                    }
+                },
+            };
+
+            let value = value.trim().to_string();
+            match token {
+                Token::Artist if !value.is_empty() => artist = Some(value),
+                Token::Title => title = Some(value),
+                _ => (),
+            }
+        }
+
+        match title {
+            Some(title) if rest.is_empty() && !title.is_empty() => Some((artist, title)),
+            _ => None,
+        }
+    }
+
+    fn tokenize(pattern: &str) -> Vec<Token> {
+        let placeholders = [PATTERN_ARTIST, PATTERN_TITLE, PATTERN_IGNORE];
+
+        let mut tokens = Vec::new();
+        let mut rest = pattern;
+        loop {
+            let next = placeholders
+                .iter()
+                .filter_map(|placeholder| rest.find(placeholder).map(|index| (index, *placeholder)))
+                .min_by_key(|(index, _)| *index);
+
+            match next {
+                Some((index, placeholder)) => {
+                    if index > 0 {
+                        tokens.push(Token::Text(rest[..index].to_string()));
+                    }
+                    tokens.push(match placeholder {
+                        p if p == PATTERN_ARTIST => Token::Artist,
+                        p if p == PATTERN_TITLE => Token::Title,
+                        _ => Token::Ignore,
+                    });
+                    rest = &rest[index + placeholder.len()..];
+                }
+                None => {
+                    if !rest.is_empty() {
+                        tokens.push(Token::Text(rest.to_string()));
+                    }
+                    return tokens;
+                }
+            }
+        }
+    }
+
+    /// The pattern the user has set for this station, e.g. "{title} by {artist}".
+    pub fn get_pattern(station_id: &str) -> Option<String> {
+        PATTERNS.with(|patterns| patterns.get(station_id).cloned())
+    }
+
+    pub fn set_pattern(station_id: &str, pattern: Option<String>) {
+        let result = PATTERNS.update(|patterns| {
+            match pattern.filter(|pattern| !pattern.trim().is_empty()) {
+                Some(pattern) => patterns.insert(station_id.to_string(), pattern),
+                None => patterns.remove(station_id),
+            };
+            true
+        });
+
+        if let Err(err) = result {
+            warn!("Could not save metadata patterns: {}", err);
+        }
+        PATTERNS_GENERATION.fetch_add(1, Ordering::SeqCst);
+    }
+}
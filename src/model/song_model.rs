@@ -2,54 +2,112 @@ use gio::prelude::*;
 use glib::prelude::*;
 
 use std::fs;
+use std::time::{Duration, SystemTime};
 
+use crate::json_file::JsonFile;
 use crate::model::ObjectWrapper;
+use crate::settings::{Key, SettingsManager};
 use crate::song::Song;
 
+lazy_static! {
+    static ref SONGS: JsonFile<Vec<Song>> = JsonFile::new("songs.json");
+}
+
 #[derive(Clone)]
 pub struct SongModel {
     pub model: gio::ListStore,
-    max_len: u32,
 
     current_song: Option<Song>,
     current_song_timestamp: Option<u32>,
 }
 
 impl SongModel {
-    pub fn new(max_len: u32) -> Self {
+    /// Contains the songs of the last session, if they're kept longer than that (`recording-max-age`).
+    pub fn new() -> Self {
         let model = gio::ListStore::new(ObjectWrapper::static_type());
 
         let current_song = None;
         let current_song_timestamp = None;
 
-        Self {
+        let mut song_model = Self {
             model,
-            max_len,
             current_song,
             current_song_timestamp,
+        };
+
+        let songs = SONGS.with(|songs| songs.clone());
+        // The user could have removed the files in the meantime
+        for song in songs.into_iter().filter(|song| song.path.exists()) {
+            let object = ObjectWrapper::new(song);
+            song_model.model.append(&object);
         }
+        song_model.apply_retention();
+        song_model
     }
 
     pub fn add_song(&mut self, song: Song) {
         // Check if song does not exist yet
         if !self.index(&song).is_some() {
-            // Ensure max length
-            if self.model.get_n_items() >= self.max_len {
-                let rm_song = self.get_song(self.model.get_n_items() - 1);
-                self.remove_song(&rm_song).unwrap();
-            }
-
             let object = ObjectWrapper::new(song.clone());
             self.model.insert(0, &object);
+            self.apply_retention();
+        }
+    }
+
+    /// Replaces the song with the same file, e.g. after it has been saved.
+    pub fn update_song(&mut self, song: Song) {
+        if let Some(index) = self.index(&song) {
+            let object = ObjectWrapper::new(song);
+            self.model.remove(index);
+            self.model.insert(index, &object);
+            self.save();
         }
     }
 
     pub fn remove_song(&mut self, song: &Song) -> std::io::Result<()> {
         fs::remove_file(&song.path)?;
         self.index(song).map(|index| self.model.remove(index));
+        self.save();
         Ok(())
     }
 
+    /// Removes the oldest songs, until the song list fits into the limits of the settings.
+    /// The newest song always stays, it may not even be completely saved yet.
+    pub fn apply_retention(&mut self) {
+        let max_songs = SettingsManager::get_uint(Key::RecordingMaxSongs);
+        let max_size = u64::from(SettingsManager::get_uint(Key::RecordingMaxSize)) * 1_000_000;
+        let max_age = Duration::from_secs(u64::from(SettingsManager::get_uint(Key::RecordingMaxAge)) * 24 * 60 * 60);
+
+        // 0 disables the size and age limits
+        let get_size = |song: &Song| fs::metadata(&song.path).map(|metadata| metadata.len()).unwrap_or(0);
+        let is_expired = |song: &Song| {
+            let modified = fs::metadata(&song.path).and_then(|metadata| metadata.modified());
+            let age = modified.ok().and_then(|modified| SystemTime::now().duration_since(modified).ok());
+            max_age != Duration::from_secs(0) && age.map_or(false, |age| age > max_age)
+        };
+
+        let mut size: u64 = self.get_songs().iter().map(get_size).sum();
+        while self.model.get_n_items() > 1 {
+            let song = self.get_song(self.model.get_n_items() - 1);
+            let exceeded = self.model.get_n_items() > max_songs || (max_size != 0 && size > max_size) || is_expired(&song);
+            if !exceeded {
+                break;
+            }
+
+            debug!("Remove song \"{}\" from the song list", song.title);
+            size -= get_size(&song).min(size);
+            if let Err(err) = fs::remove_file(&song.path) {
+                warn!("Could not remove song: {}", err);
+            }
+            self.model.remove(self.model.get_n_items() - 1);
+        }
+        self.save();
+    }
+
+    pub fn get_songs(&self) -> Vec<Song> {
+        (0..self.model.get_n_items()).map(|i| self.get_song(i)).collect()
+    }
+
     fn index(&self, song: &Song) -> Option<u32> {
         for i in 0..self.model.get_n_items() {
             let s = self.get_song(i);
@@ -76,6 +134,13 @@ impl SongModel {
         }
 
         self.model.remove_all();
+        self.save();
         Ok(())
     }
+
+    fn save(&self) {
+        if let Err(err) = SONGS.set(self.get_songs()) {
+            warn!("Could not save song list: {}", err);
+        }
+    }
 }
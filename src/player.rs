@@ -1,20 +1,34 @@
+use chrono::Local;
 use gio::prelude::*;
 use glib::{Receiver, Sender};
 use gtk::prelude::*;
-use rustio::{Client, Station};
+use rustio::Station;
 
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::fs;
 use std::path::PathBuf;
 use std::rc::Rc;
 use std::sync::{Arc, Mutex};
-use std::thread;
+use std::time::{Duration, Instant};
 
 use crate::app::Action;
-use crate::config;
-use crate::player::controller::{GtkController, MprisController};
+use crate::auto_save::AutoSave;
+use crate::filename_template::FilenameTemplate;
+use crate::history::History;
+use crate::metadata::SongMetadata;
+use crate::player::audio_output::AudioOutputManager;
+use crate::player::chromecast::CastReceiver;
+use crate::player::controller::{ChromecastController, GtkController, MprisController};
+use crate::player::effects::Effects;
 use crate::player::gstreamer_backend::GstreamerMessage;
+use crate::player::loudness::LoudnessNormalizer;
+use crate::player::recording_mode::RecordingMode;
+use crate::player::sleep_timer::{SleepTimer, SleepTimerMode};
+use crate::recording_index::RecordingIndex;
+use crate::settings::{Key, SettingsManager};
 use crate::song::Song;
+use crate::title_filter::TitleFilter;
+use crate::widgets::cast_picker::CastPicker;
 use crate::widgets::song_listbox::SongListBox;
 
 ////////////////////////////////////////////////////////////////////////////////////
@@ -38,23 +52,72 @@ use crate::widgets::song_listbox::SongListBox;
 //                                                                                //
 ////////////////////////////////////////////////////////////////////////////////////
 
+pub mod audio_output;
+pub mod chromecast;
 mod controller;
+pub mod effects;
 pub mod gstreamer_backend;
+pub mod headless_recorder;
+mod loudness;
 mod playback_state;
+mod preroll;
+mod reconnect;
+mod recorder_rules;
+pub mod recording_format;
+pub mod recording_mode;
+pub mod recording_storage;
+mod resolver;
+pub mod sleep_timer;
+mod timeshift;
+pub mod track_splitter;
 
 pub use controller::Controller;
 pub use gstreamer_backend::GstreamerBackend;
 pub use playback_state::PlaybackState;
+pub use reconnect::Reconnector;
+use recorder_rules::RecorderRules;
+use recording_storage::RecordingStorage;
 
 use crate::model::SongModel;
 
+// How often the free disk space gets checked
+const FREE_SPACE_INTERVAL_SECS: u32 = 10;
+
+// How long the volume has to stay the same, before it gets saved
+const VOLUME_SAVE_DELAY_MS: u32 = 500;
+
+// How long we wait for the last session to be written, before we quit anyway
+const SHUTDOWN_TIMEOUT_SECS: u64 = 5;
+
 pub struct Player {
     pub widget: gtk::Box,
     controller: Rc<Vec<Box<Controller>>>,
+    station: Rc<RefCell<Option<Station>>>,
+    recording_mode: Rc<Cell<RecordingMode>>,
+    // Less than `recording-min-free-space` left, recording is paused until there's enough space again
+    storage_full: Rc<Cell<bool>>,
 
     backend: Arc<Mutex<GstreamerBackend>>,
+    reconnector: Reconnector,
+    recorder_rules: RecorderRules,
+    // The song which could get auto-saved, as soon as its file is complete (RecordingStopped).
+    // The station is the one which got recorded, the user could have tuned in to another one by then.
+    auto_save_song: Rc<RefCell<Option<(Song, Station)>>>,
+    audio_outputs: AudioOutputManager,
+    loudness: LoudnessNormalizer,
+    sleep_timer: SleepTimer,
+    chromecast: ChromecastController,
+    cast_picker: CastPicker,
     song_model: Rc<RefCell<SongModel>>,
     song_listbox: SongListBox,
+
+    volume: Cell<f64>,
+    muted: Cell<bool>,
+    // Volume of a ringing alarm, it overrides the volume of the user without replacing it
+    alarm_volume: Cell<Option<f64>>,
+    // Incremented on every volume change, only the last one gets saved
+    volume_generation: Rc<Cell<u32>>,
+    sender: Sender<Action>,
 }
 
 impl Player {
@@ -62,13 +125,15 @@ impl Player {
         let builder = gtk::Builder::new_from_resource("/de/haeckerfelix/Shortwave/gtk/player.ui");
         let widget: gtk::Box = builder.get_object("player").unwrap();
 
-        let song_model = Rc::new(RefCell::new(SongModel::new(5)));
+        let song_model = Rc::new(RefCell::new(SongModel::new()));
+        RecordingStorage::remove_orphans(&song_model.borrow().get_songs());
         let song_listbox = SongListBox::new(sender.clone());
         song_listbox.bind_model(&song_model.borrow());
         widget.add(&song_listbox.widget);
 
         let (gst_sender, gst_receiver) = glib::MainContext::channel(glib::PRIORITY_DEFAULT);
-        let backend = Arc::new(Mutex::new(GstreamerBackend::new(gst_sender)));
+        let backend = Arc::new(Mutex::new(GstreamerBackend::new(gst_sender.clone())));
+        let reconnector = Reconnector::new(backend.clone(), gst_sender);
 
         let mut controller: Vec<Box<Controller>> = Vec::new();
 
@@ -82,54 +147,275 @@ impl Player {
         let mpris_controller = MprisController::new(sender.clone());
         controller.push(Box::new(mpris_controller));
 
+        // Chromecast Controller
+        let chromecast = ChromecastController::new(sender.clone());
+        controller.push(Box::new(chromecast.clone()));
+        let cast_picker = CastPicker::new(sender.clone());
+        controller_box.add(&cast_picker.widget);
+
+        // Local audio output devices
+        let audio_outputs = AudioOutputManager::new(backend.clone());
+        let picker = cast_picker.clone();
+        audio_outputs.connect_changed(move |outputs, selected| picker.set_audio_outputs(outputs, selected));
+
+        // Same loudness for every station
+        let loudness = LoudnessNormalizer::new(backend.clone());
+
         let controller: Rc<Vec<Box<Controller>>> = Rc::new(controller);
 
+        // Sleep timer
+        let sleep_timer = SleepTimer::new(backend.clone(), chromecast.clone(), sender.clone());
+        let c = controller.clone();
+        sleep_timer.connect_changed(move |state| {
+            for con in &*c {
+                con.set_sleep_timer(state);
+            }
+        });
+
         let player = Self {
             widget,
             controller,
+            station: Rc::new(RefCell::new(None)),
+            recording_mode: Rc::new(Cell::new(RecordingMode::load(None))),
+            storage_full: Rc::new(Cell::new(false)),
             backend,
+            reconnector,
+            recorder_rules: RecorderRules::new(),
+            auto_save_song: Rc::new(RefCell::new(None)),
+            audio_outputs,
+            loudness,
+            sleep_timer,
+            chromecast,
+            cast_picker,
             song_model,
             song_listbox,
+            volume: Cell::new(1.0),
+            muted: Cell::new(false),
+            alarm_volume: Cell::new(None),
+            volume_generation: Rc::new(Cell::new(0)),
+            sender,
         };
 
+        // Restore last used volume and effects
+        player.set_volume(SettingsManager::get_double(Key::Volume));
+        player.backend.lock().unwrap().set_effects(&Effects::load());
+
         player.setup_signals(gst_receiver);
         player
     }
 
     pub fn set_station(&self, station: Station) {
         self.set_playback(PlaybackState::Stopped);
+        *self.station.borrow_mut() = Some(station.clone());
+        self.update_recording_mode();
+        self.reconnector.set_station(station.clone());
+        self.loudness.set_station(&station);
+        self.backend.lock().unwrap().set_station(station.clone());
 
         for con in &*self.controller {
             con.set_station(station.clone());
         }
 
-        let backend = self.backend.clone();
-        thread::spawn(move || {
-            let mut client = Client::new("http://www.radio-browser.info");
-            let station_url = client.get_playable_station_url(station).unwrap();
-            debug!("new source uri to record: {}", station_url);
-            backend.lock().unwrap().new_source_uri(&station_url);
-        });
+        self.reconnector.connect();
     }
 
     pub fn set_playback(&self, playback: PlaybackState) {
+        // While casting, the receiver plays the stream and the local pipeline stays stopped
+        if self.chromecast.is_connected() {
+            for con in &*self.controller {
+                con.set_playback_state(&playback);
+            }
+            if playback == PlaybackState::Stopped {
+                self.sleep_timer.playback_stopped();
+            }
+            return;
+        }
+
         match playback {
             PlaybackState::Playing => {
-                let _ = self.backend.lock().unwrap().set_state(gstreamer::State::Playing);
+                // The playback starts on its own, as soon as the stream of the new station is known
+                if !self.reconnector.is_resolving() {
+                    let _ = self.backend.lock().unwrap().set_state(gstreamer::State::Playing);
+                }
+            }
+            PlaybackState::Paused => {
+                let _ = self.backend.lock().unwrap().set_state(gstreamer::State::Paused);
             }
             PlaybackState::Stopped => {
+                // The user knows best, cancel any pending reconnect attempt
+                self.reconnector.reset();
+                self.loudness.remember();
+                // The session gets finalized on its own, the pipeline can stop right away
+                self.backend.lock().unwrap().finish_recording();
                 let _ = self.backend.lock().unwrap().set_state(gstreamer::State::Null);
+                self.sleep_timer.playback_stopped();
             }
             _ => (),
         }
     }
 
+    /// Whether the stream of the current station is playing, or at least has been playing.
+    pub fn is_stream_connected(&self) -> bool {
+        if self.chromecast.is_connected() {
+            self.chromecast.is_playing()
+        } else {
+            self.reconnector.is_connected()
+        }
+    }
+
+    pub fn set_sleep_timer(&self, mode: SleepTimerMode) {
+        self.sleep_timer.start(mode);
+    }
+
+    pub fn extend_sleep_timer(&self, duration: Duration) {
+        self.sleep_timer.extend(duration);
+    }
+
+    pub fn cancel_sleep_timer(&self) {
+        self.sleep_timer.cancel();
+    }
+
+    pub fn set_effects(&self, effects: Effects) {
+        effects.save();
+        self.backend.lock().unwrap().set_effects(&effects);
+    }
+
+    pub fn set_audio_output(&self, id: &str) {
+        self.audio_outputs.set_output(id);
+    }
+
+    /// Hands the playback over to a cast receiver, or back to the local computer (`None`).
+    pub fn set_cast_receiver(&self, receiver: Option<CastReceiver>) {
+        let playing = if self.chromecast.is_connected() {
+            self.chromecast.is_playing()
+        } else {
+            self.backend.lock().unwrap().is_playing()
+        };
+
+        // Stop the current output, before we switch to the new one
+        self.set_playback(PlaybackState::Stopped);
+        self.chromecast.disconnect();
+
+        if let Some(receiver) = receiver.clone() {
+            self.chromecast.connect(receiver);
+            if let Some(url) = self.backend.lock().unwrap().get_source_uri() {
+                self.chromecast.set_stream_url(&url);
+            }
+        }
+        self.cast_picker.set_active_receiver(receiver.as_ref());
+
+        // Continue the playback on the new output
+        let state = if playing { PlaybackState::Playing } else { PlaybackState::Stopped };
+        self.set_playback(state);
+    }
+
+    pub fn set_volume(&self, volume: f64) {
+        let volume = volume.max(0.0).min(1.0);
+
+        // Changing the volume unmutes the playback, and the user takes over from a ringing alarm
+        self.volume.set(volume);
+        self.muted.set(false);
+        self.alarm_volume.set(None);
+        self.update_volume();
+
+        // The slider changes the volume many times per second, so wait until it rests
+        let generation = self.volume_generation.get().wrapping_add(1);
+        self.volume_generation.set(generation);
+        let volume_generation = self.volume_generation.clone();
+        gtk::timeout_add(VOLUME_SAVE_DELAY_MS, move || {
+            if volume_generation.get() == generation {
+                SettingsManager::set_double(Key::Volume, volume);
+            }
+            glib::Continue(false)
+        });
+    }
+
+    pub fn toggle_mute(&self) {
+        self.muted.set(!self.muted.get());
+        self.alarm_volume.set(None);
+        self.update_volume();
+    }
+
+    /// Plays with `volume` while an alarm is ringing. `None` restores the volume of the user.
+    pub fn set_alarm_volume(&self, volume: Option<f64>) {
+        self.alarm_volume.set(volume);
+        self.update_volume();
+    }
+
+    fn update_volume(&self) {
+        // The controllers keep showing the volume of the user, which gets restored after the alarm
+        if let Some(volume) = self.alarm_volume.get() {
+            let mut backend = self.backend.lock().unwrap();
+            backend.set_volume(volume);
+            backend.set_mute(false);
+            self.chromecast.set_volume(volume);
+            return;
+        }
+
+        let mut backend = self.backend.lock().unwrap();
+        backend.set_volume(self.volume.get());
+        backend.set_mute(self.muted.get());
+
+        let volume = if self.muted.get() { 0.0 } else { self.volume.get() };
+        for con in &*self.controller {
+            con.set_volume(volume);
+        }
+    }
+
+    /// Applies the recording mode of the current station, e.g. after the user has changed it.
+    pub fn update_recording_mode(&self) {
+        let mode = match self.storage_full.get() {
+            true => RecordingMode::Off,
+            false => RecordingMode::load(self.station.borrow().as_ref()),
+        };
+        for con in &*self.controller {
+            con.set_recording_mode(mode);
+        }
+        if mode == self.recording_mode.get() {
+            return;
+        }
+        debug!("Recording mode: {:?}", mode);
+
+        // The current recording doesn't fit the new mode
+        if self.recording_mode.get() == RecordingMode::Continuous {
+            self.backend.lock().unwrap().finish_recording();
+        } else if !self.backend.lock().unwrap().is_saving_recording() {
+            self.backend.lock().unwrap().stop_recording(false);
+        }
+        self.recording_mode.set(mode);
+
+        // Songs get recorded from the next title change on, a session can start right away
+        let running = {
+            let backend = self.backend.lock().unwrap();
+            backend.is_playing() || backend.is_paused()
+        };
+        if mode == RecordingMode::Continuous && running {
+            Self::start_session(&self.backend, &self.station, &self.sender);
+        }
+    }
+
+    pub fn set_timeshift_delay(&self, delay: Duration) {
+        self.backend.lock().unwrap().set_timeshift_delay(delay);
+    }
+
     pub fn shutdown(&self) {
         self.set_playback(PlaybackState::Stopped);
 
-        // Clear song model and remove all saved songs
-        self.song_model.borrow_mut().clear().unwrap();
-        fs::remove_dir_all(Self::get_song_path("".to_string())).expect("Could not remove recording folder");
+        // The main loop isn't running anymore, but the last session still has to be written and added to the songs
+        let context = glib::MainContext::default();
+        let deadline = Instant::now() + Duration::from_secs(SHUTDOWN_TIMEOUT_SECS);
+        while self.backend.lock().unwrap().is_finishing_recording() && Instant::now() < deadline {
+            context.iteration(true);
+        }
+        SettingsManager::set_double(Key::Volume, self.volume.get());
+
+        // Songs only outlive the session, if they're kept for a number of days
+        if SettingsManager::get_uint(Key::RecordingMaxAge) == 0 {
+            if let Err(err) = self.song_model.borrow_mut().clear() {
+                warn!("Could not remove songs: {}", err);
+            }
+        }
+        RecordingStorage::remove_orphans(&self.song_model.borrow().get_songs());
     }
 
     fn setup_signals(&self, receiver: Receiver<GstreamerMessage>) {
@@ -137,10 +423,69 @@ impl Player {
         let controller = self.controller.clone();
         let song_model = self.song_model.clone();
         let backend = self.backend.clone();
-        receiver.attach(None, move |message| Self::process_gst_message(message, controller.clone(), song_model.clone(), backend.clone()));
+        let reconnector = self.reconnector.clone();
+        let recorder_rules = self.recorder_rules.clone();
+        let auto_save_song = self.auto_save_song.clone();
+        let station = self.station.clone();
+        let recording_mode = self.recording_mode.clone();
+        let chromecast = self.chromecast.clone();
+        let sleep_timer = self.sleep_timer.clone();
+        let sender = self.sender.clone();
+        receiver.attach(None, move |message| {
+            Self::process_gst_message(
+                message,
+                controller.clone(),
+                song_model.clone(),
+                backend.clone(),
+                reconnector.clone(),
+                recorder_rules.clone(),
+                auto_save_song.clone(),
+                station.clone(),
+                recording_mode.clone(),
+                chromecast.clone(),
+                sleep_timer.clone(),
+                sender.clone(),
+            )
+        });
+
+        // Songs don't take that long, so it's most likely a talk show or something similar
+        let backend = self.backend.clone();
+        let recorder_rules = self.recorder_rules.clone();
+        let recording_mode = self.recording_mode.clone();
+        gtk::timeout_add_seconds(1, move || {
+            let duration = backend.lock().unwrap().get_recording_duration();
+            if recording_mode.get() == RecordingMode::PerSong && duration.map_or(false, |duration| recorder_rules.exceeds_maximum(duration)) {
+                debug!("Discard recording: longer than the maximum duration");
+                // The next recording starts with the next song
+                backend.lock().unwrap().stop_recording(false);
+            }
+            glib::Continue(true)
+        });
+
+        // Don't fill up the disk, recording continues as soon as there's enough space again
+        let storage_full = self.storage_full.clone();
+        let station = self.station.clone();
+        let sender = self.sender.clone();
+        gtk::timeout_add_seconds(FREE_SPACE_INTERVAL_SECS, move || {
+            let full = !RecordingStorage::has_free_space();
+            if full != storage_full.get() {
+                storage_full.set(full);
+                sender.send(Action::PlaybackUpdateRecordingMode).unwrap();
+
+                if RecordingMode::load(station.borrow().as_ref()) != RecordingMode::Off {
+                    let message = match full {
+                        true => "Recording paused, there's not enough disk space left.",
+                        false => "Enough disk space again, recording continues.",
+                    };
+                    sender.send(Action::ViewShowNotification(message.to_string())).unwrap();
+                }
+            }
+            glib::Continue(true)
+        });
 
         // Show song listbox if a song gets added
         let listbox = self.song_listbox.widget.clone();
+        listbox.set_visible(self.song_model.borrow().model.get_n_items() > 0);
         self.song_model.borrow().model.connect_items_changed(move |_, _, _, added| {
             if added == 1 {
                 listbox.set_visible(true);
@@ -148,67 +493,278 @@ impl Player {
         });
     }
 
-    fn process_gst_message(message: GstreamerMessage, controller: Rc<Vec<Box<Controller>>>, song_model: Rc<RefCell<SongModel>>, backend: Arc<Mutex<GstreamerBackend>>) -> glib::Continue {
+    fn process_gst_message(
+        message: GstreamerMessage,
+        controller: Rc<Vec<Box<Controller>>>,
+        song_model: Rc<RefCell<SongModel>>,
+        backend: Arc<Mutex<GstreamerBackend>>,
+        reconnector: Reconnector,
+        recorder_rules: RecorderRules,
+        auto_save_song: Rc<RefCell<Option<(Song, Station)>>>,
+        station: Rc<RefCell<Option<Station>>>,
+        recording_mode: Rc<Cell<RecordingMode>>,
+        chromecast: ChromecastController,
+        sleep_timer: SleepTimer,
+        sender: Sender<Action>,
+    ) -> glib::Continue {
         match message {
-            GstreamerMessage::SongTitleChanged(title) => {
-                debug!("Song title has changed: \"{}\"", title);
+            GstreamerMessage::SongTitleChanged(song) => {
+                debug!("Song title has changed: \"{}\" ({:?})", song.stream_title, song.artist);
 
-                for con in &*controller {
-                    con.set_song_title(&title);
+                // Ads and jingles end the current song, but they aren't songs themselves
+                let filtered = TitleFilter::is_filtered(&song, station.borrow().as_ref());
+
+                if !filtered {
+                    if let Some(station) = station.borrow().as_ref() {
+                        History::add_song(station, &song);
+                    }
                 }
 
-                // Song have changed -> stop recording
-                if backend.lock().unwrap().is_recording() {
-                    let song = backend.lock().unwrap().stop_recording(true).unwrap();
-                    song_model.borrow_mut().add_song(song);
-                } else {
-                    // Get current/new song title
-                    let title = backend.lock().unwrap().get_current_song_title();
+                if recording_mode.get() != RecordingMode::PerSong {
+                    // The following songs would get recorded from their beginning, if the mode changes
+                    recorder_rules.song_changed();
+                    if recording_mode.get() == RecordingMode::Continuous && !filtered && backend.lock().unwrap().is_recording() {
+                        backend.lock().unwrap().add_recording_chapter(&song);
+                        Self::set_recorded(&station, &song.title, &song.artist);
+                    }
+                    return glib::Continue(true);
+                }
 
-                    // Nothing needs to be stopped, so we can start directly recording.
-                    backend.lock().unwrap().start_recording(Self::get_song_path(title));
+                // Song have changed -> stop recording
+                let duration = backend.lock().unwrap().get_recording_duration();
+                match duration {
+                    // The previous song is still being saved, RecordingStopped starts the recording of this one
+                    _ if backend.lock().unwrap().is_saving_recording() => (),
+                    Some(duration) if recorder_rules.keep(duration) => {
+                        let song = backend.lock().unwrap().stop_recording(true).unwrap();
+                        Self::set_recorded(&station, &song.title, &song.artist);
+                        // The rules need the complete file (e.g. for the bitrate), so they get checked later
+                        *auto_save_song.borrow_mut() = station.borrow().clone().map(|station| (song.clone(), station));
+                        song_model.borrow_mut().add_song(song);
+                    }
+                    _ => {
+                        // The recording isn't worth keeping, or there's none (e.g. after tuning in)
+                        backend.lock().unwrap().stop_recording(false);
+
+                        // Nothing needs to be saved, so we can start directly recording.
+                        if filtered || Self::is_already_saved(&song) {
+                            recorder_rules.song_changed();
+                        } else {
+                            Self::start_recording(&backend, &song, &recorder_rules, &sender);
+                        }
+                    }
+                }
+            }
+            GstreamerMessage::PlayedSongChanged(song) => {
+                // The recording follows the live stream, but the user hears the song only now
+                sleep_timer.song_changed();
+                if !TitleFilter::is_filtered(&song, station.borrow().as_ref()) {
+                    for con in &*controller {
+                        con.set_song(&song);
+                    }
                 }
             }
             GstreamerMessage::RecordingStopped => {
                 // Recording successfully stopped.
                 debug!("Recording stopped.");
 
-                // Get current/new song title
-                let title = backend.lock().unwrap().get_current_song_title();
+                // Sessions don't block the dataflow, they send RecordingFinished instead
+                if !backend.lock().unwrap().is_saving_recording() {
+                    return glib::Continue(true);
+                }
+
+                // Get current/new song
+                let song = backend.lock().unwrap().get_current_song();
 
                 // Start recording new song
-                if title != "" {
-                    backend.lock().unwrap().start_recording(Self::get_song_path(title));
+                let filtered = TitleFilter::is_filtered(&song, station.borrow().as_ref());
+                if recording_mode.get() == RecordingMode::PerSong && !song.is_empty() && !filtered && !Self::is_already_saved(&song) {
+                    Self::start_recording(&backend, &song, &recorder_rules, &sender);
+                } else {
+                    backend.lock().unwrap().release_recording();
+                }
+
+                // The file of the previous song is complete now
+                let song = auto_save_song.borrow_mut().take();
+                if let Some((mut song, song_station)) = song {
+                    if AutoSave::applies(&song, &song_station) {
+                        AutoSave::save(&mut song);
+                        if song.auto_saved {
+                            song_model.borrow_mut().update_song(song);
+                        }
+                    }
                 }
             }
-            GstreamerMessage::PlaybackStateChanged(state) => {
+            GstreamerMessage::RecordingFinished(mut session, session_station) => {
+                debug!("Recording finished: {:?}", session.path);
+                backend.lock().unwrap().release_finished_recording(&session.path);
+
+                // The station could have changed already, the rules of the recorded one apply
+                if session_station.map_or(false, |session_station| AutoSave::applies(&session, &session_station)) {
+                    AutoSave::save(&mut session);
+                }
+                song_model.borrow_mut().add_song(session);
+            }
+            GstreamerMessage::TimeshiftPositionChanged(delay, buffered) => {
                 for con in &*controller {
-                    con.set_playback_state(&state);
+                    con.set_timeshift_position(delay, buffered);
+                }
+            }
+            GstreamerMessage::StreamResolved(url) => {
+                debug!("new source uri to record: {}", url);
+                reconnector.set_resolved();
+
+                if chromecast.is_connected() {
+                    // Keep the local pipeline stopped, the receiver fetches the stream itself
+                    backend.lock().unwrap().set_source_uri(&url);
+                    chromecast.set_stream_url(&url);
+                    for con in &*controller {
+                        con.set_playback_state(&PlaybackState::Playing);
+                    }
+                } else {
+                    backend.lock().unwrap().new_source_uri(&url);
+                }
+            }
+            GstreamerMessage::StreamConnected => {
+                reconnector.set_connected();
+
+                // A reconnect continues the current session
+                if recording_mode.get() == RecordingMode::Continuous && !backend.lock().unwrap().is_recording() {
+                    Self::start_session(&backend, &station, &sender);
+                }
+
+                if reconnector.is_reconnecting() {
+                    debug!("Reconnected successfully.");
+                    reconnector.reset();
+
+                    let state = if backend.lock().unwrap().is_paused() { PlaybackState::Paused } else { PlaybackState::Playing };
+                    for con in &*controller {
+                        con.set_playback_state(&state);
+                    }
+                }
+            }
+            GstreamerMessage::StreamError(msg) => {
+                warn!("Stream error: {}", msg);
+                reconnector.set_resolved();
+
+                if reconnector.is_connected() {
+                    // Keep the recording, so it can continue after the reconnect
+                    let attempt = reconnector.schedule();
+                    for con in &*controller {
+                        con.set_playback_state(&PlaybackState::Reconnecting(attempt));
+                    }
+                } else if reconnector.is_switching() {
+                    // Error of the previous candidate, we're already trying the next one
+                } else if reconnector.has_candidates() {
+                    // The stream never worked, but the station has other streams we can try
+                    reconnector.try_next_candidate();
+                } else {
+                    // The station never worked, so there's no point in trying it again
+                    let message = GstreamerMessage::PlaybackStateChanged(PlaybackState::Failure(msg));
+                    return Self::process_gst_message(
+                        message,
+                        controller,
+                        song_model,
+                        backend,
+                        reconnector,
+                        recorder_rules,
+                        auto_save_song,
+                        station,
+                        recording_mode,
+                        chromecast,
+                        sleep_timer,
+                        sender,
+                    );
+                }
+            }
+            GstreamerMessage::PlaybackStateChanged(state) => {
+                // The local pipeline is not relevant while casting
+                if !chromecast.is_connected() {
+                    for con in &*controller {
+                        con.set_playback_state(&state);
+                    }
                 }
 
                 if matches!(state, PlaybackState::Failure(_)) || matches!(state, PlaybackState::Stopped) {
-                    // Discard current recording because the song has not yet been completely recorded.
-                    backend.lock().unwrap().stop_recording(false);
+                    if recording_mode.get() == RecordingMode::Continuous {
+                        // Keep everything which has been recorded so far
+                        backend.lock().unwrap().finish_recording();
+                    } else {
+                        // Discard current recording because the song has not yet been completely recorded.
+                        backend.lock().unwrap().stop_recording(false);
+                    }
+                    recorder_rules.reset();
                 }
             }
         }
         glib::Continue(true)
     }
 
+    fn start_recording(backend: &Arc<Mutex<GstreamerBackend>>, song: &SongMetadata, recorder_rules: &RecorderRules, sender: &Sender<Action>) {
+        recorder_rules.recording_started();
+        if let Err(err) = backend.lock().unwrap().start_recording(song.clone(), Self::get_song_path(song.get_display_title())) {
+            let message = format!("Could not record song: {}", err);
+            sender.send(Action::ViewShowNotification(message)).unwrap();
+        }
+    }
+
+    // The user has a copy of the song already, and doesn't want another one
+    fn is_already_saved(song: &SongMetadata) -> bool {
+        if SettingsManager::get_boolean(Key::RecordingSkipSaved) && RecordingIndex::find(&song.title, &song.artist).is_some() {
+            debug!("Don't record \"{}\", it has already been saved", song.get_display_title());
+            return true;
+        }
+        false
+    }
+
+    // One file for the whole session, every song gets a chapter
+    fn start_session(backend: &Arc<Mutex<GstreamerBackend>>, station: &Rc<RefCell<Option<Station>>>, sender: &Sender<Action>) {
+        let name = match station.borrow().as_ref() {
+            Some(station) => format!("{} {}", station.name, Local::now().format("%Y-%m-%d %H-%M")),
+            None => return,
+        };
+        let session = SongMetadata {
+            stream_title: name.clone(),
+            title: name,
+            artist: None,
+        };
+
+        let mut backend = backend.lock().unwrap();
+        if let Err(err) = backend.start_recording(session.clone(), Self::get_song_path(session.title.clone())) {
+            let message = format!("Could not record session: {}", err);
+            sender.send(Action::ViewShowNotification(message)).unwrap();
+            return;
+        }
+
+        // We're already in the middle of a song
+        let song = backend.get_current_song();
+        if !song.is_empty() && !TitleFilter::is_filtered(&song, station.borrow().as_ref()) {
+            backend.add_recording_chapter(&song);
+            Self::set_recorded(station, &song.title, &song.artist);
+        }
+    }
+
+    fn set_recorded(station: &Rc<RefCell<Option<Station>>>, title: &str, artist: &Option<String>) {
+        if let Some(station) = station.borrow().as_ref() {
+            History::set_recorded(&station.id, title, artist);
+        }
+    }
+
+    // The extension depends on the recording format, RecorderBin adds it
     fn get_song_path(title: String) -> PathBuf {
-        let title = Song::simplify_title(title);
+        let title = FilenameTemplate::sanitize(&title);
 
-        let mut path = glib::get_user_cache_dir().unwrap();
-        path.push(config::NAME);
-        path.push("recording");
+        let mut path = RecordingStorage::get_directory();
 
-        // Make sure that the path exists
-        fs::create_dir_all(path.clone()).expect("Could not create path for recording");
+        // Make sure that the path exists, otherwise the recording fails (and the user gets notified)
+        if let Err(err) = fs::create_dir_all(path.clone()) {
+            warn!("Could not create path for recording: {}", err);
+        }
 
         if title != "" {
             path.push(title);
-            path.set_extension("ogg");
         }
-        path
+        // The same song can get recorded again, while the previous recording is still in the song list
+        Song::get_unique_path_without_extension(path)
     }
 }
@@ -0,0 +1,147 @@
+use gstreamer::prelude::*;
+use gstreamer::{Device, DeviceMonitor, ElementFactory};
+
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+
+use crate::player::GstreamerBackend;
+use crate::settings::{Key, SettingsManager};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AudioOutput {
+    pub id: String,
+    pub name: String,
+}
+
+/// Keeps track of the available audio output devices, and makes sure that the backend uses the selected one.
+/// If the selected device is not available (anymore), an autoaudiosink gets used until it comes back.
+#[derive(Clone)]
+pub struct AudioOutputManager {
+    monitor: DeviceMonitor,
+    backend: Arc<Mutex<GstreamerBackend>>,
+
+    devices: Rc<RefCell<Vec<Device>>>,
+    // Device which got chosen by the user, an empty string means automatic
+    selected: Rc<RefCell<String>>,
+    // Device which is currently in use
+    active: Rc<RefCell<String>>,
+
+    changed_callback: Rc<RefCell<Option<Box<Fn(&[AudioOutput], &str)>>>>,
+}
+
+impl AudioOutputManager {
+    pub fn new(backend: Arc<Mutex<GstreamerBackend>>) -> Self {
+        let monitor = DeviceMonitor::new();
+        monitor.add_filter("Audio/Sink", None::<&gstreamer::Caps>);
+        if monitor.start().is_err() {
+            warn!("Could not start audio device monitor");
+        }
+
+        let manager = Self {
+            monitor,
+            backend,
+            devices: Rc::new(RefCell::new(Vec::new())),
+            selected: Rc::new(RefCell::new(SettingsManager::get_string(Key::AudioOutput))),
+            active: Rc::new(RefCell::new(String::new())),
+            changed_callback: Rc::new(RefCell::new(None)),
+        };
+
+        manager.refresh();
+        manager.setup_signals();
+        manager
+    }
+
+    /// Gets called every time the list of devices, or the selected device changes.
+    pub fn connect_changed<F: Fn(&[AudioOutput], &str) + 'static>(&self, callback: F) {
+        callback(&self.get_outputs(), &self.selected.borrow());
+        *self.changed_callback.borrow_mut() = Some(Box::new(callback));
+    }
+
+    /// Selects the output device by its id, an empty id selects the device automatically.
+    pub fn set_output(&self, id: &str) {
+        if *self.selected.borrow() == id {
+            return;
+        }
+
+        debug!("Select audio output \"{}\"", id);
+        *self.selected.borrow_mut() = id.to_string();
+        SettingsManager::set_string(Key::AudioOutput, id);
+        self.refresh();
+    }
+
+    pub fn get_outputs(&self) -> Vec<AudioOutput> {
+        self.devices
+            .borrow()
+            .iter()
+            .map(|device| AudioOutput {
+                id: Self::get_device_id(device),
+                name: device.get_display_name().map(|name| name.to_string()).unwrap_or_default(),
+            })
+            .collect()
+    }
+
+    fn setup_signals(&self) {
+        // Devices can get plugged in / removed at any time
+        let bus = self.monitor.get_bus();
+        let manager = self.clone();
+        gtk::timeout_add(500, move || {
+            let mut changed = false;
+            while let Some(message) = bus.pop() {
+                match message.view() {
+                    gstreamer::MessageView::DeviceAdded(_) | gstreamer::MessageView::DeviceRemoved(_) => changed = true,
+                    _ => (),
+                }
+            }
+
+            if changed {
+                manager.refresh();
+            }
+            glib::Continue(true)
+        });
+    }
+
+    fn refresh(&self) {
+        *self.devices.borrow_mut() = self.monitor.get_devices();
+
+        // Use the selected device if it's available, otherwise fall back to autoaudiosink
+        let selected = self.selected.borrow().clone();
+        let device = self.devices.borrow().iter().find(|device| Self::get_device_id(device) == selected).cloned();
+        let target = device.as_ref().map(|_| selected.clone()).unwrap_or_default();
+
+        if *self.active.borrow() != target {
+            let sink = match device {
+                Some(device) => device.create_element("audiosink"),
+                None => {
+                    if !selected.is_empty() {
+                        warn!("Audio output \"{}\" is not available, falling back to automatic selection", selected);
+                    }
+                    ElementFactory::make("autoaudiosink", "audiosink")
+                }
+            };
+
+            match sink {
+                Some(sink) => {
+                    debug!("Use audio output \"{}\"", target);
+                    self.backend.lock().unwrap().set_audio_sink(sink);
+                    *self.active.borrow_mut() = target;
+                }
+                None => warn!("Could not create audio sink for \"{}\"", target),
+            }
+        }
+
+        if let Some(callback) = self.changed_callback.borrow().as_ref() {
+            callback(&self.get_outputs(), &selected);
+        }
+    }
+
+    // The display name is not unique, so we prefer the internal name of the sink (e.g. PulseAudio)
+    fn get_device_id(device: &Device) -> String {
+        device
+            .get_property("internal-name")
+            .ok()
+            .and_then(|value| value.get::<String>())
+            .or_else(|| device.get_display_name().map(|name| name.to_string()))
+            .unwrap_or_default()
+    }
+}
@@ -0,0 +1,90 @@
+use glib::Sender;
+use mdns::RecordKind;
+
+use std::env;
+use std::net::IpAddr;
+use std::thread;
+use std::time::Duration;
+
+// mDNS service, which gets announced by every cast device
+const SERVICE_NAME: &str = "_googlecast._tcp.local";
+const DEFAULT_PORT: u16 = 8009;
+pub const DISCOVERY_TIMEOUT_SECS: u64 = 10;
+
+// Additional receiver, which doesn't get announced via mDNS ("host:port").
+// Useful for testing against a local fake receiver (build-aux/fake-cast-receiver.py).
+const RECEIVER_ENV_VAR: &str = "SHORTWAVE_CAST_RECEIVER";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CastReceiver {
+    pub name: String,
+    pub host: String,
+    pub port: u16,
+}
+
+/// Searches for cast receivers in the background. Every found receiver gets sent to `sender`.
+pub fn discover(sender: Sender<CastReceiver>) {
+    if let Some(receiver) = get_env_receiver() {
+        sender.send(receiver).unwrap();
+    }
+
+    thread::spawn(move || {
+        let discovery = match mdns::discover::all(SERVICE_NAME) {
+            Ok(discovery) => discovery.timeout(Duration::from_secs(DISCOVERY_TIMEOUT_SECS)),
+            Err(err) => {
+                warn!("Could not start cast receiver discovery: {}", err);
+                return;
+            }
+        };
+
+        for response in discovery {
+            let response = match response {
+                Ok(response) => response,
+                Err(err) => {
+                    warn!("Could not discover cast receivers: {}", err);
+                    break;
+                }
+            };
+
+            let mut address: Option<IpAddr> = None;
+            let mut port = DEFAULT_PORT;
+            let mut name: Option<String> = None;
+
+            for record in response.records() {
+                match record.kind {
+                    RecordKind::A(addr) => address = Some(addr.into()),
+                    RecordKind::AAAA(addr) => address = address.or_else(|| Some(addr.into())),
+                    RecordKind::SRV { port: p, .. } => port = p,
+                    // The "fn" entry contains the user visible name ("Living Room")
+                    RecordKind::TXT(ref entries) => {
+                        name = entries.iter().find(|entry| entry.starts_with("fn=")).map(|entry| entry["fn=".len()..].to_string());
+                    }
+                    _ => (),
+                }
+            }
+
+            if let Some(address) = address {
+                let receiver = CastReceiver {
+                    name: name.unwrap_or_else(|| address.to_string()),
+                    host: address.to_string(),
+                    port,
+                };
+                debug!("Found cast receiver: {:?}", receiver);
+
+                // Nobody is waiting for the results anymore
+                if sender.send(receiver).is_err() {
+                    break;
+                }
+            }
+        }
+    });
+}
+
+fn get_env_receiver() -> Option<CastReceiver> {
+    let value = env::var(RECEIVER_ENV_VAR).ok()?;
+    let mut split = value.rsplitn(2, ':');
+    let port = split.next()?.parse::<u16>().ok()?;
+    let host = split.next()?.to_string();
+
+    Some(CastReceiver { name: value.clone(), host, port })
+}
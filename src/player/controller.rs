@@ -1,14 +1,27 @@
+mod chromecast_controller;
 mod gtk_controller;
 mod mpris_controller;
 
+pub use chromecast_controller::ChromecastController;
 pub use gtk_controller::GtkController;
 pub use mpris_controller::MprisController;
 
+use crate::metadata::SongMetadata;
+use crate::player::recording_mode::RecordingMode;
+use crate::player::sleep_timer::SleepTimerState;
 use crate::player::PlaybackState;
 use rustio::Station;
 
+use std::time::Duration;
+
 pub trait Controller {
     fn set_station(&self, station: Station);
     fn set_playback_state(&self, playback_state: &PlaybackState);
-    fn set_song_title(&self, title: &str);
+    fn set_song(&self, song: &SongMetadata);
+    /// Volume between 0.0 and 1.0 (0.0 if muted)
+    fn set_volume(&self, volume: f64);
+    /// `delay`: how far the playback is behind the live stream, `buffered`: how much can be rewound.
+    fn set_timeshift_position(&self, delay: Duration, buffered: Duration);
+    fn set_sleep_timer(&self, state: &SleepTimerState);
+    fn set_recording_mode(&self, mode: RecordingMode);
 }
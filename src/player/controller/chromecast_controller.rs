@@ -0,0 +1,367 @@
+use glib::Sender;
+use rust_cast::channels::media::{GenericMediaMetadata, Image, Media, Metadata, StreamType};
+use rust_cast::channels::receiver::{Application, CastDeviceApp};
+use rust_cast::CastDevice;
+use rustio::Station;
+
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use crate::app::Action;
+use crate::metadata::SongMetadata;
+use crate::player::chromecast::CastReceiver;
+use crate::player::recording_mode::RecordingMode;
+use crate::player::sleep_timer::SleepTimerState;
+use crate::player::Controller;
+use crate::player::PlaybackState;
+
+// Id of the platform receiver, which is always running on a cast device
+const DEFAULT_DESTINATION_ID: &str = "receiver-0";
+// The cast device closes the connection if it doesn't hear from us for a while
+const HEARTBEAT_INTERVAL_SECS: u64 = 5;
+
+//////////////////////////////////////////////////////////////////////////////////////////////////////////
+//                                                                                                      //
+//  # ChromecastController                                                                              //
+//                                                                                                      //
+//    ----------------------                      ---------------                  ---------------      //
+//   | ChromecastController | -- CastCommand --> | worker thread | <-- CASTv2 --> | cast receiver |     //
+//    ----------------------                      ---------------                  ---------------      //
+//              ^                                        |                                              //
+//              \--------------- CastEvent --------------/                                              //
+//                                                                                                      //
+//  The connection to the receiver is blocking, so it lives in its own thread. The receiver fetches     //
+//  the stream itself, we only tell it which url to play (DefaultMediaReceiver app).                    //
+//                                                                                                      //
+//////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug, Clone)]
+struct CastMedia {
+    url: String,
+    title: String,
+    subtitle: Option<String>,
+    image: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+enum CastCommand {
+    Load(CastMedia),
+    Play,
+    Pause,
+    Stop,
+    SetVolume(f64),
+    Disconnect,
+}
+
+#[derive(Debug, Clone)]
+enum CastEvent {
+    Connected(String),
+    Error(String),
+}
+
+#[derive(Clone)]
+pub struct ChromecastController {
+    sender: Sender<Action>,
+    commands: Rc<RefCell<Option<mpsc::Sender<CastCommand>>>>,
+    // Changes with every connect / disconnect, late events of an old worker thread get ignored
+    generation: Rc<Cell<u32>>,
+
+    station: Rc<RefCell<Option<Station>>>,
+    song_title: Rc<RefCell<Option<String>>>,
+    stream_url: Rc<RefCell<Option<String>>>,
+    // The receiver plays (or has paused) the current stream url
+    loaded: Rc<Cell<bool>>,
+    playing: Rc<Cell<bool>>,
+    volume: Rc<Cell<f64>>,
+    // Factor which gets applied on top of the volume (sleep timer)
+    fade: Rc<Cell<f64>>,
+}
+
+impl ChromecastController {
+    pub fn new(sender: Sender<Action>) -> Self {
+        Self {
+            sender,
+            commands: Rc::new(RefCell::new(None)),
+            generation: Rc::new(Cell::new(0)),
+            station: Rc::new(RefCell::new(None)),
+            song_title: Rc::new(RefCell::new(None)),
+            stream_url: Rc::new(RefCell::new(None)),
+            loaded: Rc::new(Cell::new(false)),
+            playing: Rc::new(Cell::new(false)),
+            volume: Rc::new(Cell::new(1.0)),
+            fade: Rc::new(Cell::new(1.0)),
+        }
+    }
+
+    pub fn connect(&self, receiver: CastReceiver) {
+        self.disconnect();
+
+        let (command_sender, command_receiver) = mpsc::channel();
+        let (event_sender, event_receiver) = glib::MainContext::channel(glib::PRIORITY_DEFAULT);
+        *self.commands.borrow_mut() = Some(command_sender);
+
+        thread::spawn(move || {
+            debug!("Connect to cast receiver {:?}", receiver);
+            if let Err(err) = Self::run(&receiver, &command_receiver, &event_sender) {
+                let _ = event_sender.send(CastEvent::Error(err.to_string()));
+            }
+            debug!("Disconnected from cast receiver {:?}", receiver);
+        });
+
+        let sender = self.sender.clone();
+        let commands = self.commands.clone();
+        let current_generation = self.generation.clone();
+        let generation = current_generation.get();
+        event_receiver.attach(None, move |event| {
+            // We're connected to another receiver now, or not at all anymore
+            if current_generation.get() != generation {
+                debug!("Ignore event of previous cast connection: {:?}", event);
+                return glib::Continue(false);
+            }
+
+            match event {
+                CastEvent::Connected(name) => {
+                    let message = format!("Connected to \"{}\"", name);
+                    sender.send(Action::ViewShowNotification(message)).unwrap();
+                }
+                CastEvent::Error(err) => {
+                    warn!("Cast error: {}", err);
+
+                    // The worker thread is already gone, continue with local playback
+                    if commands.borrow().is_some() {
+                        let message = format!("Connection to cast device lost: {}", err);
+                        sender.send(Action::ViewShowNotification(message)).unwrap();
+                        sender.send(Action::PlaybackSetCastReceiver(None)).unwrap();
+                    }
+                }
+            }
+            glib::Continue(true)
+        });
+    }
+
+    pub fn disconnect(&self) {
+        self.generation.set(self.generation.get().wrapping_add(1));
+        if let Some(commands) = self.commands.borrow_mut().take() {
+            let _ = commands.send(CastCommand::Disconnect);
+        }
+        self.loaded.set(false);
+        self.playing.set(false);
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.commands.borrow().is_some()
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playing.get()
+    }
+
+    /// The resolved stream of the current station, which gets handed to the receiver.
+    pub fn set_stream_url(&self, url: &str) {
+        *self.stream_url.borrow_mut() = Some(url.to_string());
+        self.loaded.set(false);
+
+        // Switch the receiver directly to the new station
+        if self.playing.get() {
+            self.load();
+        }
+    }
+
+    /// Factor between 0.0 and 1.0, which gets applied on top of the volume of the receiver (sleep timer).
+    pub fn set_fade(&self, fade: f64) {
+        // The sleep timer updates the fade several times per second, which is too often for the network
+        let fade = (fade * 50.0).round() / 50.0;
+        if fade == self.fade.get() {
+            return;
+        }
+
+        self.fade.set(fade);
+        self.send_command(CastCommand::SetVolume(self.volume.get() * fade));
+    }
+
+    fn load(&self) {
+        let url = match self.stream_url.borrow().clone() {
+            Some(url) => url,
+            None => return,
+        };
+        let station = self.station.borrow().clone();
+
+        let media = CastMedia {
+            url,
+            title: station.clone().map(|station| station.name).unwrap_or_default(),
+            subtitle: self.song_title.borrow().clone(),
+            image: station.map(|station| station.favicon).filter(|favicon| !favicon.is_empty()),
+        };
+        self.send_command(CastCommand::Load(media));
+        self.loaded.set(true);
+    }
+
+    fn send_command(&self, command: CastCommand) {
+        if let Some(commands) = self.commands.borrow().as_ref() {
+            let _ = commands.send(command);
+        }
+    }
+
+    fn run(receiver: &CastReceiver, commands: &mpsc::Receiver<CastCommand>, events: &Sender<CastEvent>) -> Result<(), rust_cast::errors::Error> {
+        let device = CastDevice::connect_without_host_verification(receiver.host.as_str(), receiver.port)?;
+        device.connection.connect(DEFAULT_DESTINATION_ID)?;
+        device.heartbeat.ping()?;
+        let _ = events.send(CastEvent::Connected(receiver.name.clone()));
+
+        let mut app: Option<Application> = None;
+        let mut media_session_id: Option<i32> = None;
+        let mut last_media: Option<CastMedia> = None;
+
+        loop {
+            let command = match commands.recv_timeout(Duration::from_secs(HEARTBEAT_INTERVAL_SECS)) {
+                Ok(command) => command,
+                Err(mpsc::RecvTimeoutError::Timeout) => {
+                    // Also reads the pending messages of the receiver
+                    device.heartbeat.ping()?;
+                    device.receiver.get_status()?;
+                    continue;
+                }
+                Err(mpsc::RecvTimeoutError::Disconnected) => break,
+            };
+            debug!("Cast command: {:?}", command);
+
+            // Play without loaded media -> load the last one again (e.g. after stop)
+            let command = match (command, media_session_id, last_media.clone()) {
+                (CastCommand::Play, None, Some(media)) => CastCommand::Load(media),
+                (command, _, _) => command,
+            };
+
+            match command {
+                CastCommand::Load(media) => {
+                    let current_app = match app.take() {
+                        Some(app) => app,
+                        None => {
+                            let app = device.receiver.launch_app(&CastDeviceApp::DefaultMediaReceiver)?;
+                            device.connection.connect(app.transport_id.as_str())?;
+                            app
+                        }
+                    };
+
+                    let status = device.media.load(current_app.transport_id.as_str(), current_app.session_id.as_str(), &Self::create_media(&media))?;
+                    media_session_id = status.entries.first().map(|entry| entry.media_session_id);
+                    last_media = Some(media);
+                    app = Some(current_app);
+                }
+                CastCommand::Play => {
+                    if let (Some(app), Some(id)) = (app.as_ref(), media_session_id) {
+                        device.media.play(app.transport_id.as_str(), id)?;
+                    }
+                }
+                CastCommand::Pause => {
+                    if let (Some(app), Some(id)) = (app.as_ref(), media_session_id) {
+                        device.media.pause(app.transport_id.as_str(), id)?;
+                    }
+                }
+                CastCommand::Stop => {
+                    if let (Some(app), Some(id)) = (app.as_ref(), media_session_id.take()) {
+                        device.media.stop(app.transport_id.as_str(), id)?;
+                    }
+                }
+                CastCommand::SetVolume(volume) => {
+                    device.receiver.set_volume(volume as f32)?;
+                }
+                CastCommand::Disconnect => break,
+            }
+        }
+
+        // Don't leave the receiver app running, when we're gone
+        if let Some(app) = app {
+            device.receiver.stop_app(app.session_id.as_str())?;
+        }
+        Ok(())
+    }
+
+    fn create_media(media: &CastMedia) -> Media {
+        let metadata = GenericMediaMetadata {
+            title: Some(media.title.clone()),
+            subtitle: media.subtitle.clone(),
+            images: media.image.iter().map(|url| Image::new(url.clone())).collect(),
+            release_date: None,
+        };
+
+        Media {
+            content_id: media.url.clone(),
+            content_type: Self::guess_content_type(&media.url).to_string(),
+            stream_type: StreamType::Live,
+            duration: None,
+            metadata: Some(Metadata::Generic(metadata)),
+        }
+    }
+
+    // Most receivers only look at the content type to decide which player they should use
+    fn guess_content_type(url: &str) -> &'static str {
+        let path = url.split('?').next().unwrap_or("").to_lowercase();
+        if path.ends_with(".m3u8") {
+            "application/x-mpegurl"
+        } else if path.ends_with(".aac") || path.ends_with(".aacp") {
+            "audio/aac"
+        } else if path.ends_with(".ogg") || path.ends_with(".opus") || path.ends_with(".oga") {
+            "audio/ogg"
+        } else {
+            "audio/mpeg"
+        }
+    }
+}
+
+impl Controller for ChromecastController {
+    fn set_station(&self, station: Station) {
+        *self.station.borrow_mut() = Some(station);
+        *self.song_title.borrow_mut() = None;
+    }
+
+    fn set_playback_state(&self, playback_state: &PlaybackState) {
+        if !self.is_connected() {
+            return;
+        }
+
+        match playback_state {
+            PlaybackState::Playing => {
+                if self.loaded.get() {
+                    self.send_command(CastCommand::Play);
+                } else {
+                    self.load();
+                }
+                self.playing.set(true);
+            }
+            PlaybackState::Paused => {
+                self.send_command(CastCommand::Pause);
+                self.playing.set(false);
+            }
+            PlaybackState::Stopped => {
+                self.send_command(CastCommand::Stop);
+                self.playing.set(false);
+            }
+            _ => (),
+        }
+    }
+
+    fn set_song(&self, song: &SongMetadata) {
+        // The receiver cannot update the metadata of a running stream, it's used for the next load
+        *self.song_title.borrow_mut() = Some(song.get_display_title());
+    }
+
+    fn set_volume(&self, volume: f64) {
+        self.volume.set(volume);
+        self.send_command(CastCommand::SetVolume(volume * self.fade.get()));
+    }
+
+    fn set_timeshift_position(&self, _delay: Duration, _buffered: Duration) {
+        // The receiver fetches the live stream itself, there's no timeshift while casting
+    }
+
+    fn set_sleep_timer(&self, _state: &SleepTimerState) {
+        // The receiver stops together with the local playback, nothing to show there
+    }
+
+    fn set_recording_mode(&self, _mode: RecordingMode) {
+        // The receiver only plays the stream, the recording happens locally
+    }
+}
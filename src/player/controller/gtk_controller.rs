@@ -2,12 +2,18 @@ use glib::Sender;
 use gtk::prelude::*;
 use rustio::Station;
 
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::rc::Rc;
+use std::time::Duration;
 
 use crate::app::Action;
+use crate::metadata::SongMetadata;
+use crate::player::recording_mode::RecordingMode;
+use crate::player::sleep_timer::SleepTimerState;
 use crate::player::Controller;
 use crate::player::PlaybackState;
+use crate::widgets::effects_popover::EffectsPopover;
+use crate::widgets::song_row::SongRow;
 use crate::widgets::station_dialog::StationDialog;
 
 pub struct GtkController {
@@ -21,9 +27,28 @@ pub struct GtkController {
     subtitle_revealer: gtk::Revealer,
     playback_button_stack: gtk::Stack,
     start_playback_button: gtk::Button,
+    pause_playback_button: gtk::Button,
     stop_playback_button: gtk::Button,
     info_button: gtk::Button,
+    volume_button: gtk::VolumeButton,
+    // Blocked while the volume gets set by us, e.g. muting shows 0 without changing the volume
+    volume_signal_id: RefCell<Option<glib::SignalHandlerId>>,
+    effects_popover: EffectsPopover,
     error_label: gtk::Label,
+    reconnect_revealer: gtk::Revealer,
+    reconnect_label: gtk::Label,
+    sleep_timer_revealer: gtk::Revealer,
+    sleep_timer_label: gtk::Label,
+    recording_revealer: gtk::Revealer,
+    recording_label: gtk::Label,
+    recording_mode: Cell<RecordingMode>,
+    // Nothing gets recorded while the playback is stopped
+    recording_active: Cell<bool>,
+
+    timeshift_revealer: gtk::Revealer,
+    timeshift_scale: gtk::Scale,
+    timeshift_label: gtk::Label,
+    live_button: gtk::Button,
 }
 
 impl GtkController {
@@ -39,9 +64,25 @@ impl GtkController {
         let subtitle_revealer: gtk::Revealer = builder.get_object("subtitle_revealer").unwrap();
         let playback_button_stack: gtk::Stack = builder.get_object("playback_button_stack").unwrap();
         let start_playback_button: gtk::Button = builder.get_object("start_playback_button").unwrap();
+        let pause_playback_button: gtk::Button = builder.get_object("pause_playback_button").unwrap();
         let stop_playback_button: gtk::Button = builder.get_object("stop_playback_button").unwrap();
         let info_button: gtk::Button = builder.get_object("info_button").unwrap();
+        let volume_button: gtk::VolumeButton = builder.get_object("volume_button").unwrap();
+        let effects_button: gtk::MenuButton = builder.get_object("effects_button").unwrap();
         let error_label: gtk::Label = builder.get_object("error_label").unwrap();
+        let reconnect_revealer: gtk::Revealer = builder.get_object("reconnect_revealer").unwrap();
+        let reconnect_label: gtk::Label = builder.get_object("reconnect_label").unwrap();
+        let sleep_timer_revealer: gtk::Revealer = builder.get_object("sleep_timer_revealer").unwrap();
+        let sleep_timer_label: gtk::Label = builder.get_object("sleep_timer_label").unwrap();
+        let recording_revealer: gtk::Revealer = builder.get_object("recording_revealer").unwrap();
+        let recording_label: gtk::Label = builder.get_object("recording_label").unwrap();
+        let timeshift_revealer: gtk::Revealer = builder.get_object("timeshift_revealer").unwrap();
+        let timeshift_scale: gtk::Scale = builder.get_object("timeshift_scale").unwrap();
+        let timeshift_label: gtk::Label = builder.get_object("timeshift_label").unwrap();
+        let live_button: gtk::Button = builder.get_object("live_button").unwrap();
+
+        let effects_popover = EffectsPopover::new(sender.clone());
+        effects_button.set_popover(Some(&effects_popover.widget));
 
         let controller = Self {
             widget,
@@ -53,9 +94,25 @@ impl GtkController {
             subtitle_revealer,
             playback_button_stack,
             start_playback_button,
+            pause_playback_button,
             stop_playback_button,
             info_button,
+            volume_button,
+            volume_signal_id: RefCell::new(None),
+            effects_popover,
             error_label,
+            reconnect_revealer,
+            reconnect_label,
+            sleep_timer_revealer,
+            sleep_timer_label,
+            recording_revealer,
+            recording_label,
+            recording_mode: Cell::new(RecordingMode::Off),
+            recording_active: Cell::new(false),
+            timeshift_revealer,
+            timeshift_scale,
+            timeshift_label,
+            live_button,
         };
 
         controller.setup_signals();
@@ -69,12 +126,40 @@ impl GtkController {
             sender.send(Action::PlaybackStart).unwrap();
         });
 
+        // pause_playback_button
+        let sender = self.sender.clone();
+        self.pause_playback_button.connect_clicked(move |_| {
+            sender.send(Action::PlaybackPause).unwrap();
+        });
+
         // stop_playback_button
         let sender = self.sender.clone();
         self.stop_playback_button.connect_clicked(move |_| {
             sender.send(Action::PlaybackStop).unwrap();
         });
 
+        // timeshift_scale (the value is the position in the buffered data, not the delay)
+        let sender = self.sender.clone();
+        self.timeshift_scale.connect_change_value(move |scale, _, value| {
+            let buffered = scale.get_adjustment().get_upper();
+            let delay = (buffered - value).max(0.0);
+            sender.send(Action::PlaybackSetTimeshiftDelay(Duration::from_millis((delay * 1000.0) as u64))).unwrap();
+            gtk::Inhibit(false)
+        });
+
+        // live_button
+        let sender = self.sender.clone();
+        self.live_button.connect_clicked(move |_| {
+            sender.send(Action::PlaybackSetTimeshiftDelay(Duration::from_secs(0))).unwrap();
+        });
+
+        // volume_button
+        let sender = self.sender.clone();
+        let signal_id = self.volume_button.connect_value_changed(move |_, value| {
+            sender.send(Action::PlaybackSetVolume(value)).unwrap();
+        });
+        *self.volume_signal_id.borrow_mut() = Some(signal_id);
+
         // info_button
         let station = self.station.clone();
         let app = self.app.clone();
@@ -87,6 +172,17 @@ impl GtkController {
             station_dialog.show();
         });
     }
+
+    fn update_recording_indicator(&self) {
+        let text = match self.recording_mode.get() {
+            RecordingMode::Off => "",
+            RecordingMode::PerSong => "Recording every song",
+            RecordingMode::Continuous => "Recording the whole session",
+        };
+
+        self.recording_label.set_text(text);
+        self.recording_revealer.set_reveal_child(self.recording_active.get() && self.recording_mode.get() != RecordingMode::Off);
+    }
 }
 
 impl Controller for GtkController {
@@ -101,9 +197,14 @@ impl Controller for GtkController {
 
     fn set_playback_state(&self, playback_state: &PlaybackState) {
         match playback_state {
-            PlaybackState::Playing => self.playback_button_stack.set_visible_child_name("stop_playback"),
+            PlaybackState::Playing => self.playback_button_stack.set_visible_child_name("pause_playback"),
+            PlaybackState::Paused => self.playback_button_stack.set_visible_child_name("start_playback"),
             PlaybackState::Stopped => self.playback_button_stack.set_visible_child_name("start_playback"),
             PlaybackState::Loading => self.playback_button_stack.set_visible_child_name("loading"),
+            PlaybackState::Reconnecting(attempt) => {
+                self.playback_button_stack.set_visible_child_name("loading");
+                self.reconnect_label.set_text(&format!("Reconnecting (attempt {})…", attempt));
+            }
             PlaybackState::Failure(msg) => {
                 self.playback_button_stack.set_visible_child_name("error");
                 let mut text = self.error_label.get_text().unwrap().to_string();
@@ -111,15 +212,64 @@ impl Controller for GtkController {
                 self.error_label.set_text(&text);
             }
         };
+
+        self.reconnect_revealer.set_reveal_child(matches!(playback_state, PlaybackState::Reconnecting(_)));
+
+        // The timeshift buffer gets discarded, as soon as the playback stops
+        let timeshift = match playback_state {
+            PlaybackState::Stopped | PlaybackState::Failure(_) => false,
+            _ => true,
+        };
+        self.timeshift_revealer.set_reveal_child(timeshift);
+
+        // Same as the timeshift buffer, the recording stops together with the playback
+        self.recording_active.set(timeshift);
+        self.update_recording_indicator();
     }
 
-    fn set_song_title(&self, title: &str) {
-        if title != "" {
-            self.subtitle_label.set_text(title);
+    fn set_song(&self, song: &SongMetadata) {
+        if !song.is_empty() {
+            self.subtitle_label.set_text(&song.get_display_title());
             self.subtitle_revealer.set_reveal_child(true);
         } else {
             self.subtitle_label.set_text("");
             self.subtitle_revealer.set_reveal_child(false);
         }
     }
+
+    fn set_volume(&self, volume: f64) {
+        // Only changes by the user are actions, otherwise muting would set the volume to 0.
+        let signal_id = self.volume_signal_id.borrow();
+        signal_id.as_ref().map(|id| self.volume_button.block_signal(id));
+        self.volume_button.set_value(volume);
+        signal_id.as_ref().map(|id| self.volume_button.unblock_signal(id));
+    }
+
+    fn set_timeshift_position(&self, delay: Duration, buffered: Duration) {
+        let buffered = buffered.as_millis() as f64 / 1000.0;
+        let delay = delay.as_millis() as f64 / 1000.0;
+
+        self.timeshift_scale.set_range(0.0, buffered.max(1.0));
+        self.timeshift_scale.set_value(buffered - delay);
+
+        self.timeshift_label.set_text(&format!("-{}", SongRow::format_duration(delay as u64)));
+        self.live_button.set_sensitive(delay >= 1.0);
+    }
+
+    fn set_sleep_timer(&self, state: &SleepTimerState) {
+        let text = match state {
+            SleepTimerState::Inactive => "".to_string(),
+            SleepTimerState::Remaining(remaining) => format!("Playback stops in {}", SongRow::format_duration(remaining.as_secs())),
+            SleepTimerState::EndOfSong => "Playback stops after this song".to_string(),
+            SleepTimerState::FadingOut => "Playback stops now…".to_string(),
+        };
+
+        self.sleep_timer_label.set_text(&text);
+        self.sleep_timer_revealer.set_reveal_child(*state != SleepTimerState::Inactive);
+    }
+
+    fn set_recording_mode(&self, mode: RecordingMode) {
+        self.recording_mode.set(mode);
+        self.update_recording_indicator();
+    }
 }
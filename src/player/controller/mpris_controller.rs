@@ -3,9 +3,14 @@ use mpris_player::{Metadata, MprisPlayer, OrgMprisMediaPlayer2Player, PlaybackSt
 use rustio::Station;
 
 use std::cell::Cell;
+use std::rc::Rc;
 use std::sync::Arc;
+use std::time::Duration;
 
 use crate::app::Action;
+use crate::metadata::SongMetadata;
+use crate::player::recording_mode::RecordingMode;
+use crate::player::sleep_timer::SleepTimerState;
 use crate::player::Controller;
 use crate::player::PlaybackState;
 
@@ -13,8 +18,9 @@ pub struct MprisController {
     sender: Sender<Action>,
     mpris: Arc<MprisPlayer>,
 
-    song_title: Cell<Option<String>>,
+    song: Cell<Option<SongMetadata>>,
     station: Cell<Option<Station>>,
+    timeshift_delay: Rc<Cell<Duration>>,
 }
 
 impl MprisController {
@@ -29,8 +35,9 @@ impl MprisController {
         let controller = Self {
             sender,
             mpris,
-            song_title: Cell::new(None),
+            song: Cell::new(None),
             station: Cell::new(None),
+            timeshift_delay: Rc::new(Cell::new(Duration::from_secs(0))),
         };
 
         controller.setup_signals();
@@ -41,18 +48,23 @@ impl MprisController {
         let mut metadata = Metadata::new();
 
         let station = self.station.take();
-        let song_title = self.song_title.take();
+        let song = self.song.take();
 
+        // The station is the album, and the artist if the stream doesn't tell us the real one
         station.clone().map(|station| {
             metadata.art_url = Some(station.favicon);
+            metadata.album = Some(station.name.clone());
             metadata.artist = Some(vec![station.name]);
         });
-        song_title.clone().map(|song_title| {
-            metadata.title = Some(song_title);
+        song.clone().filter(|song| !song.is_empty()).map(|song| {
+            metadata.title = Some(song.title);
+            if let Some(artist) = song.artist {
+                metadata.artist = Some(vec![artist]);
+            }
         });
 
         self.station.set(station);
-        self.song_title.set(song_title);
+        self.song.set(song);
 
         self.mpris.set_metadata(metadata);
     }
@@ -71,7 +83,7 @@ impl MprisController {
             match mpris.get_playback_status().unwrap().as_ref() {
                 "Paused" => sender.send(Action::PlaybackStart).unwrap(),
                 "Stopped" => sender.send(Action::PlaybackStart).unwrap(),
-                _ => sender.send(Action::PlaybackStop).unwrap(),
+                _ => sender.send(Action::PlaybackPause).unwrap(),
             };
         });
 
@@ -90,7 +102,22 @@ impl MprisController {
         // mpris pause
         let sender = self.sender.clone();
         self.mpris.connect_pause(move || {
-            sender.send(Action::PlaybackStop).unwrap();
+            sender.send(Action::PlaybackPause).unwrap();
+        });
+
+        // mpris volume
+        let sender = self.sender.clone();
+        self.mpris.connect_volume(move |volume| {
+            sender.send(Action::PlaybackSetVolume(volume)).unwrap();
+        });
+
+        // mpris seek (offset in microseconds, positive values are moving towards the live stream)
+        let sender = self.sender.clone();
+        let timeshift_delay = self.timeshift_delay.clone();
+        self.mpris.connect_seek(move |offset| {
+            let delay = timeshift_delay.get().as_micros() as i64 - offset;
+            let delay = Duration::from_micros(delay.max(0) as u64);
+            sender.send(Action::PlaybackSetTimeshiftDelay(delay)).unwrap();
         });
     }
 }
@@ -106,12 +133,38 @@ impl Controller for MprisController {
 
         match playback_state {
             PlaybackState::Playing => self.mpris.set_playback_status(PlaybackStatus::Playing),
-            _ => self.mpris.set_playback_status(PlaybackStatus::Stopped),
+            PlaybackState::Paused => self.mpris.set_playback_status(PlaybackStatus::Paused),
+            // We're still trying to play the station
+            PlaybackState::Reconnecting(_) => self.mpris.set_playback_status(PlaybackStatus::Playing),
+            _ => {
+                self.mpris.set_can_seek(false);
+                self.mpris.set_playback_status(PlaybackStatus::Stopped);
+            }
         };
     }
 
-    fn set_song_title(&self, title: &str) {
-        self.song_title.set(Some(title.to_string()));
+    fn set_song(&self, song: &SongMetadata) {
+        self.song.set(Some(song.clone()));
         self.update_metadata();
     }
+
+    fn set_volume(&self, volume: f64) {
+        self.mpris.set_volume(volume);
+    }
+
+    fn set_timeshift_position(&self, delay: Duration, buffered: Duration) {
+        self.timeshift_delay.set(delay);
+
+        // The position is the offset from the oldest buffered data
+        self.mpris.set_can_seek(buffered.as_secs() > 0);
+        self.mpris.set_position((buffered - delay).as_micros() as i64);
+    }
+
+    fn set_sleep_timer(&self, _state: &SleepTimerState) {
+        // MPRIS has no concept of a sleep timer
+    }
+
+    fn set_recording_mode(&self, _mode: RecordingMode) {
+        // Nothing to show, the recordings are only available in the song list
+    }
 }
@@ -0,0 +1,266 @@
+use gstreamer::prelude::*;
+use gstreamer::{Bin, Element, ElementFactory, GhostPad};
+
+use crate::settings::{Key, SettingsManager};
+
+// Gain range of the equalizer bands in dB
+pub const BAND_GAIN_MIN: f64 = -12.0;
+pub const BAND_GAIN_MAX: f64 = 12.0;
+
+// Center frequencies of the equalizer-10bands element, only used for the labels
+pub static BAND_FREQUENCIES: [&str; 10] = ["29", "59", "119", "237", "474", "947", "1.9k", "3.8k", "7.5k", "15k"];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EqualizerPreset {
+    Flat,
+    Classical,
+    Pop,
+    Rock,
+    Jazz,
+    Dance,
+    Speech,
+    BassBoost,
+    TrebleBoost,
+    Custom,
+}
+
+impl EqualizerPreset {
+    pub fn all() -> Vec<EqualizerPreset> {
+        vec![
+            EqualizerPreset::Flat,
+            EqualizerPreset::Classical,
+            EqualizerPreset::Pop,
+            EqualizerPreset::Rock,
+            EqualizerPreset::Jazz,
+            EqualizerPreset::Dance,
+            EqualizerPreset::Speech,
+            EqualizerPreset::BassBoost,
+            EqualizerPreset::TrebleBoost,
+            EqualizerPreset::Custom,
+        ]
+    }
+
+    pub fn from_str(name: &str) -> Self {
+        match name {
+            "classical" => EqualizerPreset::Classical,
+            "pop" => EqualizerPreset::Pop,
+            "rock" => EqualizerPreset::Rock,
+            "jazz" => EqualizerPreset::Jazz,
+            "dance" => EqualizerPreset::Dance,
+            "speech" => EqualizerPreset::Speech,
+            "bass-boost" => EqualizerPreset::BassBoost,
+            "treble-boost" => EqualizerPreset::TrebleBoost,
+            "custom" => EqualizerPreset::Custom,
+            _ => EqualizerPreset::Flat,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            EqualizerPreset::Flat => "flat",
+            EqualizerPreset::Classical => "classical",
+            EqualizerPreset::Pop => "pop",
+            EqualizerPreset::Rock => "rock",
+            EqualizerPreset::Jazz => "jazz",
+            EqualizerPreset::Dance => "dance",
+            EqualizerPreset::Speech => "speech",
+            EqualizerPreset::BassBoost => "bass-boost",
+            EqualizerPreset::TrebleBoost => "treble-boost",
+            EqualizerPreset::Custom => "custom",
+        }
+    }
+
+    pub fn get_title(self) -> &'static str {
+        match self {
+            EqualizerPreset::Flat => "Flat",
+            EqualizerPreset::Classical => "Classical",
+            EqualizerPreset::Pop => "Pop",
+            EqualizerPreset::Rock => "Rock",
+            EqualizerPreset::Jazz => "Jazz",
+            EqualizerPreset::Dance => "Dance",
+            EqualizerPreset::Speech => "Speech",
+            EqualizerPreset::BassBoost => "Bass Boost",
+            EqualizerPreset::TrebleBoost => "Treble Boost",
+            EqualizerPreset::Custom => "Custom",
+        }
+    }
+
+    /// Gain of every band in dB, `None` for the user defined preset.
+    pub fn get_bands(self) -> Option<[f64; 10]> {
+        match self {
+            EqualizerPreset::Flat => Some([0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0]),
+            EqualizerPreset::Classical => Some([0.0, 0.0, 0.0, 0.0, 0.0, 0.0, -4.0, -4.0, -4.0, -6.0]),
+            EqualizerPreset::Pop => Some([-1.0, 3.0, 4.5, 5.0, 3.0, 0.0, -1.0, -1.0, -1.0, -1.0]),
+            EqualizerPreset::Rock => Some([5.0, 3.0, -3.0, -5.0, -2.0, 2.0, 5.0, 6.5, 6.5, 6.5]),
+            EqualizerPreset::Jazz => Some([4.0, 3.0, 1.0, 2.0, -1.5, -1.5, 0.0, 1.0, 3.0, 4.0]),
+            EqualizerPreset::Dance => Some([6.0, 4.5, 1.5, 0.0, 0.0, -3.5, -4.5, -4.5, 0.0, 0.0]),
+            EqualizerPreset::Speech => Some([-6.0, -4.0, -1.0, 2.0, 4.0, 4.0, 3.0, 1.0, -2.0, -4.0]),
+            EqualizerPreset::BassBoost => Some([7.0, 6.0, 4.5, 2.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0]),
+            EqualizerPreset::TrebleBoost => Some([0.0, 0.0, 0.0, 0.0, 0.0, 1.0, 3.0, 5.0, 6.5, 7.0]),
+            EqualizerPreset::Custom => None,
+        }
+    }
+}
+
+/// Settings of the effects chain in the playback pipeline.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Effects {
+    pub equalizer: bool,
+    pub preset: EqualizerPreset,
+    // Gain of every band in dB, these are the custom values if `preset` is `Custom`
+    pub bands: [f64; 10],
+    pub night_mode: bool,
+}
+
+impl Effects {
+    pub fn load() -> Self {
+        let preset = EqualizerPreset::from_str(&SettingsManager::get_string(Key::EqualizerPreset));
+
+        Self {
+            equalizer: SettingsManager::get_boolean(Key::EqualizerEnabled),
+            preset,
+            bands: preset.get_bands().unwrap_or_else(Self::get_custom_bands),
+            night_mode: SettingsManager::get_boolean(Key::NightMode),
+        }
+    }
+
+    /// The last values of the user defined preset.
+    pub fn get_custom_bands() -> [f64; 10] {
+        let mut bands = [0.0; 10];
+        let custom = SettingsManager::get_string(Key::EqualizerBands);
+        for (band, value) in bands.iter_mut().zip(custom.split_whitespace()) {
+            *band = value.parse::<f64>().unwrap_or(0.0).max(BAND_GAIN_MIN).min(BAND_GAIN_MAX);
+        }
+        bands
+    }
+
+    pub fn save(&self) {
+        SettingsManager::set_boolean(Key::EqualizerEnabled, self.equalizer);
+        SettingsManager::set_string(Key::EqualizerPreset, self.preset.as_str());
+        SettingsManager::set_boolean(Key::NightMode, self.night_mode);
+
+        // Only the custom values are worth keeping, the presets are fixed
+        if self.preset == EqualizerPreset::Custom {
+            let bands: Vec<String> = self.bands.iter().map(|band| band.to_string()).collect();
+            SettingsManager::set_string(Key::EqualizerBands, &bands.join(" "));
+        }
+    }
+
+    pub fn is_bypassed(&self) -> bool {
+        !self.equalizer && !self.night_mode
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////////////////////////////////
+//                                                                                                      //
+//  # EffectsBin                                                                                        //
+//                                                                                                      //
+//    -------------------------------------------------------------------------------------------       //
+//   |                  --------------      -----------      ------------      --------------   |       //
+//   | ( ghostpad ) -> | audioconvert | -> | equalizer | -> | night mode | -> | audioconvert |  |       //
+//   |                  --------------      -----------      ------------      --------------   |       //
+//    -------------------------------------------------------------------------------------------       //
+//                                                                                                      //
+//  Both stages are optional. Night mode:                                                               //
+//    ------------      --------------      ---------                                                   //
+//   | compressor | -> | audioamplify | -> | limiter |                                                  //
+//    ------------      --------------      ---------                                                   //
+//                                                                                                      //
+//  compressor and limiter are audiodynamic elements, the limiter has an infinite ratio.                //
+//                                                                                                      //
+//  Only the enabled stages are part of the bin. If no stage is enabled, the whole bin gets removed     //
+//  from the playback pipeline, so it doesn't cost anything.                                            //
+//                                                                                                      //
+//////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+// Night mode: everything above the threshold gets compressed, the quiet parts get boosted afterwards
+const COMPRESSOR_THRESHOLD: f64 = 0.1;
+const COMPRESSOR_RATIO: f64 = 0.25;
+const MAKEUP_GAIN: f64 = 2.5;
+const LIMITER_THRESHOLD: f64 = 0.9;
+
+pub struct EffectsBin {
+    pub bin: Bin,
+    equalizer: Option<Element>,
+    night_mode: bool,
+}
+
+impl EffectsBin {
+    /// `None` if an element is missing (gst-plugins-good), the playback continues without effects then.
+    pub fn new(effects: &Effects) -> Option<Self> {
+        let bin = Bin::new("effects");
+        let mut elements: Vec<Element> = vec![Self::make("audioconvert", "effects_convert_in")?];
+
+        let equalizer = if effects.equalizer {
+            let equalizer = Self::make("equalizer-10bands", "equalizer")?;
+            elements.push(equalizer.clone());
+            Some(equalizer)
+        } else {
+            None
+        };
+
+        if effects.night_mode {
+            let compressor = Self::make("audiodynamic", "compressor")?;
+            compressor.set_property_from_str("characteristics", "soft-knee");
+            compressor.set_property_from_str("mode", "compressor");
+            compressor.set_property("threshold", &(COMPRESSOR_THRESHOLD as f32)).unwrap();
+            compressor.set_property("ratio", &(COMPRESSOR_RATIO as f32)).unwrap();
+
+            let amplify = Self::make("audioamplify", "makeup_gain")?;
+            amplify.set_property("amplification", &(MAKEUP_GAIN as f32)).unwrap();
+
+            let limiter = Self::make("audiodynamic", "limiter")?;
+            limiter.set_property_from_str("characteristics", "hard-knee");
+            limiter.set_property_from_str("mode", "compressor");
+            limiter.set_property("threshold", &(LIMITER_THRESHOLD as f32)).unwrap();
+            limiter.set_property("ratio", &0.0f32).unwrap();
+
+            elements.push(compressor);
+            elements.push(amplify);
+            elements.push(limiter);
+        }
+        elements.push(Self::make("audioconvert", "effects_convert_out")?);
+
+        let element_refs: Vec<&Element> = elements.iter().collect();
+        bin.add_many(&element_refs).unwrap();
+        Element::link_many(&element_refs).unwrap();
+
+        let sinkpad = elements.first().unwrap().get_static_pad("sink").unwrap();
+        let ghostpad = GhostPad::new("sink", &sinkpad).unwrap();
+        bin.add_pad(&ghostpad).unwrap();
+
+        let srcpad = elements.last().unwrap().get_static_pad("src").unwrap();
+        let ghostpad = GhostPad::new("src", &srcpad).unwrap();
+        bin.add_pad(&ghostpad).unwrap();
+
+        let effects_bin = Self {
+            bin,
+            equalizer,
+            night_mode: effects.night_mode,
+        };
+        effects_bin.set_bands(&effects.bands);
+        Some(effects_bin)
+    }
+
+    fn make(factory: &str, name: &str) -> Option<Element> {
+        let element = ElementFactory::make(factory, name);
+        if element.is_none() {
+            warn!("GStreamer element \"{}\" is not installed, effects are disabled", factory);
+        }
+        element
+    }
+
+    /// Whether the bin contains the stages, which are needed for `effects`.
+    pub fn matches(&self, effects: &Effects) -> bool {
+        self.equalizer.is_some() == effects.equalizer && self.night_mode == effects.night_mode
+    }
+
+    /// The band gains can be changed while playing.
+    pub fn set_bands(&self, bands: &[f64; 10]) {
+        if let Some(equalizer) = self.equalizer.as_ref() {
+            for (i, gain) in bands.iter().enumerate() {
+                equalizer.set_property(&format!("band{}", i), gain).unwrap();
+            }
+        }
+    }
+}
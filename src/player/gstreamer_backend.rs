@@ -1,14 +1,24 @@
 use glib::Sender;
 use gstreamer::prelude::*;
-use gstreamer::{Bin, Element, ElementFactory, GhostPad, Pad, PadProbeId, Pipeline, State};
+use gstreamer::{Bin, Caps, Element, ElementFactory, GhostPad, Pad, PadProbeId, Pipeline, State, TagList, TagMergeMode, TagSetter, Toc, TocEntry, TocEntryType, TocScope, TocSetter};
+use gstreamer_app::{AppSink, AppSrc};
+use rustio::Station;
 
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
+use crate::filename_template::FilenameTemplate;
+use crate::metadata::{MetadataParser, SongMetadata};
+use crate::player::effects::{Effects, EffectsBin};
+use crate::player::loudness::LoudnessMeter;
 use crate::player::playback_state::PlaybackState;
-use crate::song::Song;
+use crate::player::preroll::Preroll;
+use crate::player::recording_format::{self, RecordingError, RecordingFormat, STREAM_CAPS};
+use crate::player::timeshift::{Timeshift, TIMESHIFT_CAPS};
+use crate::settings::{Key, SettingsManager};
+use crate::song::{Chapter, Song};
 
 //////////////////////////////////////////////////////////////////////////////////////////////////////////
 //                                                                                                      //
@@ -17,22 +27,75 @@ use crate::song::Song;
 //                                           |     | -> | queue [1] -> | recorderbin |                  //
 //    --------------      --------------     |     |     --------       -------------                   //
 //   | uridecodebin | -> | audioconvert | -> | tee |                                                    //
-//    --------------      --------------     |     |     -------      --------      ---------------     //
-//                                           |     | -> | queue | -> | volume | -> | autoaudiosink |    //
-//                                            -----      -------      --------      ---------------     //
+//    --------------      --------------     |     |     -------      --------------                    //
+//                                           |     | -> | queue | -> | audioconvert | -> ...            //
+//                                            -----      -------      --------------                    //
 //                                                                                                      //
+//            ---------------      ------------                                                         //
+//    ... -> | audioresample | -> | appsink[2] |                                                        //
+//            ---------------      ------------                                                         //
+//                                                                                                      //
+//  # Compressed Streams [6]                                                                            //
+//                           -----      -------      -----------                                        //
+//                          |     | -> | queue | -> | decodebin | -> audioconvert (see above)           //
+//    --------------        |     |     -------      -----------                                        //
+//   | uridecodebin | ->    | tee |                                                                     //
+//    --------------        |     |     -----------      -------------                                  //
+//                          |     | -> | queue [6] | -> | recorderbin |                                 //
+//                           -----      -----------      -------------                                  //
+//                                                                                                      //
+//  # Playback Pipeline                                                                                 //
+//    -----------      ------------------      --------------                                           //
+//   | appsrc[2] | -> | normalization[5] | -> | audioconvert | -> ...                                   //
+//    -----------      ------------------      --------------                                           //
+//                                                                                                      //
+//            ------------      --------      --------------                                            //
+//    ... -> | effects[4] | -> | volume | -> | audiosink[3] |                                           //
+//            ------------      --------      --------------                                            //
 //                                                                                                      //
 //                                                                                                      //
 //  We use the the file_srcpad[1] to block the dataflow, so we can change the recorderbin.              //
 //  The dataflow gets blocked when the song changes.                                                    //
+//  If nothing gets recorded (recording mode "off", or a discarded recording), the data gets dropped    //
+//  at the file_srcpad[1] instead. Continuous recordings (sessions) keep the same recorderbin, and only //
+//  remember a chapter when the song changes. When a session ends, its recorderbin gets removed from   //
+//  the pipeline and finalizes the file on its own, so the pipeline can stop in the meantime.           //
+//  The recording queues hold back the last seconds of audio (pre-roll, see preroll.rs), so a new song  //
+//  can start in front of its title change.                                                             //
+//                                                                                                      //
+//  The live stream gets written into the timeshift buffer by appsink[2], and appsrc[2] reads it from   //
+//  there again. This way we can pause / rewind the playback, while the live stream keeps running.      //
+//                                                                                                      //
+//  The audiosink[3] is an autoaudiosink by default, or the sink of the selected audio output device.   //
+//  It can be replaced while playing, the volume src pad gets blocked for that.                         //
+//                                                                                                      //
+//  The effects[4] bin (equalizer, night mode) is only part of the pipeline, if at least one effect is  //
+//  enabled. Otherwise audioconvert is linked directly to volume.                                       //
+//                                                                                                      //
+//  The normalization[5] adjusts the gain, so that every station has the same loudness. The loudness    //
+//  gets measured at its sink pad (see loudness.rs).                                                    //
+//                                                                                                      //
+//  MP3, AAC, Vorbis and Opus streams only get parsed by uridecodebin (see STREAM_CAPS), and are decoded//
+//  by our own decodebin. This way the recorderbin can take the original stream from the queue[6], and  //
+//  write it to disk without re-encoding it (recording-passthrough). Only one of the two recording      //
+//  queues is used at the same time, the data of the other one gets dropped. Streams in other formats   //
+//  are decoded by uridecodebin, and linked to audioconvert directly.                                   //
 //                                                                                                      //
 //////////////////////////////////////////////////////////////////////////////////////////////////////////
 
 #[derive(Clone)]
 pub enum GstreamerMessage {
-    SongTitleChanged(String),
+    SongTitleChanged(SongMetadata),
+    // The title which is audible right now, later than `SongTitleChanged` if the playback is delayed (timeshift)
+    PlayedSongChanged(SongMetadata),
     PlaybackStateChanged(PlaybackState),
+    TimeshiftPositionChanged(Duration, Duration),
+    StreamResolved(String),
+    StreamConnected,
+    StreamError(String),
     RecordingStopped,
+    // The file of a session is complete, the station is the one which got recorded
+    RecordingFinished(Song, Option<Station>),
 }
 
 #[allow(dead_code)]
@@ -40,19 +103,48 @@ pub struct GstreamerBackend {
     pipeline: Pipeline,
 
     uridecodebin: Element,
+    stream_tee: Element,
+    decode_queue: Element,
+    decodebin: Element,
     audioconvert: Element,
     tee: Element,
 
     audio_queue: Element,
+    timeshift_convert: Element,
+    timeshift_resample: Element,
+    timeshift_sink: AppSink,
+
+    playback_pipeline: Pipeline,
+    timeshift_src: AppSrc,
+    normalization: Element,
+    loudness_meter: Arc<Mutex<LoudnessMeter>>,
+    playback_convert: Element,
+    effects: Option<EffectsBin>,
     volume: Element,
-    autoaudiosink: Element,
+    volume_level: f64,
+    fade: f64,
+    audiosink: Element,
 
     file_queue: Element,
+    stream_queue: Element,
     recorderbin: Arc<Mutex<Option<RecorderBin>>>,
+    // Finished sessions, which are still writing the end of their file
+    finishing_recorderbins: Vec<RecorderBin>,
     file_srcpad: Pad,
+    stream_srcpad: Pad,
     file_blockprobe_id: Option<PadProbeId>,
+    file_preroll: Preroll,
+    stream_preroll: Preroll,
+    // The current recording uses the compressed stream (stream_srcpad) instead of the raw audio (file_srcpad)
+    passthrough: Arc<Mutex<bool>>,
 
-    current_title: Arc<Mutex<String>>,
+    timeshift: Timeshift,
+    paused: Arc<Mutex<bool>>,
+    // Only needed for the tags of the recorded songs
+    station: Option<Station>,
+    current_song: Arc<Mutex<SongMetadata>>,
+    played_song: Arc<Mutex<SongMetadata>>,
+    metadata_parser: MetadataParser,
     sender: Sender<GstreamerMessage>,
 }
 
@@ -62,17 +154,45 @@ impl GstreamerBackend {
         let pipeline = Pipeline::new("recorder_pipeline");
 
         // create pipeline elements
-        let uridecodebin = ElementFactory::make("uridecodebin", "uridecodebin").unwrap();
         let audioconvert = ElementFactory::make("audioconvert", "audioconvert").unwrap();
+        let stream_tee = ElementFactory::make("tee", "stream_tee").unwrap();
+        let decode_queue = ElementFactory::make("queue", "decode_queue").unwrap();
+        let decodebin = ElementFactory::make("decodebin", "decodebin").unwrap();
+        let uridecodebin = Self::create_uridecodebin(&audioconvert, Some(&stream_tee), sender.clone(), false);
         let tee = ElementFactory::make("tee", "tee").unwrap();
         let audio_queue = ElementFactory::make("queue", "audio_queue").unwrap();
-        let volume = ElementFactory::make("volume", "volume").unwrap();
-        let autoaudiosink = ElementFactory::make("autoaudiosink", "autoaudiosink").unwrap();
+        let timeshift_convert = ElementFactory::make("audioconvert", "timeshift_convert").unwrap();
+        let timeshift_resample = ElementFactory::make("audioresample", "timeshift_resample").unwrap();
+        let timeshift_sink = ElementFactory::make("appsink", "timeshift_sink").unwrap();
         let file_queue = ElementFactory::make("queue", "file_queue").unwrap();
         let file_srcpad = file_queue.get_static_pad("src").unwrap();
+        let stream_queue = ElementFactory::make("queue", "stream_queue").unwrap();
+        let stream_srcpad = stream_queue.get_static_pad("src").unwrap();
+        let file_preroll = Preroll::new(&file_queue);
+        let stream_preroll = Preroll::new(&stream_queue);
+
+        // The live stream gets buffered on disk, so the appsink never has to wait for the clock
+        let timeshift_sink = timeshift_sink.dynamic_cast::<AppSink>().unwrap();
+        timeshift_sink.set_caps(Some(&gstreamer::Caps::from_string(TIMESHIFT_CAPS).unwrap()));
+        timeshift_sink.set_property("sync", &false).unwrap();
 
         // link pipeline elements
-        pipeline.add_many(&[&uridecodebin, &audioconvert, &tee, &audio_queue, &volume, &autoaudiosink, &file_queue]).unwrap();
+        pipeline
+            .add_many(&[
+                &uridecodebin,
+                &stream_tee,
+                &decode_queue,
+                &decodebin,
+                &stream_queue,
+                &audioconvert,
+                &tee,
+                &audio_queue,
+                &timeshift_convert,
+                &timeshift_resample,
+                timeshift_sink.upcast_ref(),
+                &file_queue,
+            ])
+            .unwrap();
         Element::link_many(&[&audioconvert, &tee]).unwrap();
         let tee_tempmlate = tee.get_pad_template("src_%u").unwrap();
 
@@ -80,63 +200,189 @@ impl GstreamerBackend {
         let tee_file_srcpad = tee.request_pad(&tee_tempmlate, None, None).unwrap();
         let _ = tee_file_srcpad.link(&file_queue.get_static_pad("sink").unwrap());
 
-        // link tee -> queue -> volume -> autoaudiosink
+        // link tee -> queue -> audioconvert -> audioresample -> appsink
         let tee_audio_srcpad = tee.request_pad(&tee_tempmlate, None, None).unwrap();
         let _ = tee_audio_srcpad.link(&audio_queue.get_static_pad("sink").unwrap());
-        let _ = audio_queue.link(&volume);
-        let _ = volume.link(&autoaudiosink);
+        let _ = audio_queue.link(&timeshift_convert);
+        let _ = timeshift_convert.link(&timeshift_resample);
+        let _ = timeshift_resample.link(&timeshift_sink);
+
+        // link stream_tee -> queue -> decodebin, and stream_tee -> queue
+        let _ = stream_tee.link(&decode_queue);
+        let _ = decode_queue.link(&decodebin);
+        let _ = stream_tee.link(&stream_queue);
 
-        // dynamically link uridecodebin element with audioconvert element
+        // dynamically link decodebin element with audioconvert element
         let convert = audioconvert.clone();
-        uridecodebin.connect_pad_added(move |_, src_pad| {
-            let sink_pad = convert.get_static_pad("sink").expect("Failed to get static sink pad from convert");
-            if sink_pad.is_linked() {
-                return; // We are already linked. Ignoring.
+        decodebin.connect_pad_added(move |_, src_pad| {
+            let sink_pad = convert.get_static_pad("sink").unwrap();
+            if !sink_pad.is_linked() {
+                let _ = src_pad.link(&sink_pad);
+            }
+        });
+
+        // Only the recording queue which is currently used gets data, see `start_recording`
+        let passthrough = Arc::new(Mutex::new(false));
+        let p = passthrough.clone();
+        file_srcpad.add_probe(gstreamer::PadProbeType::BUFFER, move |_, _| {
+            if *p.lock().unwrap() {
+                gstreamer::PadProbeReturn::Drop
+            } else {
+                gstreamer::PadProbeReturn::Ok
             }
+        });
+        let p = passthrough.clone();
+        stream_srcpad.add_probe(gstreamer::PadProbeType::BUFFER, move |_, _| {
+            if *p.lock().unwrap() {
+                gstreamer::PadProbeReturn::Ok
+            } else {
+                gstreamer::PadProbeReturn::Drop
+            }
+        });
 
-            let new_pad_caps = src_pad.get_current_caps().expect("Failed to get caps of new pad.");
-            let new_pad_struct = new_pad_caps.get_structure(0).expect("Failed to get first structure of caps.");
-            let new_pad_type = new_pad_struct.get_name();
+        // create playback pipeline
+        let playback_pipeline = Pipeline::new("playback_pipeline");
 
-            if new_pad_type.starts_with("audio/x-raw") {
-                // check if new_pad is audio
-                let _ = src_pad.link(&sink_pad);
-                return;
+        // create playback pipeline elements
+        let timeshift_src = ElementFactory::make("appsrc", "timeshift_src").unwrap();
+        let normalization = ElementFactory::make("volume", "normalization").unwrap();
+        let playback_convert = ElementFactory::make("audioconvert", "playback_convert").unwrap();
+        let volume = ElementFactory::make("volume", "volume").unwrap();
+        let audiosink = ElementFactory::make("autoaudiosink", "audiosink").unwrap();
+
+        let timeshift_src = timeshift_src.dynamic_cast::<AppSrc>().unwrap();
+        timeshift_src.set_caps(Some(&gstreamer::Caps::from_string(TIMESHIFT_CAPS).unwrap()));
+        timeshift_src.set_property("format", &gstreamer::Format::Time).unwrap();
+        // Keep the internal queue small, otherwise seeking would take effect with a noticeable delay
+        timeshift_src.set_property("max-bytes", &35_280u64).unwrap();
+
+        // link appsrc -> normalization -> audioconvert -> volume -> audiosink
+        playback_pipeline
+            .add_many(&[timeshift_src.upcast_ref(), &normalization, &playback_convert, &volume, &audiosink])
+            .unwrap();
+        Element::link_many(&[timeshift_src.upcast_ref(), &normalization, &playback_convert, &volume, &audiosink]).unwrap();
+
+        // Measure the loudness of everything that gets played, before the normalization gain gets applied
+        let loudness_meter = Arc::new(Mutex::new(LoudnessMeter::new()));
+        let meter = loudness_meter.clone();
+        let normalization_sinkpad = normalization.get_static_pad("sink").unwrap();
+        normalization_sinkpad.add_probe(gstreamer::PadProbeType::BUFFER, move |_, info| {
+            if let Some(gstreamer::PadProbeData::Buffer(ref buffer)) = info.data {
+                if let Some(map) = buffer.map_readable() {
+                    meter.lock().unwrap().process(map.as_slice());
+                }
             }
+            gstreamer::PadProbeReturn::Ok
         });
 
-        // Current song title. We need this variable to check if the title have changed.
-        let current_title = Arc::new(Mutex::new(String::new()));
+        let timeshift = Timeshift::new(&timeshift_sink, &timeshift_src);
+
+        // Compressed streams end at the stream_tee, so their EOS never reaches audioconvert
+        Self::add_eos_probe(&audioconvert.get_static_pad("sink").unwrap(), sender.clone());
+        Self::add_eos_probe(&stream_tee.get_static_pad("sink").unwrap(), sender.clone());
+
+        // Current song. We need this variable to check if the title have changed.
+        let current_song = Arc::new(Mutex::new(SongMetadata::default()));
+        let played_song = Arc::new(Mutex::new(SongMetadata::default()));
+        let metadata_parser = MetadataParser::new();
+
+        // Whether the playback got paused by the user, or is only waiting for data.
+        let paused = Arc::new(Mutex::new(false));
 
         // listen for new pipeline / bus messages
-        let ct = current_title.clone();
+        let cs = current_song.clone();
+        let mp = metadata_parser.clone();
+        let ts = timeshift.clone();
         let bus = pipeline.get_bus().expect("Unable to get pipeline bus");
         let s = sender.clone();
         gtk::timeout_add(250, move || {
             while bus.have_pending() {
                 bus.pop().map(|message| {
                     //debug!("new message {:?}", message);
-                    Self::parse_bus_message(&message, s.clone(), ct.clone());
+                    Self::parse_bus_message(&message, s.clone(), cs.clone(), &mp, Some(&ts));
+                });
+            }
+            Continue(true)
+        });
+
+        // Announce the title when the playback reaches it, and not already when it arrives live
+        let ts = timeshift.clone();
+        let ps = played_song.clone();
+        let s = sender.clone();
+        gtk::timeout_add(250, move || {
+            if let Some(song) = ts.get_played_title() {
+                if *ps.lock().unwrap() != song {
+                    *ps.lock().unwrap() = song.clone();
+                    s.send(GstreamerMessage::PlayedSongChanged(song)).unwrap();
+                }
+            }
+            Continue(true)
+        });
+
+        // listen for new playback pipeline / bus messages
+        let p = paused.clone();
+        let pp = playback_pipeline.clone();
+        let bus = playback_pipeline.get_bus().expect("Unable to get playback pipeline bus");
+        let s = sender.clone();
+        gtk::timeout_add(250, move || {
+            while bus.have_pending() {
+                bus.pop().map(|message| {
+                    Self::parse_playback_bus_message(&message, &pp, s.clone(), p.clone());
                 });
             }
             Continue(true)
         });
 
+        // Update timeshift position
+        let ts = timeshift.clone();
+        let s = sender.clone();
+        gtk::timeout_add(1000, move || {
+            if let Some((delay, buffered)) = ts.get_position() {
+                s.send(GstreamerMessage::TimeshiftPositionChanged(delay, buffered)).unwrap();
+            }
+            Continue(true)
+        });
+
         let recorderbin = Arc::new(Mutex::new(None));
 
         let pipeline = Self {
             pipeline,
             uridecodebin,
+            stream_tee,
+            decode_queue,
+            decodebin,
             audioconvert,
             tee,
             audio_queue,
+            timeshift_convert,
+            timeshift_resample,
+            timeshift_sink,
+            playback_pipeline,
+            timeshift_src,
+            normalization,
+            loudness_meter,
+            playback_convert,
+            effects: None,
             volume,
-            autoaudiosink,
+            volume_level: 1.0,
+            fade: 1.0,
+            audiosink,
             file_queue,
+            stream_queue,
             recorderbin,
+            finishing_recorderbins: Vec::new(),
             file_srcpad,
+            stream_srcpad,
             file_blockprobe_id: None,
-            current_title,
+            file_preroll,
+            stream_preroll,
+            passthrough,
+            timeshift,
+            paused,
+            station: None,
+            current_song,
+            played_song,
+            metadata_parser,
             sender,
         };
 
@@ -144,33 +390,283 @@ impl GstreamerBackend {
     }
 
     pub fn set_state(&mut self, state: gstreamer::State) {
-        if state == gstreamer::State::Null {
-            self.sender.send(GstreamerMessage::PlaybackStateChanged(PlaybackState::Stopped)).unwrap();
+        match state {
+            State::Playing => {
+                *self.paused.lock().unwrap() = false;
+
+                // Start buffering the live stream, if we're not already doing it (resume)
+                if !self.timeshift.is_running() {
+                    self.timeshift.start();
+                    let _ = self.pipeline.set_state(State::Playing);
+                }
+                let _ = self.playback_pipeline.set_state(State::Playing);
+            }
+            State::Paused => {
+                // Only pause the playback, the live stream keeps getting buffered
+                if self.timeshift.is_running() {
+                    *self.paused.lock().unwrap() = true;
+                    let _ = self.playback_pipeline.set_state(State::Paused);
+                    self.sender.send(GstreamerMessage::PlaybackStateChanged(PlaybackState::Paused)).unwrap();
+                }
+            }
+            _ => {
+                *self.paused.lock().unwrap() = false;
+                self.sender.send(GstreamerMessage::PlaybackStateChanged(PlaybackState::Stopped)).unwrap();
+
+                // The song has to be announced again when we tune in, so the recording can start
+                *self.current_song.lock().unwrap() = SongMetadata::default();
+                *self.played_song.lock().unwrap() = SongMetadata::default();
+
+                let _ = self.playback_pipeline.set_state(state);
+                let _ = self.pipeline.set_state(state);
+                self.timeshift.stop();
+            }
         }
+    }
+
+    pub fn set_volume(&mut self, volume: f64) {
+        self.volume_level = volume;
+        self.volume.set_property("volume", &(self.volume_level * self.fade)).unwrap();
+    }
+
+    /// Factor between 0.0 and 1.0, which gets applied on top of the volume (sleep timer).
+    pub fn set_fade(&mut self, fade: f64) {
+        self.fade = fade;
+        self.volume.set_property("volume", &(self.volume_level * self.fade)).unwrap();
+    }
 
-        let _ = self.pipeline.set_state(state);
+    pub fn set_mute(&self, mute: bool) {
+        self.volume.set_property("mute", &mute).unwrap();
+    }
+
+    /// Gain of the loudness normalization in dB.
+    pub fn set_normalization_gain(&self, gain: f64) {
+        self.normalization.set_property("volume", &10f64.powf(gain / 20.0)).unwrap();
+    }
+
+    pub fn get_loudness(&self) -> Option<f64> {
+        self.loudness_meter.lock().unwrap().get_loudness()
+    }
+
+    pub fn reset_loudness(&self) {
+        self.loudness_meter.lock().unwrap().reset();
+    }
+
+    /// Applies the effect settings. The effects bin only gets rebuilt if stages get enabled / disabled.
+    pub fn set_effects(&mut self, effects: &Effects) {
+        // Only the equalizer bands have changed, we can apply them directly
+        if let Some(effects_bin) = self.effects.as_ref() {
+            if !effects.is_bypassed() && effects_bin.matches(effects) {
+                effects_bin.set_bands(&effects.bands);
+                return;
+            }
+        }
+        if effects.is_bypassed() && self.effects.is_none() {
+            return;
+        }
+
+        let old_bin = self.effects.take().map(|effects_bin| effects_bin.bin);
+        // Without the effect elements we keep playing as if the effects were disabled
+        self.effects = if effects.is_bypassed() { None } else { EffectsBin::new(effects) };
+        if old_bin.is_none() && self.effects.is_none() {
+            return;
+        }
+        let new_bin = self.effects.as_ref().map(|effects_bin| effects_bin.bin.clone());
+
+        let pipeline = self.playback_pipeline.clone();
+        let convert = self.playback_convert.clone();
+        let volume = self.volume.clone();
+
+        // Wait until no data is flowing, before we relink the elements
+        let convert_srcpad = self.playback_convert.get_static_pad("src").unwrap();
+        convert_srcpad.add_probe(gstreamer::PadProbeType::IDLE, move |_, _| {
+            match old_bin.as_ref() {
+                Some(old_bin) => {
+                    convert.unlink(old_bin);
+                    old_bin.unlink(&volume);
+                    let _ = old_bin.set_state(State::Null);
+                    let _ = pipeline.remove(old_bin);
+                }
+                None => convert.unlink(&volume),
+            }
+
+            match new_bin.as_ref() {
+                Some(new_bin) => {
+                    pipeline.add(new_bin).unwrap();
+                    if Element::link_many(&[&convert, new_bin.upcast_ref(), &volume]).is_err() {
+                        warn!("Could not link effects bin");
+                    }
+                    let _ = new_bin.sync_state_with_parent();
+                }
+                None => {
+                    let _ = convert.link(&volume);
+                }
+            }
+            gstreamer::PadProbeReturn::Remove
+        });
+    }
+
+    /// Replaces the audio sink, without interrupting the playback.
+    pub fn set_audio_sink(&mut self, audiosink: Element) {
+        let old_sink = self.audiosink.clone();
+        let new_sink = audiosink.clone();
+        let pipeline = self.playback_pipeline.clone();
+        let volume = self.volume.clone();
+
+        // Wait until no data is flowing between volume and sink. If the pipeline is not running,
+        // the probe gets called immediately.
+        let volume_srcpad = self.volume.get_static_pad("src").unwrap();
+        volume_srcpad.add_probe(gstreamer::PadProbeType::IDLE, move |_, _| {
+            volume.unlink(&old_sink);
+            let _ = old_sink.set_state(State::Null);
+            let _ = pipeline.remove(&old_sink);
+
+            pipeline.add(&new_sink).unwrap();
+            if volume.link(&new_sink).is_err() {
+                warn!("Could not link new audio sink");
+            }
+            let _ = new_sink.sync_state_with_parent();
+            gstreamer::PadProbeReturn::Remove
+        });
+
+        self.audiosink = audiosink;
+    }
+
+    pub fn set_station(&mut self, station: Station) {
+        self.metadata_parser.set_station(&station);
+        self.station = Some(station);
+    }
+
+    pub fn set_timeshift_delay(&self, delay: Duration) {
+        self.timeshift.set_delay(delay);
     }
 
     pub fn new_source_uri(&mut self, source: &str) {
+        self.set_source_uri(source);
+
+        debug!("Start pipeline...");
+        self.set_state(State::Playing);
+    }
+
+    /// Sets the source uri, without starting the pipeline.
+    pub fn set_source_uri(&mut self, source: &str) {
         debug!("Stop pipeline...");
-        let _ = self.pipeline.set_state(State::Null);
+        self.set_state(State::Null);
 
         debug!("Set new source uri...");
         self.uridecodebin.set_property("uri", &source).unwrap();
+    }
 
-        debug!("Start pipeline...");
-        let _ = self.pipeline.set_state(State::Playing);
+    pub fn get_source_uri(&self) -> Option<String> {
+        self.uridecodebin.get_property("uri").ok().and_then(|value| value.get::<String>())
+    }
+
+    /// Replaces the uridecodebin, without interrupting the rest of the pipeline (timeshift, recording).
+    pub fn reconnect(&mut self, source: &str) {
+        debug!("Replace uridecodebin...");
+        let _ = self.uridecodebin.set_state(State::Null);
+        let _ = self.pipeline.remove(&self.uridecodebin);
+
+        self.uridecodebin = Self::create_uridecodebin(&self.audioconvert, Some(&self.stream_tee), self.sender.clone(), true);
+        self.uridecodebin.set_property("uri", &source).unwrap();
+        self.pipeline.add(&self.uridecodebin).unwrap();
+        let _ = self.uridecodebin.sync_state_with_parent();
+    }
+
+    pub fn is_paused(&self) -> bool {
+        *self.paused.lock().unwrap()
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.timeshift.is_running() && !self.is_paused()
+    }
+
+    /// A live stream should never end, if it does anyway, the connection got lost.
+    /// The EOS must not reach the recorderbin, otherwise the current recording would get finalized.
+    pub fn add_eos_probe(pad: &Pad, sender: Sender<GstreamerMessage>) {
+        let s = Mutex::new(sender);
+        pad.add_probe(gstreamer::PadProbeType::EVENT_DOWNSTREAM, move |_, info| {
+            if let Some(gstreamer::PadProbeData::Event(ref event)) = info.data {
+                if event.get_type() == gstreamer::EventType::Eos {
+                    s.lock().unwrap().send(GstreamerMessage::StreamError("Stream ended".to_string())).unwrap();
+                    return gstreamer::PadProbeReturn::Drop;
+                }
+            }
+            gstreamer::PadProbeReturn::Ok
+        });
     }
 
-    pub fn start_recording(&mut self, path: PathBuf) {
+    /// Raw audio gets linked to `audioconvert`. If `stream_sink` is set, compressed streams which can be
+    /// recorded without re-encoding (STREAM_CAPS) get linked to it, without decoding them.
+    pub fn create_uridecodebin(audioconvert: &Element, stream_sink: Option<&Element>, sender: Sender<GstreamerMessage>, continue_timestamps: bool) -> Element {
+        let uridecodebin = ElementFactory::make("uridecodebin", "uridecodebin").unwrap();
+        if stream_sink.is_some() {
+            uridecodebin.set_property("caps", &Caps::from_string(STREAM_CAPS).unwrap()).unwrap();
+        }
+
+        // dynamically link uridecodebin element with audioconvert / stream_sink element
+        let convert = audioconvert.clone();
+        let stream_sink = stream_sink.cloned();
+        let sender = Mutex::new(sender);
+        uridecodebin.connect_pad_added(move |uridecodebin, src_pad| {
+            let new_pad_caps = src_pad.get_current_caps().expect("Failed to get caps of new pad.");
+            let new_pad_struct = new_pad_caps.get_structure(0).expect("Failed to get first structure of caps.");
+            let new_pad_type = new_pad_struct.get_name();
+
+            let sink = if new_pad_type.starts_with("audio/x-raw") {
+                &convert
+            } else {
+                match stream_sink.as_ref() {
+                    Some(stream_sink) if new_pad_type.starts_with("audio/") => stream_sink,
+                    _ => return,
+                }
+            };
+
+            let sink_pad = sink.get_static_pad("sink").expect("Failed to get static sink pad");
+            if sink_pad.is_linked() {
+                return; // We are already linked. Ignoring.
+            }
+
+            // The new source starts again at timestamp 0, so we continue where the old one stopped.
+            if continue_timestamps {
+                let pipeline = uridecodebin.get_parent().unwrap().downcast::<Element>().unwrap();
+                if let Some(clock) = pipeline.get_clock() {
+                    let running_time = clock.get_time() - pipeline.get_base_time();
+                    src_pad.set_offset(running_time.nseconds().unwrap_or(0) as i64);
+                }
+            }
+
+            let _ = src_pad.link(&sink_pad);
+            sender.lock().unwrap().send(GstreamerMessage::StreamConnected).unwrap();
+        });
+
+        uridecodebin
+    }
+
+    /// Starts recording `song` into `path`, the extension gets added depending on the recording format.
+    pub fn start_recording(&mut self, song: SongMetadata, path: PathBuf) -> Result<(), RecordingError> {
         debug!("Start recording to \"{:?}\"...", path);
 
+        // Record the original stream, if the user wants it and the station has a stream we can write as it is.
+        // The block probe of the previous recording is still on the old srcpad.
+        let old_srcpad = self.get_recording_srcpad().clone();
+        let stream_caps = if SettingsManager::get_boolean(Key::RecordingPassthrough) { self.get_stream_caps() } else { None };
+        *self.passthrough.lock().unwrap() = stream_caps.is_some();
+        let srcpad = self.get_recording_srcpad().clone();
+        self.get_preroll().update();
+        debug!("Passthrough: {:?}", stream_caps);
+
         // We need to set an offset, otherwise the length of the recorded song would be wrong.
-        // Get current clock time and calculate offset
-        let clock = self.pipeline.get_clock().expect("Could not get gstreamer pipeline clock");
-        debug!("Clock time: {}", clock.get_time());
-        let offset = -(clock.get_time().nseconds().unwrap() as i64);
-        self.file_srcpad.set_offset(offset);
+        // A session can start while the pipeline is still prerolling (StreamConnected), there's no clock yet,
+        // but nothing has been played either. Same as in the HeadlessRecorder.
+        match self.pipeline.get_clock() {
+            Some(clock) => {
+                let running_time = clock.get_time() - self.pipeline.get_base_time();
+                debug!("Running time: {}", running_time);
+                srcpad.set_offset(-(running_time.nseconds().unwrap_or(0) as i64));
+            }
+            None => srcpad.set_offset(0),
+        }
 
         debug!("Destroy old recorderbin...");
         if self.recorderbin.lock().unwrap().is_some() {
@@ -180,17 +676,37 @@ impl GstreamerBackend {
         }
 
         debug!("Create new recorderbin");
-        let recorderbin = RecorderBin::new(self.get_current_song_title(), path, self.pipeline.clone(), &self.file_srcpad);
-        *self.recorderbin.lock().unwrap() = Some(recorderbin);
+        let result = RecorderBin::new(song, path, self.station.as_ref(), self.pipeline.clone(), &srcpad, stream_caps.as_ref());
+
+        // The queue still holds the data in front of the new song (pre-roll), which may belong to a discarded recording
+        let preroll = self.get_preroll().clone();
+        srcpad.add_probe(gstreamer::PadProbeType::BUFFER, move |_, info| {
+            if preroll.is_before_song_start(info) {
+                gstreamer::PadProbeReturn::Drop
+            } else {
+                gstreamer::PadProbeReturn::Remove
+            }
+        });
 
         // Remove block probe id, if available
         debug!("Remove block probe...");
         match self.file_blockprobe_id.take() {
-            Some(id) => self.file_srcpad.remove_probe(id),
+            Some(id) => old_srcpad.remove_probe(id),
             None => (),
         }
 
-        debug!("Everything ok.");
+        match result {
+            Ok(recorderbin) => {
+                *self.recorderbin.lock().unwrap() = Some(recorderbin);
+                debug!("Everything ok.");
+                Ok(())
+            }
+            Err(err) => {
+                warn!("Could not start recording: {}", err);
+                self.drop_recording_data();
+                Err(err)
+            }
+        }
     }
 
     pub fn stop_recording(&mut self, save_song: bool) -> Option<Song> {
@@ -199,9 +715,15 @@ impl GstreamerBackend {
         if self.recorderbin.lock().unwrap().is_some() {
             let rbin = self.recorderbin.clone();
             if save_song {
+                let preroll = self.get_preroll().clone();
                 let file_id = self
-                    .file_srcpad
-                    .add_probe(gstreamer::PadProbeType::BLOCK_DOWNSTREAM, move |_, _| {
+                    .get_recording_srcpad()
+                    .add_probe(gstreamer::PadProbeType::BLOCK_DOWNSTREAM, move |_, info| {
+                        // The data in front of the new song still belongs to the old one (pre-roll)
+                        if preroll.is_before_song_start(info) {
+                            return gstreamer::PadProbeReturn::Pass;
+                        }
+
                         // Dataflow is blocked
                         debug!("Push EOS into recorderbin sinkpad...");
                         let sinkpad = rbin.lock().unwrap().clone().unwrap().gstbin.get_static_pad("sink").unwrap();
@@ -220,8 +742,12 @@ impl GstreamerBackend {
             } else {
                 debug!("Discard recorded data");
                 let recorderbin = self.recorderbin.lock().unwrap().take().unwrap();
-                fs::remove_file(&recorderbin.song_path).expect("Could not delete recorded data");
+                // The file has to be closed first, otherwise the filesink would keep writing into it
                 recorderbin.destroy();
+                self.drop_recording_data();
+                if let Err(err) = fs::remove_file(&recorderbin.song_path) {
+                    warn!("Could not delete recorded data {:?}: {}", recorderbin.song_path, err);
+                }
                 return None;
             }
         } else {
@@ -230,36 +756,129 @@ impl GstreamerBackend {
         }
     }
 
+    /// Saves the current recording right away, instead of waiting for the dataflow (e.g. before the pipeline gets stopped).
+    /// The file gets finalized in the background, `RecordingFinished` gets sent as soon as it's complete.
+    pub fn finish_recording(&mut self) {
+        let recorderbin = match self.recorderbin.lock().unwrap().take() {
+            Some(recorderbin) => recorderbin,
+            None => return,
+        };
+        debug!("Finish recording...");
+
+        self.drop_recording_data();
+        recorderbin.set_toc();
+        recorderbin.finish(self.get_recording_srcpad(), self.station.clone(), self.sender.clone());
+        self.finishing_recorderbins.push(recorderbin);
+    }
+
+    /// The file at `path` is complete (`RecordingFinished`), so its recorderbin isn't needed anymore.
+    pub fn release_finished_recording(&mut self, path: &Path) {
+        self.finishing_recorderbins.retain(|recorderbin| {
+            if recorderbin.song_path != path {
+                return true;
+            }
+            let _ = recorderbin.gstbin.set_state(State::Null);
+            false
+        });
+    }
+
+    /// Whether a finished session is still being written.
+    pub fn is_finishing_recording(&self) -> bool {
+        !self.finishing_recorderbins.is_empty()
+    }
+
+    /// Removes the saved recorderbin (`RecordingStopped`), if no new recording gets started.
+    pub fn release_recording(&mut self) {
+        if !self.is_saving_recording() {
+            return;
+        }
+
+        let recorderbin = self.recorderbin.lock().unwrap().take().unwrap();
+        recorderbin.destroy();
+        self.drop_recording_data();
+    }
+
+    pub fn add_recording_chapter(&self, song: &SongMetadata) {
+        let song_start = self.get_preroll().get_song_start(&song.stream_title);
+        if let Some(recorderbin) = self.recorderbin.lock().unwrap().as_ref() {
+            recorderbin.add_chapter(song, song_start);
+        }
+    }
+
+    // Nothing is linked to the recording srcpad anymore, so the data gets thrown away until the next recording starts.
+    // The probe gets removed like the block probe, see `start_recording`.
+    fn drop_recording_data(&mut self) {
+        let srcpad = self.get_recording_srcpad().clone();
+        let old_probe_id = self.file_blockprobe_id.take();
+        self.file_blockprobe_id = srcpad.add_probe(gstreamer::PadProbeType::BUFFER, |_, _| gstreamer::PadProbeReturn::Drop);
+        if let Some(id) = old_probe_id {
+            srcpad.remove_probe(id);
+        }
+    }
+
+    fn get_recording_srcpad(&self) -> &Pad {
+        if *self.passthrough.lock().unwrap() {
+            &self.stream_srcpad
+        } else {
+            &self.file_srcpad
+        }
+    }
+
+    fn get_preroll(&self) -> &Preroll {
+        if *self.passthrough.lock().unwrap() {
+            &self.stream_preroll
+        } else {
+            &self.file_preroll
+        }
+    }
+
+    /// Caps of the compressed stream, if the current station has one which can be recorded without re-encoding.
+    fn get_stream_caps(&self) -> Option<Caps> {
+        self.stream_tee.get_static_pad("sink").and_then(|pad| pad.get_current_caps())
+    }
+
     pub fn is_recording(&self) -> bool {
         self.recorderbin.lock().unwrap().is_some()
     }
 
-    pub fn get_current_song_title(&self) -> String {
-        self.current_title.lock().unwrap().clone()
+    /// The dataflow is blocked, and the current recording gets finalized.
+    pub fn is_saving_recording(&self) -> bool {
+        self.is_recording() && self.file_blockprobe_id.is_some()
     }
 
-    fn parse_bus_message(message: &gstreamer::Message, sender: Sender<GstreamerMessage>, current_title: Arc<Mutex<String>>) {
+    /// How long the current recording is running, `None` if we're not recording or already saving it.
+    pub fn get_recording_duration(&self) -> Option<Duration> {
+        if self.is_saving_recording() {
+            return None;
+        }
+        self.recorderbin.lock().unwrap().as_ref().map(|recorderbin| recorderbin.get_duration())
+    }
+
+    pub fn get_current_song(&self) -> SongMetadata {
+        self.current_song.lock().unwrap().clone()
+    }
+
+    /// `timeshift` delays `PlayedSongChanged` until the playback reaches the title, without it the title counts as played right away.
+    pub fn parse_bus_message(message: &gstreamer::Message, sender: Sender<GstreamerMessage>, current_song: Arc<Mutex<SongMetadata>>, parser: &MetadataParser, timeshift: Option<&Timeshift>) {
         match message.view() {
             gstreamer::MessageView::Tag(tag) => {
-                tag.get_tags().get::<gstreamer::tags::Title>().map(|t| {
-                    let new_title = t.get().unwrap().to_string();
-
+                let tags = tag.get_tags();
+                if let Some(t) = tags.get::<gstreamer::tags::Title>() {
                     // only send message if song title really have changed.
-                    if *current_title.lock().unwrap() != new_title {
-                        *current_title.lock().unwrap() = new_title.clone();
-                        sender.send(GstreamerMessage::SongTitleChanged(new_title)).unwrap();
+                    // Streams repeat their tags, e.g. with a changed artist tag, that's still the same song.
+                    let stream_title = t.get().unwrap().trim();
+                    if current_song.lock().unwrap().stream_title == stream_title {
+                        return;
                     }
-                });
-            }
-            gstreamer::MessageView::StateChanged(sc) => {
-                let playback_state = match sc.get_current() {
-                    gstreamer::State::Playing => PlaybackState::Playing,
-                    gstreamer::State::Paused => PlaybackState::Loading,
-                    gstreamer::State::Ready => PlaybackState::Loading,
-                    _ => PlaybackState::Stopped,
-                };
 
-                sender.send(GstreamerMessage::PlaybackStateChanged(playback_state)).unwrap();
+                    let artist = tags.get::<gstreamer::tags::Artist>();
+                    let new_song = parser.parse(stream_title, artist.as_ref().and_then(|artist| artist.get()));
+                    *current_song.lock().unwrap() = new_song.clone();
+                    sender.send(GstreamerMessage::SongTitleChanged(new_song.clone())).unwrap();
+                    if !timeshift.map_or(false, |timeshift| timeshift.add_title(new_song.clone())) {
+                        sender.send(GstreamerMessage::PlayedSongChanged(new_song)).unwrap();
+                    }
+                }
             }
             gstreamer::MessageView::Element(element) => {
                 let structure = element.get_structure().unwrap();
@@ -272,11 +891,57 @@ impl GstreamerBackend {
                     }
                 }
             }
+            gstreamer::MessageView::Error(err) => {
+                let msg = err.get_error().to_string();
+                warn!("Gstreamer Error: {:?}", msg);
+
+                // Errors of the source can get fixed by reconnecting, everything else is fatal.
+                let from_source = message.get_src().and_then(|src| src.get_path_string()).map_or(false, |path| path.contains("uridecodebin"));
+                if from_source {
+                    sender.send(GstreamerMessage::StreamError(msg)).unwrap();
+                } else {
+                    sender.send(GstreamerMessage::PlaybackStateChanged(PlaybackState::Failure(msg))).unwrap();
+                }
+            }
+            _ => (),
+        };
+    }
+
+    fn parse_playback_bus_message(message: &gstreamer::Message, pipeline: &Pipeline, sender: Sender<GstreamerMessage>, paused: Arc<Mutex<bool>>) {
+        match message.view() {
+            gstreamer::MessageView::StateChanged(sc) => {
+                // Elements can change their state on their own (e.g. a new audio sink)
+                if message.get_src().as_ref() != Some(pipeline.upcast_ref::<gstreamer::Object>()) {
+                    return;
+                }
+
+                let playback_state = match sc.get_current() {
+                    gstreamer::State::Playing => PlaybackState::Playing,
+                    gstreamer::State::Paused => {
+                        if *paused.lock().unwrap() {
+                            PlaybackState::Paused
+                        } else {
+                            PlaybackState::Loading
+                        }
+                    }
+                    gstreamer::State::Ready => PlaybackState::Loading,
+                    _ => PlaybackState::Stopped,
+                };
+
+                sender.send(GstreamerMessage::PlaybackStateChanged(playback_state)).unwrap();
+            }
             gstreamer::MessageView::Error(err) => {
                 let msg = err.get_error().to_string();
                 warn!("Gstreamer Error: {:?}", msg);
                 sender.send(GstreamerMessage::PlaybackStateChanged(PlaybackState::Failure(msg))).unwrap();
             }
+            gstreamer::MessageView::ClockLost(_) => {
+                // The audio sink which provided the clock got replaced, we have to select a new one
+                if pipeline.get_state(gstreamer::ClockTime::from_seconds(0)).1 == State::Playing {
+                    let _ = pipeline.set_state(State::Paused);
+                    let _ = pipeline.set_state(State::Playing);
+                }
+            }
             _ => (),
         };
     }
@@ -286,79 +951,249 @@ impl GstreamerBackend {
 //                                                                                                      //
 //  # RecorderBin                                                                                       //
 //                                                                                                      //
-//    --------------------------------------------------------------                                    //
-//   |                  -----------       --------      ----------  |                                   //
-//   | ( ghostpad ) -> | vorbisenc | ->  | oggmux | -> | filesink | |                                   //
-//   |                  -----------       --------      ----------  |                                   //
-//    --------------------------------------------------------------                                    //
+//    -------------------------------------------------------------------------------------------       //
+//   |                  --------------      ---------------      -------------      ----------  |       //
+//   | ( ghostpad ) -> | audioconvert | -> | audioresample | -> | encoder [1] | -> | filesink | |       //
+//   |                  --------------      ---------------      -------------      ----------  |       //
+//    -------------------------------------------------------------------------------------------       //
+//                                                                                                      //
+//  [1] The encoder of the selected RecordingFormat, and its parser / muxer if needed:                  //
+//      vorbisenc ! oggmux, opusenc ! oggmux, flacenc, lamemp3enc, AAC encoder ! aacparse ! mp4mux      //
+//                                                                                                      //
+//  The file extension of the song path is set to the one of the format.                                //
+//                                                                                                      //
+//  If the compressed stream gets recorded as it is (passthrough), the bin only contains the muxer the  //
+//  stream format needs (oggmux, mp4mux, or nothing for MP3 / ADTS) and the filesink.                   //
+//                                                                                                      //
+//  All elements which implement TagSetter get the tags of the song and the station. MP3 and ADTS files //
+//  get them from an additional ID3 muxer, Vorbis streams from vorbistag.                               //
 //                                                                                                      //
 /////////////////////////////////////////////////////////////////////////////////////////////////////////
 
 #[allow(dead_code)]
 #[derive(Clone)]
-struct RecorderBin {
+pub struct RecorderBin {
     pub gstbin: Bin,
     pipeline: Pipeline,
 
     ghostpad: GhostPad,
-    vorbisenc: Element,
-    oggmux: Element,
+    elements: Vec<Element>,
     filesink: Element,
 
-    song_title: String,
+    // None if the compressed stream gets recorded as it is
+    format: Option<RecordingFormat>,
+    song: SongMetadata,
+    station_name: Option<String>,
     pub song_path: PathBuf,
     song_timestamp: SystemTime,
+    // Timestamp (PTS) of the first buffer in the file
+    start_position: Arc<Mutex<Option<u64>>>,
+    chapters: Arc<Mutex<Vec<Chapter>>>,
 }
 
 impl RecorderBin {
-    pub fn new(song_title: String, song_path: PathBuf, pipeline: Pipeline, srcpad: &Pad) -> Self {
-        // Create elements
-        let vorbisenc = ElementFactory::make("vorbisenc", "vorbisenc").unwrap();
-        let oggmux = ElementFactory::make("oggmux", "oggmux").unwrap();
-        let filesink = ElementFactory::make("filesink", "filesink").unwrap();
-        filesink.set_property("location", &song_path.to_str().unwrap()).unwrap();
+    /// Records `stream_caps` as they are, if they are set. Otherwise the raw audio gets encoded with the selected format.
+    pub fn new(song: SongMetadata, mut song_path: PathBuf, station: Option<&Station>, pipeline: Pipeline, srcpad: &Pad, stream_caps: Option<&Caps>) -> Result<Self, RecordingError> {
+        // Create elements, this fails if the plugin of the format is missing
+        let (elements, format) = match stream_caps {
+            Some(caps) => {
+                let (elements, extension) = recording_format::create_stream_elements(caps)?;
+                FilenameTemplate::add_extension(&mut song_path, extension);
+                (elements, None)
+            }
+            None => {
+                let format = RecordingFormat::load();
+                FilenameTemplate::add_extension(&mut song_path, format.get_extension());
+
+                let convert = ElementFactory::make("audioconvert", "recorder_convert").ok_or_else(|| RecordingError::MissingElement("audioconvert".to_string()))?;
+                let resample = ElementFactory::make("audioresample", "recorder_resample").ok_or_else(|| RecordingError::MissingElement("audioresample".to_string()))?;
+                let mut elements = vec![convert, resample];
+                elements.extend(format.create_elements()?);
+                (elements, Some(format))
+            }
+        };
+        let filesink = ElementFactory::make("filesink", "filesink").ok_or_else(|| RecordingError::MissingElement("filesink".to_string()))?;
+        filesink.set_property("location", &song_path.to_string_lossy().to_string()).unwrap();
+
+        // Every element which can write tags gets ours, the tags of the stream itself get ignored
+        let tags = Self::create_tags(&song, station);
+        for element in &elements {
+            if let Some(tag_setter) = element.dynamic_cast_ref::<TagSetter>() {
+                tag_setter.merge_tags(&tags, TagMergeMode::Replace);
+                tag_setter.set_tag_merge_mode(TagMergeMode::ReplaceAll);
+            }
+        }
 
         // Create bin itself
         let bin = Bin::new("bin");
         bin.set_property("message-forward", &true).unwrap();
 
         // Add elements to bin and link them
-        bin.add(&vorbisenc).unwrap();
-        bin.add(&oggmux).unwrap();
-        bin.add(&filesink).unwrap();
-        Element::link_many(&[&vorbisenc, &oggmux, &filesink]).unwrap();
-
-        // Add bin to pipeline
-        pipeline.add(&bin).expect("Could not add recorderbin to pipeline");
+        let mut chain: Vec<&Element> = elements.iter().collect();
+        chain.push(&filesink);
+        bin.add_many(&chain).map_err(|err| RecordingError::Pipeline(err.to_string()))?;
+        Element::link_many(&chain).map_err(|err| RecordingError::Pipeline(err.to_string()))?;
 
-        // Link file_srcpad with vorbisenc sinkpad using a ghostpad
-        let vorbisenc_sinkpad = vorbisenc.get_static_pad("sink").unwrap();
-        let ghostpad = gstreamer::GhostPad::new("sink", &vorbisenc_sinkpad).unwrap();
+        // Link srcpad with the first element of the bin using a ghostpad
+        let first_sinkpad = chain[0].get_static_pad("sink").unwrap();
+        let ghostpad = gstreamer::GhostPad::new("sink", &first_sinkpad).unwrap();
         bin.add_pad(&ghostpad).unwrap();
+
+        // Add bin to pipeline
+        pipeline.add(&bin).map_err(|err| RecordingError::Pipeline(err.to_string()))?;
         bin.sync_state_with_parent().unwrap();
-        srcpad.link(&ghostpad).expect("Queue src pad cannot linked to vorbisenc sinkpad");
+        if let Err(err) = srcpad.link(&ghostpad) {
+            let _ = pipeline.remove(&bin);
+            let _ = bin.set_state(State::Null);
+            return Err(RecordingError::Pipeline(format!("{:?}", err)));
+        }
 
         // Set song timestamp so we can check the duration later
         let song_timestamp = SystemTime::now();
 
-        Self {
+        // Chapters are positioned by the timestamps of the stream, the file starts with the first buffer
+        let start_position = Arc::new(Mutex::new(None));
+        let sp = start_position.clone();
+        ghostpad.add_probe(gstreamer::PadProbeType::BUFFER, move |_, info| {
+            if let Some(gstreamer::PadProbeData::Buffer(ref buffer)) = info.data {
+                *sp.lock().unwrap() = buffer.get_pts().nseconds();
+            }
+            gstreamer::PadProbeReturn::Remove
+        });
+
+        Ok(Self {
             gstbin: bin,
             pipeline,
             ghostpad,
-            vorbisenc,
-            oggmux,
+            elements,
             filesink,
-            song_title,
+            format,
+            song,
+            station_name: station.map(|station| station.name.clone()),
             song_path,
             song_timestamp,
+            start_position,
+            chapters: Arc::new(Mutex::new(Vec::new())),
+        })
+    }
+
+    // Title and artist of the song, and everything we know about the station
+    fn create_tags(song: &SongMetadata, station: Option<&Station>) -> TagList {
+        let mut tags = TagList::new();
+        {
+            let tags = tags.get_mut().unwrap();
+            let mode = TagMergeMode::Append;
+
+            tags.add::<gstreamer::tags::Title>(&song.title.as_str(), mode);
+            if let Some(artist) = song.artist.as_ref() {
+                tags.add::<gstreamer::tags::Artist>(&artist.as_str(), mode);
+            }
+            tags.add::<gstreamer::tags::DateTime>(&gstreamer::DateTime::new_now_local_time(), mode);
+
+            if let Some(station) = station {
+                tags.add::<gstreamer::tags::Album>(&station.name.as_str(), mode);
+                tags.add::<gstreamer::tags::Organization>(&station.name.as_str(), mode);
+                for genre in station.tags.split(',').map(|genre| genre.trim()).filter(|genre| !genre.is_empty()) {
+                    tags.add::<gstreamer::tags::Genre>(&genre, mode);
+                }
+                if station.homepage != "" {
+                    tags.add::<gstreamer::tags::Comment>(&station.homepage.as_str(), mode);
+                }
+            }
+        }
+        tags
+    }
+
+    pub fn get_duration(&self) -> Duration {
+        SystemTime::now().duration_since(self.song_timestamp).unwrap_or_default()
+    }
+
+    /// A new song starts at `song_start` (timestamp of the stream), or now if we don't know it. Only used by continuous recordings.
+    pub fn add_chapter(&self, song: &SongMetadata, song_start: Option<u64>) {
+        let start = match (song_start, *self.start_position.lock().unwrap()) {
+            (Some(song_start), Some(start_position)) => Duration::from_nanos(song_start.saturating_sub(start_position)),
+            _ => self.get_duration(),
+        };
+        let chapter = Chapter {
+            start,
+            title: song.title.clone(),
+            artist: song.artist.clone(),
+        };
+        self.chapters.lock().unwrap().push(chapter);
+
+        // Some elements write their header when the data starts (flacenc, id3v2mux), others at the EOS
+        self.set_toc();
+    }
+
+    /// Writes the chapters into the file, if the format can store them (e.g. FLAC). This has to happen before the EOS,
+    /// and formats which write their header at the start only get the chapters which are known by then.
+    /// The chapter files (`Song::write_chapter_files`) always have all of them.
+    pub fn set_toc(&self) {
+        let chapters = self.chapters.lock().unwrap();
+        if chapters.is_empty() {
+            return;
+        }
+
+        let duration = self.get_duration();
+        let mut edition = TocEntry::new(TocEntryType::Edition, "edition");
+        for (i, chapter) in chapters.iter().enumerate() {
+            let stop = chapters.get(i + 1).map_or(duration, |next| next.start);
+            let mut tags = TagList::new();
+            {
+                // Most players only show the title of a chapter
+                let tags = tags.get_mut().unwrap();
+                tags.add::<gstreamer::tags::Title>(&chapter.get_display_title().as_str(), TagMergeMode::Append);
+                if let Some(artist) = chapter.artist.as_ref() {
+                    tags.add::<gstreamer::tags::Artist>(&artist.as_str(), TagMergeMode::Append);
+                }
+            }
+
+            let mut entry = TocEntry::new(TocEntryType::Chapter, &format!("chapter{}", i + 1));
+            {
+                let entry = entry.get_mut().unwrap();
+                entry.set_start_stop_times(chapter.start.as_nanos() as i64, stop.as_nanos() as i64);
+                entry.set_tags(tags);
+            }
+            edition.get_mut().unwrap().append_sub_entry(entry);
+        }
+
+        let mut toc = Toc::new(TocScope::Global);
+        toc.get_mut().unwrap().append_entry(edition);
+        for element in &self.elements {
+            if let Some(toc_setter) = element.dynamic_cast_ref::<TocSetter>() {
+                toc_setter.set_toc(Some(&toc));
+            }
         }
     }
 
     pub fn stop(&self) -> Song {
-        let now = SystemTime::now();
-        let duration = now.duration_since(self.song_timestamp).unwrap();
+        let mut song = Song::new(&self.song, self.song_path.clone(), self.get_duration());
+        song.station = self.station_name.clone();
+        song.timestamp = self.song_timestamp.duration_since(UNIX_EPOCH).map(|duration| duration.as_secs() as i64).unwrap_or(0);
+        song.chapters = self.chapters.lock().unwrap().clone();
+        song
+    }
+
+    /// Removes the bin from the pipeline, and finalizes the file. The muxers write their index (e.g. the moov atom of MP4)
+    /// when they receive the EOS, possibly in a thread of their own, so the file is complete once the EOS reaches the filesink.
+    pub fn finish(&self, srcpad: &Pad, station: Option<Station>, sender: Sender<GstreamerMessage>) {
+        let _ = srcpad.unlink(&self.ghostpad);
+        let _ = self.pipeline.remove(&self.gstbin);
+
+        let song = self.stop();
+        let sender = Mutex::new(sender);
+        self.filesink.get_static_pad("sink").unwrap().add_probe(gstreamer::PadProbeType::EVENT_DOWNSTREAM, move |_, info| {
+            if let Some(gstreamer::PadProbeData::Event(ref event)) = info.data {
+                if event.get_type() == gstreamer::EventType::Eos {
+                    debug!("Finished recording received EOS...");
+                    sender.lock().unwrap().send(GstreamerMessage::RecordingFinished(song.clone(), station.clone())).unwrap();
+                    return gstreamer::PadProbeReturn::Remove;
+                }
+            }
+            gstreamer::PadProbeReturn::Ok
+        });
 
-        Song::new(&self.song_title, self.song_path.clone(), duration)
+        self.ghostpad.send_event(gstreamer::Event::new_eos().build());
     }
 
     pub fn destroy(&self) {
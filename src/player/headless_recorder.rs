@@ -0,0 +1,459 @@
+use chrono::Local;
+use glib::Sender;
+use gstreamer::prelude::*;
+use gstreamer::{Caps, Element, ElementFactory, Pad, PadProbeId, Pipeline, State};
+use rustio::Station;
+
+use std::cell::{Cell, RefCell};
+use std::fs;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crate::app::Action;
+use crate::config;
+use crate::filename_template::FilenameTemplate;
+use crate::metadata::{MetadataParser, SongMetadata};
+use crate::player::gstreamer_backend::{GstreamerBackend, GstreamerMessage, RecorderBin};
+use crate::player::playback_state::PlaybackState;
+use crate::player::preroll::Preroll;
+use crate::player::recording_mode::RecordingMode;
+use crate::player::resolver::Resolver;
+use crate::settings::{Key, SettingsManager};
+use crate::title_filter::TitleFilter;
+
+const RECONNECT_DELAY_SECS: u32 = 10;
+// If no data arrives anymore, the EOS never reaches the recorderbin. Don't wait for it forever.
+const STOP_TIMEOUT_SECS: u32 = 5;
+
+//////////////////////////////////////////////////////////////////////////////////////////////////////////
+//                                                                                                      //
+//  # HeadlessRecorder                                                                                  //
+//                                                                                                      //
+//    --------------      --------------      -------      -------------                                //
+//   | uridecodebin | -> | audioconvert | -> | queue | -> | recorderbin |                               //
+//    --------------      --------------      -------      -------------                                //
+//                                                                                                      //
+//  Records a station without playing it. This is a pipeline of its own, so it runs independently of    //
+//  the playback (GstreamerBackend), and the user can listen to another station in the meantime.        //
+//                                                                                                      //
+//  The messages of the pipeline are the same ones the GstreamerBackend sends (GstreamerMessage).       //
+//                                                                                                      //
+//  If the original stream gets recorded (recording-passthrough), streams which don't have to be        //
+//  re-encoded skip audioconvert, and go to a queue of their own:                                       //
+//    --------------      -------      -------------                                                    //
+//   | uridecodebin | -> | queue | -> | recorderbin |                                                   //
+//    --------------      -------      -------------                                                    //
+//  Nothing needs to be played, so they don't get decoded at all.                                       //
+//                                                                                                      //
+//////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Clone)]
+pub struct HeadlessRecorder {
+    station: Station,
+    mode: RecordingMode,
+    // Continuous: the file (without extension), per song: the folder of the songs
+    path: PathBuf,
+
+    pipeline: Pipeline,
+    uridecodebin: Rc<RefCell<Option<Element>>>,
+    audioconvert: Element,
+    stream_queue: Option<Element>,
+    file_srcpad: Pad,
+    file_preroll: Preroll,
+    stream_preroll: Option<Preroll>,
+    recorderbin: Arc<Mutex<Option<RecorderBin>>>,
+    // Blocks the dataflow while the file gets finalized, see `stop_recording`
+    file_blockprobe_id: Rc<RefCell<Option<(Pad, PadProbeId)>>>,
+    // Throw away the data of the srcpads which aren't recorded, see `drop_recording_data`
+    dropprobe_ids: Rc<RefCell<Vec<(Pad, PadProbeId)>>>,
+
+    resolver: Resolver,
+    current_song: Arc<Mutex<SongMetadata>>,
+    // Number of saved files
+    saved: Rc<Cell<u32>>,
+    stopping: Rc<Cell<bool>>,
+    finished: Rc<Cell<bool>>,
+
+    gst_sender: Sender<GstreamerMessage>,
+    sender: Sender<Action>,
+}
+
+impl HeadlessRecorder {
+    pub fn new(station: Station, mode: RecordingMode, sender: Sender<Action>) -> Self {
+        let pipeline = Pipeline::new("headless_pipeline");
+        let audioconvert = ElementFactory::make("audioconvert", "audioconvert").unwrap();
+        let file_queue = ElementFactory::make("queue", "file_queue").unwrap();
+        let file_srcpad = file_queue.get_static_pad("src").unwrap();
+        let file_preroll = Preroll::new(&file_queue);
+
+        pipeline.add_many(&[&audioconvert, &file_queue]).unwrap();
+        Element::link_many(&[&audioconvert, &file_queue]).unwrap();
+
+        let (gst_sender, gst_receiver) = glib::MainContext::channel(glib::PRIORITY_DEFAULT);
+
+        // Same as in the GstreamerBackend: the end of a live stream means the connection got lost
+        GstreamerBackend::add_eos_probe(&audioconvert.get_static_pad("sink").unwrap(), gst_sender.clone());
+
+        let stream_queue = if SettingsManager::get_boolean(Key::RecordingPassthrough) {
+            let stream_queue = ElementFactory::make("queue", "stream_queue").unwrap();
+            pipeline.add(&stream_queue).unwrap();
+            GstreamerBackend::add_eos_probe(&stream_queue.get_static_pad("sink").unwrap(), gst_sender.clone());
+            Some(stream_queue)
+        } else {
+            None
+        };
+
+        let stream_preroll = stream_queue.as_ref().map(|stream_queue| Preroll::new(stream_queue));
+
+        let name = format!("{} {}", station.name, Local::now().format("%Y-%m-%d %H-%M"));
+        let mut path = Self::get_recording_dir();
+        path.push(FilenameTemplate::sanitize(&name));
+
+        let recorder = Self {
+            station,
+            mode,
+            path,
+            pipeline,
+            uridecodebin: Rc::new(RefCell::new(None)),
+            audioconvert,
+            stream_queue,
+            file_srcpad,
+            file_preroll,
+            stream_preroll,
+            recorderbin: Arc::new(Mutex::new(None)),
+            file_blockprobe_id: Rc::new(RefCell::new(None)),
+            dropprobe_ids: Rc::new(RefCell::new(Vec::new())),
+            resolver: Resolver::new(),
+            current_song: Arc::new(Mutex::new(SongMetadata::default())),
+            saved: Rc::new(Cell::new(0)),
+            stopping: Rc::new(Cell::new(false)),
+            finished: Rc::new(Cell::new(false)),
+            gst_sender,
+            sender,
+        };
+
+        // Nothing gets recorded until the stream is connected, or the first title arrives
+        recorder.drop_recording_data();
+
+        // Both sources stop as soon as the recording is finished, and drop their reference to the recorder
+        let r = recorder.clone();
+        gst_receiver.attach(None, move |message| {
+            r.process_message(message);
+            glib::Continue(!r.finished.get())
+        });
+
+        let bus = recorder.pipeline.get_bus().expect("Unable to get headless pipeline bus");
+        let finished = recorder.finished.clone();
+        let current_song = recorder.current_song.clone();
+        let metadata_parser = MetadataParser::new();
+        metadata_parser.set_station(&recorder.station);
+        let s = recorder.gst_sender.clone();
+        gtk::timeout_add(250, move || {
+            while let Some(message) = bus.pop() {
+                GstreamerBackend::parse_bus_message(&message, s.clone(), current_song.clone(), &metadata_parser, None);
+            }
+            glib::Continue(!finished.get())
+        });
+
+        recorder
+    }
+
+    /// Scheduled recordings get saved to the music folder of the user.
+    pub fn get_recording_dir() -> PathBuf {
+        let mut path = FilenameTemplate::get_music_dir();
+        path.push(config::NAME);
+        path
+    }
+
+    pub fn start(&self) {
+        info!("Start headless recording of \"{}\" ({:?})", self.station.name, self.mode);
+
+        let folder = match self.mode {
+            RecordingMode::PerSong => self.path.clone(),
+            _ => Self::get_recording_dir(),
+        };
+        if let Err(error) = fs::create_dir_all(&folder) {
+            let message = format!("Could not record \"{}\": {}", self.station.name, error.to_string());
+            self.sender.send(Action::ViewShowNotification(message)).unwrap();
+            self.finish();
+            return;
+        }
+
+        let message = format!("Scheduled recording of \"{}\" started.", self.station.name);
+        self.sender.send(Action::ViewShowNotification(message)).unwrap();
+        self.connect();
+    }
+
+    /// Saves the current file, and stops the pipeline afterwards.
+    pub fn stop(&self) {
+        if self.stopping.get() || self.finished.get() {
+            return;
+        }
+
+        debug!("Stop headless recording of \"{}\"", self.station.name);
+        self.stopping.set(true);
+        if self.recorderbin.lock().unwrap().is_some() {
+            self.stop_recording();
+
+            let recorder = self.clone();
+            gtk::timeout_add_seconds(STOP_TIMEOUT_SECS, move || {
+                if !recorder.finished.get() {
+                    warn!("Recorderbin didn't receive EOS, stop headless recording anyway");
+                    recorder.finish();
+                }
+                glib::Continue(false)
+            });
+        } else {
+            self.finish();
+        }
+    }
+
+    /// The pipeline is stopped, and the last file is saved.
+    pub fn is_finished(&self) -> bool {
+        self.finished.get()
+    }
+
+    fn connect(&self) {
+        let resolver = self.resolver.clone();
+        let station = self.station.clone();
+        let sender = self.gst_sender.clone();
+        thread::spawn(move || match resolver.resolve(station) {
+            Some(station_url) => sender.send(GstreamerMessage::StreamResolved(station_url)).unwrap(),
+            None => {
+                let message = "Could not find a playable stream for this station".to_string();
+                sender.send(GstreamerMessage::StreamError(message)).unwrap();
+            }
+        });
+    }
+
+    fn process_message(&self, message: GstreamerMessage) {
+        if self.finished.get() {
+            return;
+        }
+
+        match message {
+            GstreamerMessage::StreamResolved(url) => {
+                if self.stopping.get() {
+                    return;
+                }
+
+                // A reconnect replaces the uridecodebin, the recording continues
+                let old = self.uridecodebin.borrow_mut().take();
+                if let Some(old) = old.as_ref() {
+                    let _ = old.set_state(State::Null);
+                    let _ = self.pipeline.remove(old);
+                }
+
+                let uridecodebin = GstreamerBackend::create_uridecodebin(&self.audioconvert, self.stream_queue.as_ref(), self.gst_sender.clone(), old.is_some());
+                uridecodebin.set_property("uri", &url).unwrap();
+                self.pipeline.add(&uridecodebin).unwrap();
+                if old.is_some() {
+                    let _ = uridecodebin.sync_state_with_parent();
+                } else {
+                    let _ = self.pipeline.set_state(State::Playing);
+                }
+                *self.uridecodebin.borrow_mut() = Some(uridecodebin);
+            }
+            GstreamerMessage::StreamConnected => {
+                if self.mode == RecordingMode::Continuous && self.recorderbin.lock().unwrap().is_none() && !self.stopping.get() {
+                    // One file for everything, so the song is the station itself
+                    let song = SongMetadata {
+                        stream_title: self.station.name.clone(),
+                        title: self.station.name.clone(),
+                        artist: None,
+                    };
+                    self.start_recording(song, self.path.clone());
+                }
+            }
+            GstreamerMessage::SongTitleChanged(song) => {
+                // Ads and jingles end the current song, but they don't get recorded
+                let filtered = TitleFilter::is_filtered(&song, Some(&self.station));
+                if self.mode == RecordingMode::Continuous && !filtered {
+                    let song_start = self.get_preroll().get_song_start(&song.stream_title);
+                    if let Some(recorderbin) = self.recorderbin.lock().unwrap().as_ref() {
+                        recorderbin.add_chapter(&song, song_start);
+                    }
+                }
+                if self.mode != RecordingMode::PerSong || self.stopping.get() {
+                    return;
+                }
+
+                if self.recorderbin.lock().unwrap().is_some() {
+                    // The next song gets started, as soon as this one is saved (RecordingStopped)
+                    self.stop_recording();
+                } else if !filtered {
+                    let path = self.get_song_path(&song);
+                    self.start_recording(song, path);
+                }
+            }
+            GstreamerMessage::RecordingStopped => {
+                debug!("Headless recording saved");
+                self.saved.set(self.saved.get() + 1);
+                if let Some(recorderbin) = self.recorderbin.lock().unwrap().take() {
+                    recorderbin.destroy();
+
+                    // The songs of a show can be navigated in other players as well
+                    let recording = recorderbin.stop();
+                    if let Err(err) = recording.write_chapter_files(&recording.path) {
+                        warn!("Could not save chapters of {:?}: {}", recording.path, err);
+                    }
+                }
+
+                let song = self.current_song.lock().unwrap().clone();
+                if self.stopping.get() {
+                    self.finish();
+                } else if self.mode == RecordingMode::PerSong && !song.is_empty() && !TitleFilter::is_filtered(&song, Some(&self.station)) {
+                    let path = self.get_song_path(&song);
+                    self.start_recording(song, path);
+                } else {
+                    // The stream has to keep running until the next title
+                    self.drop_recording_data();
+                }
+            }
+            GstreamerMessage::StreamError(msg) => {
+                if self.stopping.get() {
+                    return;
+                }
+
+                // Keep the current file, the recording continues after the reconnect
+                warn!("Headless recording of \"{}\": {}", self.station.name, msg);
+                let recorder = self.clone();
+                gtk::timeout_add_seconds(RECONNECT_DELAY_SECS, move || {
+                    if !recorder.stopping.get() && !recorder.finished.get() {
+                        recorder.connect();
+                    }
+                    glib::Continue(false)
+                });
+            }
+            GstreamerMessage::PlaybackStateChanged(PlaybackState::Failure(msg)) => {
+                let message = format!("Could not record \"{}\": {}", self.station.name, msg);
+                self.sender.send(Action::ViewShowNotification(message)).unwrap();
+                self.finish();
+            }
+            _ => (),
+        }
+    }
+
+    fn get_song_path(&self, song: &SongMetadata) -> PathBuf {
+        // The number keeps the order of the songs, and two songs with the same title apart
+        let mut path = self.path.clone();
+        path.push(format!("{:02} {}", self.saved.get() + 1, FilenameTemplate::sanitize(&song.get_display_title())));
+        path
+    }
+
+    fn start_recording(&self, song: SongMetadata, path: PathBuf) {
+        debug!("Start headless recording to {:?}", path);
+
+        // The file starts at 0, no matter how long the pipeline is running already
+        let (srcpad, stream_caps) = self.get_recording_srcpad();
+        if let Some(clock) = self.pipeline.get_clock() {
+            let running_time = clock.get_time() - self.pipeline.get_base_time();
+            srcpad.set_offset(-(running_time.nseconds().unwrap_or(0) as i64));
+        }
+
+        let recorderbin = match RecorderBin::new(song, path, Some(&self.station), self.pipeline.clone(), &srcpad, stream_caps.as_ref()) {
+            Ok(recorderbin) => recorderbin,
+            Err(err) => {
+                // Without a recorderbin nothing can get saved, so there is no reason to keep the stream running
+                let message = format!("Could not record \"{}\": {}", self.station.name, err);
+                self.sender.send(Action::ViewShowNotification(message)).unwrap();
+                self.finish();
+                return;
+            }
+        };
+        *self.recorderbin.lock().unwrap() = Some(recorderbin);
+
+        // The recorderbin gets the data from now on
+        let dropprobe_ids: Vec<(Pad, PadProbeId)> = self.dropprobe_ids.borrow_mut().drain(..).collect();
+        for (pad, id) in dropprobe_ids {
+            if pad == srcpad {
+                pad.remove_probe(id);
+            } else {
+                self.dropprobe_ids.borrow_mut().push((pad, id));
+            }
+        }
+        if let Some((pad, id)) = self.file_blockprobe_id.borrow_mut().take() {
+            pad.remove_probe(id);
+        }
+    }
+
+    // Nothing is linked to the srcpads between two recordings, without a recorderbin the source would fail (not-linked).
+    // Same as `GstreamerBackend::drop_recording_data`, the block probe of the last recording gets replaced.
+    fn drop_recording_data(&self) {
+        let mut srcpads = vec![self.file_srcpad.clone()];
+        srcpads.extend(self.stream_queue.as_ref().and_then(|stream_queue| stream_queue.get_static_pad("src")));
+
+        let mut dropprobe_ids = self.dropprobe_ids.borrow_mut();
+        for srcpad in srcpads {
+            if dropprobe_ids.iter().any(|(pad, _)| *pad == srcpad) {
+                continue;
+            }
+            if let Some(id) = srcpad.add_probe(gstreamer::PadProbeType::BUFFER, |_, _| gstreamer::PadProbeReturn::Drop) {
+                dropprobe_ids.push((srcpad, id));
+            }
+        }
+
+        if let Some((pad, id)) = self.file_blockprobe_id.borrow_mut().take() {
+            pad.remove_probe(id);
+        }
+    }
+
+    // The stream queue only gets data, if uridecodebin didn't have to decode the stream
+    fn get_recording_srcpad(&self) -> (Pad, Option<Caps>) {
+        if let Some(stream_queue) = self.stream_queue.as_ref() {
+            let caps = stream_queue.get_static_pad("sink").and_then(|pad| pad.get_current_caps());
+            if caps.is_some() {
+                return (stream_queue.get_static_pad("src").unwrap(), caps);
+            }
+        }
+        (self.file_srcpad.clone(), None)
+    }
+
+    fn get_preroll(&self) -> &Preroll {
+        match self.stream_preroll.as_ref() {
+            Some(stream_preroll) if self.get_recording_srcpad().1.is_some() => stream_preroll,
+            _ => &self.file_preroll,
+        }
+    }
+
+    // Blocks the dataflow and finalizes the file, `RecordingStopped` gets sent afterwards
+    fn stop_recording(&self) {
+        if let Some(recorderbin) = self.recorderbin.lock().unwrap().as_ref() {
+            recorderbin.set_toc();
+        }
+
+        let rbin = self.recorderbin.clone();
+        let (srcpad, _) = self.get_recording_srcpad();
+        let preroll = self.get_preroll().clone();
+        let id = srcpad.add_probe(gstreamer::PadProbeType::BLOCK_DOWNSTREAM, move |_, info| {
+            // Same as in the GstreamerBackend, the data in front of the new song still belongs to the old one
+            if preroll.is_before_song_start(info) {
+                return gstreamer::PadProbeReturn::Pass;
+            }
+
+            if let Some(recorderbin) = rbin.lock().unwrap().as_ref() {
+                let sinkpad = recorderbin.gstbin.get_static_pad("sink").unwrap();
+                sinkpad.send_event(gstreamer::Event::new_eos().build());
+            }
+            gstreamer::PadProbeReturn::Ok
+        });
+        *self.file_blockprobe_id.borrow_mut() = id.map(|id| (srcpad, id));
+    }
+
+    fn finish(&self) {
+        if self.finished.get() {
+            return;
+        }
+
+        let _ = self.pipeline.set_state(State::Null);
+        self.finished.set(true);
+
+        info!("Headless recording of \"{}\" finished ({} file(s))", self.station.name, self.saved.get());
+        if self.saved.get() > 0 {
+            let message = format!("Scheduled recording of \"{}\" finished.", self.station.name);
+            self.sender.send(Action::ViewShowNotification(message)).unwrap();
+        }
+    }
+}
@@ -0,0 +1,420 @@
+use gio::prelude::*;
+use rustio::Station;
+
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, VecDeque};
+use std::f64::consts::PI;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+
+use crate::json_file::JsonFile;
+use crate::player::GstreamerBackend;
+use crate::settings::{Key, SettingsManager};
+
+lazy_static! {
+    static ref LOUDNESS: JsonFile<HashMap<String, f64>> = JsonFile::new("loudness.json");
+}
+
+// Format of the timeshift buffer (TIMESHIFT_CAPS), which gets measured
+const RATE: f64 = 44100.0;
+const CHANNELS: usize = 2;
+
+// All stations get adjusted to this loudness (in LUFS), which is the ReplayGain 2.0 reference level
+const TARGET_LOUDNESS: f64 = -18.0;
+const GAIN_MIN: f64 = -18.0;
+const GAIN_MAX: f64 = 12.0;
+
+// The gain moves slowly towards the target, so the correction itself isn't audible
+const UPDATE_INTERVAL_MS: u32 = 500;
+const GAIN_STEP: f64 = 0.5;
+// Don't trust the measurement before we have heard a few seconds of the station
+const MIN_MEASURED_BLOCKS: usize = 50;
+
+//////////////////////////////////////////////////////////////////////////////////////////////////////////
+//                                                                                                      //
+//  # LoudnessMeter                                                                                     //
+//                                                                                                      //
+//  Running loudness measurement according to EBU R128 / ITU-R BS.1770:                                 //
+//                                                                                                      //
+//    ---------      ------------      -----------      -----------------      -------------------      //
+//   | samples | -> | high shelf | -> | high pass | -> | 400ms blocks[1] | -> | gated average [2] |     //
+//    ---------      ------------      -----------      -----------------      -------------------      //
+//                                                                                                      //
+//  The two filters are the "K-weighting", which roughly matches the perceived loudness.                //
+//                                                                                                      //
+//  [1] A new block starts every 100ms, so they overlap by 75%.                                         //
+//  [2] Only the blocks of the last 30 seconds are used, so the measurement follows the programme.      //
+//      Blocks below -70 LUFS (silence) and 10 LU below the average (quiet passages) are ignored.       //
+//                                                                                                      //
+//////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+const SUBBLOCK_FRAMES: usize = (RATE as usize) / 10;
+const SUBBLOCKS_PER_BLOCK: usize = 4;
+const WINDOW_BLOCKS: usize = 300;
+const ABSOLUTE_GATE: f64 = -70.0;
+const RELATIVE_GATE: f64 = -10.0;
+
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 3],
+}
+
+impl Biquad {
+    fn high_shelf() -> Self {
+        let f0 = 1681.974_450_955_533;
+        let gain = 3.999_843_853_973_347;
+        let q = 0.707_175_236_955_419_6;
+
+        let k = (PI * f0 / RATE).tan();
+        let vh = 10f64.powf(gain / 20.0);
+        let vb = vh.powf(0.499_666_774_154_541_6);
+        let a0 = 1.0 + k / q + k * k;
+
+        Self {
+            b: [(vh + vb * k / q + k * k) / a0, 2.0 * (k * k - vh) / a0, (vh - vb * k / q + k * k) / a0],
+            a: [1.0, 2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        }
+    }
+
+    fn high_pass() -> Self {
+        let f0 = 38.135_470_876_024_44;
+        let q = 0.500_327_037_323_877_3;
+
+        let k = (PI * f0 / RATE).tan();
+        let a0 = 1.0 + k / q + k * k;
+
+        Self {
+            b: [1.0, -2.0, 1.0],
+            a: [1.0, 2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        }
+    }
+}
+
+// Filter state of one channel (direct form II)
+#[derive(Clone, Copy, Default)]
+struct FilterState {
+    shelf: [f64; 2],
+    pass: [f64; 2],
+}
+
+fn filter(biquad: &Biquad, state: &mut [f64; 2], input: f64) -> f64 {
+    let w = input - biquad.a[1] * state[0] - biquad.a[2] * state[1];
+    let output = biquad.b[0] * w + biquad.b[1] * state[0] + biquad.b[2] * state[1];
+    state[1] = state[0];
+    state[0] = w;
+    output
+}
+
+pub struct LoudnessMeter {
+    shelf: Biquad,
+    pass: Biquad,
+    states: [FilterState; CHANNELS],
+
+    // Sum of the squared samples of the current 100ms subblock
+    subblock_power: f64,
+    subblock_frames: usize,
+    subblocks: VecDeque<f64>,
+
+    // Mean power of the last 400ms blocks
+    blocks: VecDeque<f64>,
+}
+
+impl LoudnessMeter {
+    pub fn new() -> Self {
+        Self {
+            shelf: Biquad::high_shelf(),
+            pass: Biquad::high_pass(),
+            states: [FilterState::default(); CHANNELS],
+            subblock_power: 0.0,
+            subblock_frames: 0,
+            subblocks: VecDeque::new(),
+            blocks: VecDeque::new(),
+        }
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+
+    /// Processes interleaved S16LE samples.
+    pub fn process(&mut self, data: &[u8]) {
+        for frame in data.chunks(2 * CHANNELS) {
+            if frame.len() != 2 * CHANNELS {
+                break;
+            }
+
+            for (channel, bytes) in frame.chunks(2).enumerate() {
+                let sample = f64::from(i16::from_le_bytes([bytes[0], bytes[1]])) / 32768.0;
+                let state = &mut self.states[channel];
+                let sample = filter(&self.shelf, &mut state.shelf, sample);
+                let sample = filter(&self.pass, &mut state.pass, sample);
+                self.subblock_power += sample * sample;
+            }
+
+            self.subblock_frames += 1;
+            if self.subblock_frames == SUBBLOCK_FRAMES {
+                self.finish_subblock();
+            }
+        }
+    }
+
+    fn finish_subblock(&mut self) {
+        self.subblocks.push_back(self.subblock_power / SUBBLOCK_FRAMES as f64);
+        self.subblock_power = 0.0;
+        self.subblock_frames = 0;
+
+        if self.subblocks.len() > SUBBLOCKS_PER_BLOCK {
+            self.subblocks.pop_front();
+        }
+        if self.subblocks.len() == SUBBLOCKS_PER_BLOCK {
+            let power = self.subblocks.iter().sum::<f64>() / SUBBLOCKS_PER_BLOCK as f64;
+            self.blocks.push_back(power);
+            if self.blocks.len() > WINDOW_BLOCKS {
+                self.blocks.pop_front();
+            }
+        }
+    }
+
+    /// Gated loudness of the last 30 seconds in LUFS, `None` if there's not enough audible material yet.
+    pub fn get_loudness(&self) -> Option<f64> {
+        let absolute: Vec<f64> = self.blocks.iter().cloned().filter(|power| Self::to_lufs(*power) > ABSOLUTE_GATE).collect();
+        if absolute.len() < MIN_MEASURED_BLOCKS {
+            return None;
+        }
+
+        let threshold = Self::to_lufs(Self::mean(&absolute)) + RELATIVE_GATE;
+        let relative: Vec<f64> = absolute.into_iter().filter(|power| Self::to_lufs(*power) > threshold).collect();
+        if relative.is_empty() {
+            return None;
+        }
+
+        Some(Self::to_lufs(Self::mean(&relative)))
+    }
+
+    fn mean(powers: &[f64]) -> f64 {
+        powers.iter().sum::<f64>() / powers.len() as f64
+    }
+
+    fn to_lufs(power: f64) -> f64 {
+        -0.691 + 10.0 * power.max(std::f64::MIN_POSITIVE).log10()
+    }
+}
+
+/// Adjusts the gain of the playback, so that all stations play at `TARGET_LOUDNESS`.
+/// The last gain of every station gets remembered, so the next time it starts at the right level.
+#[derive(Clone)]
+pub struct LoudnessNormalizer {
+    backend: Arc<Mutex<GstreamerBackend>>,
+    enabled: Rc<Cell<bool>>,
+    // Keeps the "changed" signal connected
+    settings: gio::Settings,
+
+    // Current gain in dB
+    gain: Rc<Cell<f64>>,
+    station: Rc<RefCell<Option<String>>>,
+    station_gains: Rc<RefCell<HashMap<String, f64>>>,
+}
+
+impl LoudnessNormalizer {
+    pub fn new(backend: Arc<Mutex<GstreamerBackend>>) -> Self {
+        let station_gains = LOUDNESS.with(|gains| gains.clone());
+
+        let normalizer = Self {
+            backend,
+            enabled: Rc::new(Cell::new(SettingsManager::get_boolean(Key::LoudnessNormalization))),
+            settings: SettingsManager::get_settings(),
+            gain: Rc::new(Cell::new(0.0)),
+            station: Rc::new(RefCell::new(None)),
+            station_gains: Rc::new(RefCell::new(station_gains)),
+        };
+
+        normalizer.setup_signals();
+        normalizer
+    }
+
+    /// Remembers the gain of the previous station, and starts with the known gain of the new one.
+    pub fn set_station(&self, station: &Station) {
+        self.remember();
+
+        let gain = self.station_gains.borrow().get(&station.id).cloned().unwrap_or(0.0);
+        *self.station.borrow_mut() = Some(station.id.clone());
+
+        let backend = self.backend.lock().unwrap();
+        backend.reset_loudness();
+        if self.enabled.get() {
+            debug!("Start \"{}\" with a gain of {:.1} dB", station.name, gain);
+            self.gain.set(gain);
+            backend.set_normalization_gain(gain);
+        }
+    }
+
+    fn set_enabled(&self, enabled: bool) {
+        if enabled == self.enabled.get() {
+            return;
+        }
+        debug!("Loudness normalization enabled: {}", enabled);
+
+        // Keep the gain of the station for the next time, and continue with it if normalization gets enabled again
+        self.remember();
+        self.enabled.set(enabled);
+
+        let gain = match (enabled, self.station.borrow().as_ref()) {
+            (true, Some(station)) => self.station_gains.borrow().get(station).cloned().unwrap_or(0.0),
+            _ => 0.0,
+        };
+        self.gain.set(gain);
+        self.backend.lock().unwrap().set_normalization_gain(gain);
+    }
+
+    /// Stores the current gain of the station on disk.
+    pub fn remember(&self) {
+        if !self.enabled.get() {
+            return;
+        }
+
+        if let Some(station) = self.station.borrow().as_ref() {
+            let gain = self.gain.get();
+            if self.station_gains.borrow().get(station) == Some(&gain) {
+                return;
+            }
+
+            self.station_gains.borrow_mut().insert(station.clone(), gain);
+            if let Err(error) = LOUDNESS.set(self.station_gains.borrow().clone()) {
+                warn!("Could not write loudness data: {}", error);
+            }
+        }
+    }
+
+    fn setup_signals(&self) {
+        let normalizer = self.clone();
+        gtk::timeout_add(UPDATE_INTERVAL_MS, move || {
+            normalizer.update();
+            glib::Continue(true)
+        });
+
+        // The setting can be changed while playing
+        let normalizer = self.clone();
+        self.settings.connect_changed(move |settings, key| {
+            if key == Key::LoudnessNormalization.as_str() {
+                normalizer.set_enabled(settings.get_boolean(key));
+            }
+        });
+    }
+
+    fn update(&self) {
+        if !self.enabled.get() {
+            return;
+        }
+
+        let backend = self.backend.lock().unwrap();
+        let loudness = match backend.get_loudness() {
+            Some(loudness) => loudness,
+            None => return,
+        };
+
+        let gain = Self::next_gain(self.gain.get(), loudness);
+        if gain != self.gain.get() {
+            self.gain.set(gain);
+            backend.set_normalization_gain(gain);
+        }
+    }
+
+    // One step from `gain` towards the gain which brings `loudness` to the target.
+    // The measurement is done before the gain gets applied.
+    fn next_gain(gain: f64, loudness: f64) -> f64 {
+        let target = (TARGET_LOUDNESS - loudness).max(GAIN_MIN).min(GAIN_MAX);
+        if target > gain {
+            (gain + GAIN_STEP).min(target)
+        } else {
+            (gain - GAIN_STEP).max(target)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Interleaved S16LE stereo, both channels get the same sine
+    fn sine(frequency: f64, amplitude: f64, seconds: f64) -> Vec<u8> {
+        let frames = (RATE * seconds) as usize;
+        let mut data = Vec::with_capacity(frames * 2 * CHANNELS);
+        for i in 0..frames {
+            let sample = (amplitude * (2.0 * PI * frequency * i as f64 / RATE).sin() * 32767.0) as i16;
+            for _ in 0..CHANNELS {
+                data.extend_from_slice(&sample.to_le_bytes());
+            }
+        }
+        data
+    }
+
+    fn measure(parts: &[(f64, f64)]) -> Option<f64> {
+        let mut meter = LoudnessMeter::new();
+        for (amplitude, seconds) in parts {
+            meter.process(&sine(997.0, *amplitude, *seconds));
+        }
+        meter.get_loudness()
+    }
+
+    #[test]
+    fn sine_at_reference_level() {
+        // A 997 Hz sine at -20 dBFS in both channels is -20 LUFS (ITU-R BS.1770)
+        let loudness = measure(&[(0.1, 10.0)]).unwrap();
+        assert!((loudness - -20.0).abs() < 0.2, "{}", loudness);
+
+        let loudness = measure(&[(1.0, 10.0)]).unwrap();
+        assert!((loudness - 0.0).abs() < 0.2, "{}", loudness);
+    }
+
+    #[test]
+    fn not_enough_material() {
+        assert_eq!(measure(&[(0.1, 2.0)]), None);
+        // Silence is below the absolute gate
+        assert_eq!(measure(&[(0.0, 10.0)]), None);
+        assert_eq!(measure(&[(0.00001, 10.0)]), None);
+    }
+
+    #[test]
+    fn quiet_passages_get_gated() {
+        // The -40 dBFS part is more than 10 LU below the average, so only the -20 dBFS part counts
+        let loudness = measure(&[(0.1, 10.0), (0.01, 10.0)]).unwrap();
+        assert!((loudness - -20.0).abs() < 0.3, "{}", loudness);
+
+        // -23 dBFS is within the relative gate, the result is between both parts
+        let loudness = measure(&[(0.1, 10.0), (0.0708, 10.0)]).unwrap();
+        assert!(loudness < -20.5 && loudness > -22.5, "{}", loudness);
+    }
+
+    #[test]
+    fn only_the_last_30_seconds_count() {
+        let loudness = measure(&[(1.0, 20.0), (0.1, 31.0)]).unwrap();
+        assert!((loudness - -20.0).abs() < 0.2, "{}", loudness);
+    }
+
+    #[test]
+    fn reset_forgets_everything() {
+        let mut meter = LoudnessMeter::new();
+        meter.process(&sine(997.0, 0.1, 10.0));
+        meter.reset();
+        assert_eq!(meter.get_loudness(), None);
+    }
+
+    #[test]
+    fn gain_moves_in_steps_towards_the_target() {
+        // -28 LUFS needs +10 dB
+        assert_eq!(LoudnessNormalizer::next_gain(0.0, -28.0), GAIN_STEP);
+        assert_eq!(LoudnessNormalizer::next_gain(9.8, -28.0), 10.0);
+        assert_eq!(LoudnessNormalizer::next_gain(10.0, -28.0), 10.0);
+        // -8 LUFS needs -10 dB
+        assert_eq!(LoudnessNormalizer::next_gain(0.0, -8.0), -GAIN_STEP);
+        assert_eq!(LoudnessNormalizer::next_gain(-9.9, -8.0), -10.0);
+    }
+
+    #[test]
+    fn gain_stays_within_limits() {
+        assert_eq!(LoudnessNormalizer::next_gain(GAIN_MAX, -60.0), GAIN_MAX);
+        assert_eq!(LoudnessNormalizer::next_gain(GAIN_MIN, 10.0), GAIN_MIN);
+        // The limits got lowered, a stored gain outside of them gets pulled back
+        assert_eq!(LoudnessNormalizer::next_gain(GAIN_MAX + 3.0, -60.0), GAIN_MAX + 3.0 - GAIN_STEP);
+    }
+}
@@ -1,7 +1,9 @@
 #[derive(Clone, PartialEq)]
 pub enum PlaybackState {
     Playing,
+    Paused,
     Stopped,
     Loading,
+    Reconnecting(u32),
     Failure(String),
 }
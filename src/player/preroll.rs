@@ -0,0 +1,118 @@
+use gstreamer::prelude::*;
+use gstreamer::{Element, PadProbeInfo};
+
+use std::sync::{Arc, Mutex};
+
+use crate::settings::{Key, SettingsManager};
+
+// The title change reaches us with a delay (bus polling, main loop), the data must still be in the queue by then
+const MARGIN_NS: u64 = 1_000_000_000;
+
+//////////////////////////////////////////////////////////////////////////////////////////////////////////
+//                                                                                                      //
+//  # Pre-roll                                                                                          //
+//                                                                                                      //
+//    ------------------------------------      -------------                                           //
+//   | queue (holds back pre-roll + margin) | -> | recorderbin |                                        //
+//    ------------------------------------      -------------                                           //
+//          ^                                                                                           //
+//          title tag event -> song start = position of the tag - pre-roll                              //
+//                                                                                                      //
+//  The recording queue holds back the last seconds of audio, so the data in front of a title change    //
+//  hasn't reached the recorderbin yet when we hear about it. The position of the tag event (in-band,   //
+//  not the bus message) marks the title change. Stations send the title a bit after the song has       //
+//  started, so the new song starts `recording-preroll` seconds earlier. Everything in front of that    //
+//  still goes into the old file, everything after it into the new one.                                 //
+//                                                                                                      //
+//////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Clone)]
+pub struct Preroll {
+    queue: Element,
+    // Timestamp (PTS) of the last buffer which entered the queue
+    position: Arc<Mutex<Option<u64>>>,
+    // Stream title and timestamp of the last title change
+    title: Arc<Mutex<Option<(String, u64)>>>,
+    // Nanoseconds, the settings can't be read from the streaming thread
+    duration: Arc<Mutex<u64>>,
+}
+
+impl Preroll {
+    pub fn new(queue: &Element) -> Self {
+        let preroll = Self {
+            queue: queue.clone(),
+            position: Arc::new(Mutex::new(None)),
+            title: Arc::new(Mutex::new(None)),
+            duration: Arc::new(Mutex::new(0)),
+        };
+
+        let position = preroll.position.clone();
+        let title = preroll.title.clone();
+        let sinkpad = queue.get_static_pad("sink").unwrap();
+        sinkpad.add_probe(gstreamer::PadProbeType::BUFFER | gstreamer::PadProbeType::EVENT_DOWNSTREAM, move |_, info| {
+            match info.data {
+                Some(gstreamer::PadProbeData::Buffer(ref buffer)) => {
+                    if let Some(pts) = buffer.get_pts().nseconds() {
+                        *position.lock().unwrap() = Some(pts);
+                    }
+                }
+                Some(gstreamer::PadProbeData::Event(ref event)) => match event.view() {
+                    gstreamer::EventView::Tag(tag) => {
+                        // Stations repeat the title, only the first tag of a song marks its start
+                        let new_title = tag.get_tag().get::<gstreamer::tags::Title>().and_then(|t| t.get().map(|t| t.trim().to_string()));
+                        let mut title = title.lock().unwrap();
+                        if let (Some(new_title), Some(pts)) = (new_title, *position.lock().unwrap()) {
+                            if title.as_ref().map_or(true, |(title, _)| *title != new_title) {
+                                *title = Some((new_title, pts));
+                            }
+                        }
+                    }
+                    // New connection, the timestamps of the old one are meaningless now
+                    gstreamer::EventView::StreamStart(_) => *title.lock().unwrap() = None,
+                    _ => (),
+                },
+                _ => (),
+            }
+            gstreamer::PadProbeReturn::Ok
+        });
+
+        preroll.update();
+        preroll
+    }
+
+    /// Applies `recording-preroll` to the queue. Only has an effect on data which enters the queue afterwards.
+    pub fn update(&self) {
+        let duration = (SettingsManager::get_double(Key::RecordingPreroll) * 1_000_000_000.0) as u64;
+        *self.duration.lock().unwrap() = duration;
+
+        let threshold = duration + MARGIN_NS;
+        self.queue.set_property("min-threshold-time", &threshold).unwrap();
+        // There has to be some space left above the threshold, otherwise the queue would block
+        self.queue.set_property("max-size-time", &(threshold + 2 * MARGIN_NS)).unwrap();
+        self.queue.set_property("max-size-buffers", &0u32).unwrap();
+        self.queue.set_property("max-size-bytes", &0u32).unwrap();
+    }
+
+    /// Timestamp at which the song with this stream title starts, `None` if we haven't seen its tag yet.
+    pub fn get_song_start(&self, stream_title: &str) -> Option<u64> {
+        match self.title.lock().unwrap().as_ref() {
+            Some((title, pts)) if title == stream_title => Some(pts.saturating_sub(*self.duration.lock().unwrap())),
+            _ => None,
+        }
+    }
+
+    /// Whether the data of a pad probe still belongs to the song before the last title change.
+    pub fn is_before_song_start(&self, info: &PadProbeInfo) -> bool {
+        let duration = *self.duration.lock().unwrap();
+        let song_start = self.title.lock().unwrap().as_ref().map(|(_, pts)| pts.saturating_sub(duration));
+        let pts = match info.data {
+            Some(gstreamer::PadProbeData::Buffer(ref buffer)) => buffer.get_pts().nseconds(),
+            _ => None,
+        };
+
+        match (song_start, pts) {
+            (Some(song_start), Some(pts)) => pts < song_start,
+            _ => false,
+        }
+    }
+}
@@ -0,0 +1,211 @@
+use glib::Sender;
+use rustio::Station;
+
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crate::player::gstreamer_backend::GstreamerMessage;
+use crate::player::resolver::Resolver;
+use crate::player::GstreamerBackend;
+
+// Delay before the first reconnect attempt, it gets doubled with every failed attempt.
+const BACKOFF_BASE_SECS: u32 = 1;
+// Maximum delay between two attempts.
+const BACKOFF_MAX_SECS: u32 = 60;
+
+#[derive(Clone)]
+pub struct Reconnector {
+    station: Rc<RefCell<Option<Station>>>,
+    // 0 means that we're currently not reconnecting
+    attempt: Rc<Cell<u32>>,
+    // An attempt is scheduled, but has not been started yet
+    pending: Rc<Cell<bool>>,
+    // Changes with every scheduled attempt and every reset, so attempts can tell whether they're still wanted.
+    // The attempt number can't be used for this, it starts from the beginning after a reset.
+    generation: Arc<AtomicUsize>,
+    // Whether the current station has been connected successfully at least once
+    connected: Rc<Cell<bool>>,
+    // We're switching to the next stream candidate of the station
+    switching: Arc<AtomicBool>,
+    // The station has been set, but we don't know its stream url yet
+    resolving: Rc<Cell<bool>>,
+
+    resolver: Resolver,
+    backend: Arc<Mutex<GstreamerBackend>>,
+    sender: Sender<GstreamerMessage>,
+}
+
+impl Reconnector {
+    pub fn new(backend: Arc<Mutex<GstreamerBackend>>, sender: Sender<GstreamerMessage>) -> Self {
+        Self {
+            station: Rc::new(RefCell::new(None)),
+            attempt: Rc::new(Cell::new(0)),
+            pending: Rc::new(Cell::new(false)),
+            generation: Arc::new(AtomicUsize::new(0)),
+            connected: Rc::new(Cell::new(false)),
+            switching: Arc::new(AtomicBool::new(false)),
+            resolving: Rc::new(Cell::new(false)),
+            resolver: Resolver::new(),
+            backend,
+            sender,
+        }
+    }
+
+    pub fn set_station(&self, station: Station) {
+        *self.station.borrow_mut() = Some(station);
+        self.connected.set(false);
+        self.reset();
+    }
+
+    /// Resolves the station, the first stream candidate gets sent as `StreamResolved`.
+    pub fn connect(&self) {
+        let station = match self.station.borrow().clone() {
+            Some(station) => station,
+            None => return,
+        };
+
+        self.resolving.set(true);
+        let resolver = self.resolver.clone();
+        let sender = self.sender.clone();
+        thread::spawn(move || match resolver.resolve(station) {
+            Some(station_url) => {
+                sender.send(GstreamerMessage::StreamResolved(station_url)).unwrap();
+            }
+            None => {
+                let message = "Could not find a playable stream for this station".to_string();
+                sender.send(GstreamerMessage::StreamError(message)).unwrap();
+            }
+        });
+    }
+
+    /// The result of `connect` has arrived.
+    pub fn set_resolved(&self) {
+        self.resolving.set(false);
+    }
+
+    pub fn is_resolving(&self) -> bool {
+        self.resolving.get()
+    }
+
+    /// Whether the station has further stream candidates, which have not been tried yet.
+    pub fn has_candidates(&self) -> bool {
+        self.resolver.has_candidates()
+    }
+
+    /// Replaces the current stream, which never played, with the next candidate.
+    pub fn try_next_candidate(&self) {
+        // A single broken stream can cause several errors
+        if self.switching.swap(true, Ordering::SeqCst) {
+            return;
+        }
+
+        let resolver = self.resolver.clone();
+        let switching = self.switching.clone();
+        let current_generation = self.generation.clone();
+        let generation = current_generation.load(Ordering::SeqCst);
+        let backend = self.backend.clone();
+        let sender = self.sender.clone();
+        thread::spawn(move || {
+            match resolver.next_candidate() {
+                // The playback got stopped while we were resolving
+                Some(_) if current_generation.load(Ordering::SeqCst) != generation => (),
+                Some(station_url) => {
+                    debug!("Try next stream candidate: {}", station_url);
+                    backend.lock().unwrap().reconnect(&station_url);
+                }
+                None => {
+                    let message = "Could not find a playable stream for this station".to_string();
+                    sender.send(GstreamerMessage::StreamError(message)).unwrap();
+                }
+            }
+            switching.store(false, Ordering::SeqCst);
+        });
+    }
+
+    /// Whether we're currently switching to another stream candidate.
+    pub fn is_switching(&self) -> bool {
+        self.switching.load(Ordering::SeqCst)
+    }
+
+    /// Cancels a pending reconnect attempt.
+    pub fn reset(&self) {
+        self.attempt.set(0);
+        self.pending.set(false);
+        self.generation.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub fn set_connected(&self) {
+        self.connected.set(true);
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.connected.get()
+    }
+
+    pub fn is_reconnecting(&self) -> bool {
+        self.attempt.get() != 0
+    }
+
+    /// Schedules a new reconnect attempt, and returns its number.
+    pub fn schedule(&self) -> u32 {
+        // A single connection loss can cause several errors
+        if self.pending.get() {
+            return self.attempt.get();
+        }
+
+        let attempt = self.attempt.get() + 1;
+        self.attempt.set(attempt);
+        self.pending.set(true);
+        let generation = self.generation.fetch_add(1, Ordering::SeqCst) + 1;
+
+        let delay = BACKOFF_BASE_SECS.checked_shl(attempt - 1).unwrap_or(BACKOFF_MAX_SECS).min(BACKOFF_MAX_SECS);
+        debug!("Reconnect attempt {} in {} seconds", attempt, delay);
+
+        let current_generation = self.generation.clone();
+        let pending = self.pending.clone();
+        let station = self.station.clone();
+        let resolver = self.resolver.clone();
+        let backend = self.backend.clone();
+        let sender = self.sender.clone();
+        gtk::timeout_add_seconds(delay, move || {
+            // Attempt got cancelled in the meantime
+            if current_generation.load(Ordering::SeqCst) != generation {
+                return glib::Continue(false);
+            }
+            pending.set(false);
+
+            let station = match station.borrow().clone() {
+                Some(station) => station,
+                None => return glib::Continue(false),
+            };
+
+            // Resolve the station url again, the playlist can point to another server now,
+            // or the stream url can contain a token which is already expired
+            let resolver = resolver.clone();
+            let current_generation = current_generation.clone();
+            let backend = backend.clone();
+            let sender = sender.clone();
+            thread::spawn(move || match resolver.resolve(station) {
+                // Resolving takes a while, the user could have stopped the playback in the meantime
+                Some(_) if current_generation.load(Ordering::SeqCst) != generation => {
+                    debug!("Reconnect attempt {} got cancelled", attempt);
+                }
+                Some(station_url) => {
+                    debug!("Reconnect to {}", station_url);
+                    backend.lock().unwrap().reconnect(&station_url);
+                }
+                None => {
+                    let message = "Could not resolve station url".to_string();
+                    sender.send(GstreamerMessage::StreamError(message)).unwrap();
+                }
+            });
+
+            glib::Continue(false)
+        });
+
+        attempt
+    }
+}
@@ -0,0 +1,67 @@
+use std::cell::Cell;
+use std::rc::Rc;
+use std::time::Duration;
+
+use crate::settings::{Key, SettingsManager};
+
+/// Decides which recordings are worth keeping.
+///
+/// The settings get read every time, so changes apply to the song which is currently recorded.
+#[derive(Clone)]
+pub struct RecorderRules {
+    // The next recording starts with the first song after tuning in, so we most likely missed its beginning
+    tuned_in: Rc<Cell<bool>>,
+    // The current recording started in the middle of a song
+    partial: Rc<Cell<bool>>,
+}
+
+impl RecorderRules {
+    pub fn new() -> Self {
+        Self {
+            tuned_in: Rc::new(Cell::new(true)),
+            partial: Rc::new(Cell::new(false)),
+        }
+    }
+
+    /// A new station, or the playback has been stopped.
+    pub fn reset(&self) {
+        self.tuned_in.set(true);
+        self.partial.set(false);
+    }
+
+    /// A new recording has started, only the first one after tuning in misses the beginning of its song.
+    pub fn recording_started(&self) {
+        self.partial.set(self.tuned_in.get());
+        self.tuned_in.set(false);
+    }
+
+    /// A title change which didn't start a recording (e.g. recording mode "off").
+    pub fn song_changed(&self) {
+        self.tuned_in.set(false);
+    }
+
+    /// Whether a recording of `duration` should be saved, when its song has ended.
+    pub fn keep(&self, duration: Duration) -> bool {
+        if self.partial.get() && SettingsManager::get_boolean(Key::RecordingSkipPartial) {
+            debug!("Discard recording: started in the middle of the song");
+            return false;
+        }
+
+        let minimum = Duration::from_secs(SettingsManager::get_uint(Key::RecordingMinimumDuration).into());
+        if duration < minimum {
+            debug!("Discard recording: shorter than {:?}", minimum);
+            return false;
+        }
+
+        true
+    }
+
+    /// Whether a recording of `duration` is longer than a song could be, e.g. a talk show.
+    pub fn exceeds_maximum(&self, duration: Duration) -> bool {
+        // 0 disables the maximum
+        match SettingsManager::get_uint(Key::RecordingMaximumDuration) {
+            0 => false,
+            maximum => duration > Duration::from_secs(maximum.into()),
+        }
+    }
+}
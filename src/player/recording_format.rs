@@ -0,0 +1,213 @@
+use gstreamer::prelude::*;
+use gstreamer::{Caps, Element, ElementFactory};
+
+use crate::settings::{Key, SettingsManager};
+
+// Encoders which produce AAC, the best one comes first
+static AAC_ENCODERS: [&str; 4] = ["fdkaacenc", "avenc_aac", "voaacenc", "faac"];
+
+// MP3 encoders and streams have no tags, these muxers add an ID3 tag in front of them
+static ID3_MUXERS: [&str; 2] = ["id3v2mux", "id3mux"];
+
+// Compressed formats which can be recorded without re-encoding them (passthrough). uridecodebin only
+// parses them, everything else gets decoded to audio/x-raw like before.
+pub static STREAM_CAPS: &str = "audio/x-raw; \
+                                audio/mpeg, mpegversion=(int)1, parsed=(boolean)true; \
+                                audio/mpeg, mpegversion=(int){2, 4}, framed=(boolean)true; \
+                                audio/x-vorbis; \
+                                audio/x-opus";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RecordingFormat {
+    Vorbis,
+    Opus,
+    Flac,
+    Mp3,
+    Aac,
+}
+
+impl RecordingFormat {
+    pub fn all() -> Vec<RecordingFormat> {
+        vec![RecordingFormat::Vorbis, RecordingFormat::Opus, RecordingFormat::Flac, RecordingFormat::Mp3, RecordingFormat::Aac]
+    }
+
+    /// The format selected by the user.
+    pub fn load() -> Self {
+        Self::from_str(&SettingsManager::get_string(Key::RecordingFormat))
+    }
+
+    pub fn from_str(name: &str) -> Self {
+        match name {
+            "opus" => RecordingFormat::Opus,
+            "flac" => RecordingFormat::Flac,
+            "mp3" => RecordingFormat::Mp3,
+            "aac" => RecordingFormat::Aac,
+            _ => RecordingFormat::Vorbis,
+        }
+    }
+
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        Self::all().into_iter().find(|format| format.get_extension() == extension)
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            RecordingFormat::Vorbis => "vorbis",
+            RecordingFormat::Opus => "opus",
+            RecordingFormat::Flac => "flac",
+            RecordingFormat::Mp3 => "mp3",
+            RecordingFormat::Aac => "aac",
+        }
+    }
+
+    pub fn get_title(self) -> &'static str {
+        match self {
+            RecordingFormat::Vorbis => "Ogg Vorbis",
+            RecordingFormat::Opus => "Opus",
+            RecordingFormat::Flac => "FLAC",
+            RecordingFormat::Mp3 => "MP3",
+            RecordingFormat::Aac => "AAC (M4A)",
+        }
+    }
+
+    pub fn get_extension(self) -> &'static str {
+        match self {
+            RecordingFormat::Vorbis => "ogg",
+            RecordingFormat::Opus => "opus",
+            RecordingFormat::Flac => "flac",
+            RecordingFormat::Mp3 => "mp3",
+            RecordingFormat::Aac => "m4a",
+        }
+    }
+
+    pub fn get_mime_type(self) -> &'static str {
+        match self {
+            RecordingFormat::Vorbis | RecordingFormat::Opus => "audio/ogg",
+            RecordingFormat::Flac => "audio/flac",
+            RecordingFormat::Mp3 => "audio/mpeg",
+            RecordingFormat::Aac => "audio/mp4",
+        }
+    }
+
+    /// Vorbis and FLAC get configured with `recording-quality`, all other formats with `recording-bitrate`.
+    pub fn uses_bitrate(self) -> bool {
+        match self {
+            RecordingFormat::Vorbis | RecordingFormat::Flac => false,
+            _ => true,
+        }
+    }
+
+    /// Whether all GStreamer elements, which are needed for this format, are installed.
+    pub fn is_available(self) -> bool {
+        let elements: Vec<&str> = match self {
+            RecordingFormat::Vorbis => vec!["vorbisenc", "oggmux"],
+            RecordingFormat::Opus => vec!["opusenc", "oggmux"],
+            RecordingFormat::Flac => vec!["flacenc"],
+            RecordingFormat::Mp3 => vec!["lamemp3enc"],
+            RecordingFormat::Aac => return Self::find_aac_encoder().is_some() && ElementFactory::find("mp4mux").is_some(),
+        };
+        elements.iter().all(|name| ElementFactory::find(name).is_some())
+    }
+
+    /// Creates the encoder, and the parser / muxer if the format needs them. They have to be linked in this order.
+    pub fn create_elements(self) -> Result<Vec<Element>, RecordingError> {
+        let quality = SettingsManager::get_double(Key::RecordingQuality);
+        let bitrate = SettingsManager::get_uint(Key::RecordingBitrate) as i32;
+
+        let elements = match self {
+            RecordingFormat::Vorbis => {
+                // vorbisenc goes from -0.1 to 1.0, but everything below 0.0 sounds terrible
+                let encoder = Self::make("vorbisenc")?;
+                encoder.set_property("quality", &(quality as f32)).unwrap();
+                vec![encoder, Self::make("oggmux")?]
+            }
+            RecordingFormat::Opus => {
+                let encoder = Self::make("opusenc")?;
+                encoder.set_property("bitrate", &(bitrate * 1000)).unwrap();
+                vec![encoder, Self::make("oggmux")?]
+            }
+            RecordingFormat::Flac => {
+                // Lossless, the quality is only the compression level (0 - 8)
+                let encoder = Self::make("flacenc")?;
+                encoder.set_property_from_str("quality", &((quality * 8.0).round() as u32).to_string());
+                vec![encoder]
+            }
+            RecordingFormat::Mp3 => {
+                let encoder = Self::make("lamemp3enc")?;
+                encoder.set_property_from_str("target", "bitrate");
+                encoder.set_property("bitrate", &bitrate).unwrap();
+                let mut elements = vec![encoder];
+                elements.extend(Self::make_id3_muxer());
+                elements
+            }
+            RecordingFormat::Aac => {
+                let name = Self::find_aac_encoder().ok_or_else(|| RecordingError::MissingElement(AAC_ENCODERS.join(" / ")))?;
+                // Not all encoders use the same integer type for the bitrate, so let GStreamer parse it
+                let encoder = Self::make(name)?;
+                encoder.set_property_from_str("bitrate", &(bitrate * 1000).to_string());
+                vec![encoder, Self::make("aacparse")?, Self::make("mp4mux")?]
+            }
+        };
+        Ok(elements)
+    }
+
+    fn find_aac_encoder() -> Option<&'static str> {
+        AAC_ENCODERS.iter().cloned().find(|name| ElementFactory::find(name).is_some())
+    }
+
+    fn make(name: &str) -> Result<Element, RecordingError> {
+        ElementFactory::make(name, name).ok_or_else(|| RecordingError::MissingElement(name.to_string()))
+    }
+
+    // Recording without tags is still better than no recording, so this is optional
+    fn make_id3_muxer() -> Option<Element> {
+        let muxer = ID3_MUXERS.iter().filter_map(|name| ElementFactory::make(name, name)).next();
+        if muxer.is_none() {
+            warn!("No ID3 muxer installed, the recording won't have tags");
+        }
+        muxer
+    }
+}
+
+/// Creates the muxer for a compressed stream (see `STREAM_CAPS`), if it needs one, and returns the file extension for it.
+pub fn create_stream_elements(caps: &Caps) -> Result<(Vec<Element>, &'static str), RecordingError> {
+    let structure = caps.get_structure(0).ok_or_else(|| RecordingError::Pipeline("Stream has no caps".to_string()))?;
+
+    let result = match structure.get_name() {
+        "audio/mpeg" => match structure.get::<i32>("mpegversion") {
+            // MP3 and ADTS are just a sequence of frames, so they can get written into the file directly.
+            // Only the ID3 tag gets put in front of them.
+            Some(1) => match structure.get::<i32>("layer") {
+                Some(2) => (RecordingFormat::make_id3_muxer().into_iter().collect(), "mp2"),
+                _ => (RecordingFormat::make_id3_muxer().into_iter().collect(), "mp3"),
+            },
+            _ => match structure.get::<&str>("stream-format") {
+                Some("adts") => (RecordingFormat::make_id3_muxer().into_iter().collect(), "aac"),
+                _ => (vec![RecordingFormat::make("mp4mux")?], "m4a"),
+            },
+        },
+        // vorbistag replaces the comment header of the stream with our tags
+        "audio/x-vorbis" => match ElementFactory::make("vorbistag", "vorbistag") {
+            Some(vorbistag) => (vec![vorbistag, RecordingFormat::make("oggmux")?], "ogg"),
+            None => (vec![RecordingFormat::make("oggmux")?], "ogg"),
+        },
+        // There is no element which can change the tags of an Opus stream
+        "audio/x-opus" => (vec![RecordingFormat::make("oggmux")?], "opus"),
+        name => return Err(RecordingError::Pipeline(format!("Can't record \"{}\" without re-encoding it", name))),
+    };
+    Ok(result)
+}
+
+quick_error! {
+    #[derive(Debug)]
+    pub enum RecordingError {
+        MissingElement(name: String) {
+            description("missing gstreamer element")
+            display("GStreamer element \"{}\" is not installed", name)
+        }
+        Pipeline(message: String) {
+            description("pipeline error")
+            display("Could not set up recording pipeline: {}", message)
+        }
+    }
+}
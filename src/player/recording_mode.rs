@@ -0,0 +1,76 @@
+use rustio::Station;
+
+use std::collections::HashMap;
+
+use crate::json_file::JsonFile;
+use crate::settings::{Key, SettingsManager};
+
+lazy_static! {
+    static ref STATION_MODES: JsonFile<HashMap<String, RecordingMode>> = JsonFile::new("recording_modes.json");
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum RecordingMode {
+    // Nothing gets recorded, so nothing gets encoded either
+    Off,
+    // One file for the whole recording, with a chapter for every song
+    Continuous,
+    // A new file for every song, like the song list does
+    PerSong,
+}
+
+impl RecordingMode {
+    pub fn all() -> Vec<RecordingMode> {
+        vec![RecordingMode::Off, RecordingMode::PerSong, RecordingMode::Continuous]
+    }
+
+    /// The mode of the station if the user has set one, otherwise the global one.
+    pub fn load(station: Option<&Station>) -> Self {
+        station
+            .and_then(|station| Self::get_station_mode(&station.id))
+            .unwrap_or_else(|| Self::from_str(&SettingsManager::get_string(Key::RecordingMode)))
+    }
+
+    pub fn from_str(name: &str) -> Self {
+        match name {
+            "off" => RecordingMode::Off,
+            "continuous" => RecordingMode::Continuous,
+            _ => RecordingMode::PerSong,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            RecordingMode::Off => "off",
+            RecordingMode::Continuous => "continuous",
+            RecordingMode::PerSong => "per-song",
+        }
+    }
+
+    pub fn get_title(self) -> &'static str {
+        match self {
+            RecordingMode::Off => "Off",
+            RecordingMode::Continuous => "Whole Session",
+            RecordingMode::PerSong => "Every Song",
+        }
+    }
+
+    pub fn get_station_mode(station_id: &str) -> Option<Self> {
+        STATION_MODES.with(|modes| modes.get(station_id).cloned())
+    }
+
+    /// `None` removes the mode of the station, so it uses the global one again.
+    pub fn set_station_mode(station_id: &str, mode: Option<Self>) {
+        let result = STATION_MODES.update(|modes| {
+            match mode {
+                Some(mode) => modes.insert(station_id.to_string(), mode),
+                None => modes.remove(station_id),
+            };
+            true
+        });
+
+        if let Err(err) = result {
+            warn!("Could not save recording modes: {}", err);
+        }
+    }
+}
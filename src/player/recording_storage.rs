@@ -0,0 +1,57 @@
+use gio::prelude::*;
+
+use std::fs;
+use std::path::PathBuf;
+
+use crate::config;
+use crate::settings::{Key, SettingsManager};
+use crate::song::Song;
+
+/// Where the songs of the song list get recorded to.
+pub struct RecordingStorage {}
+
+impl RecordingStorage {
+    /// Everything in this folder belongs to the song list, so a custom location gets a folder of its own.
+    pub fn get_directory() -> PathBuf {
+        let location = SettingsManager::get_string(Key::RecordingDirectory);
+        if location.is_empty() {
+            let mut path = glib::get_user_cache_dir().unwrap();
+            path.push(config::NAME);
+            path.push("recording");
+            path
+        } else {
+            let mut path = PathBuf::from(location);
+            path.push(format!("{} Recordings", config::NAME));
+            path
+        }
+    }
+
+    /// Whether more than `recording-min-free-space` is left on the disk of the recording folder.
+    pub fn has_free_space() -> bool {
+        let minimum = u64::from(SettingsManager::get_uint(Key::RecordingMinFreeSpace)) * 1_000_000;
+        let file = gio::File::new_for_path(Self::get_directory());
+        match file.query_filesystem_info("filesystem::free", None::<&gio::Cancellable>) {
+            Ok(info) => info.get_attribute_uint64("filesystem::free") >= minimum,
+            // Not every filesystem knows its free space, recording is still better than nothing
+            Err(_) => true,
+        }
+    }
+
+    /// Removes files which don't belong to any song of the song list,
+    /// e.g. the recording which was in progress when Shortwave crashed.
+    pub fn remove_orphans(songs: &[Song]) {
+        let entries = match fs::read_dir(Self::get_directory()) {
+            Ok(entries) => entries,
+            Err(_) => return,
+        };
+
+        for path in entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()) {
+            if path.is_file() && !songs.iter().any(|song| song.path == path) {
+                debug!("Remove orphaned recording {:?}", path);
+                if let Err(err) = fs::remove_file(&path) {
+                    warn!("Could not remove orphaned recording: {}", err);
+                }
+            }
+        }
+    }
+}
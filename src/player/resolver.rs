@@ -0,0 +1,449 @@
+use reqwest::header::CONTENT_TYPE;
+use reqwest::Url;
+use rustio::{Client, Station};
+
+use std::collections::VecDeque;
+use std::io::Read;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+// We only read the beginning of a response, a station url can also point directly to an endless stream.
+const MAX_PLAYLIST_SIZE: u64 = 64 * 1024;
+// Playlists can point to other playlists, but we don't follow them forever.
+const MAX_PLAYLIST_DEPTH: u32 = 2;
+const REQUEST_TIMEOUT_SECS: u64 = 10;
+
+//////////////////////////////////////////////////////////////////////////////////////////////////////////
+//                                                                                                      //
+//  # Resolver                                                                                          //
+//                                                                                                      //
+//    ---------------           -----------------------           ----------------------------          //
+//   | station.url   | ------> | local playlist parser | ------> | candidate 1, 2, 3, ...     |         //
+//    ---------------           -----------------------           ----------------------------          //
+//                                                                              |                       //
+//                                                                  all candidates failed               //
+//                                                                              |                       //
+//                                                                ---------------------------           //
+//                                                               | radio-browser.info lookup |          //
+//                                                                ---------------------------           //
+//                                                                                                      //
+//  The station url can be a PLS, M3U/M3U8, XSPF or ASX playlist, or directly the stream itself.        //
+//  Every entry of the playlist is a candidate, which gets tried in order, until one of them plays.     //
+//  The remote resolver only gets asked if we cannot find a working stream on our own.                  //
+//                                                                                                      //
+//////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Clone)]
+pub struct Resolver {
+    station: Arc<Mutex<Option<Station>>>,
+    candidates: Arc<Mutex<VecDeque<String>>>,
+    remote_tried: Arc<AtomicBool>,
+}
+
+impl Resolver {
+    pub fn new() -> Self {
+        Self {
+            station: Arc::new(Mutex::new(None)),
+            candidates: Arc::new(Mutex::new(VecDeque::new())),
+            remote_tried: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Resolves the station url into a list of candidates, and returns the first one.
+    /// This is blocking, so don't call it from the main thread.
+    pub fn resolve(&self, station: Station) -> Option<String> {
+        let candidates = match resolve_playlist(&station.url) {
+            Ok(candidates) => candidates,
+            Err(err) => {
+                warn!("Could not resolve station url locally: {}", err);
+                Vec::new()
+            }
+        };
+        debug!("Stream candidates for \"{}\": {:?}", station.name, candidates);
+
+        *self.station.lock().unwrap() = Some(station);
+        *self.candidates.lock().unwrap() = candidates.into_iter().collect();
+        self.remote_tried.store(false, Ordering::SeqCst);
+
+        self.next_candidate()
+    }
+
+    /// Whether there's another candidate left, which we can try.
+    pub fn has_candidates(&self) -> bool {
+        !self.candidates.lock().unwrap().is_empty() || !self.remote_tried.load(Ordering::SeqCst)
+    }
+
+    /// Returns the next candidate. If there are no local candidates left, the remote resolver gets asked.
+    /// This is blocking, so don't call it from the main thread.
+    pub fn next_candidate(&self) -> Option<String> {
+        if let Some(url) = self.candidates.lock().unwrap().pop_front() {
+            return Some(url);
+        }
+
+        if self.remote_tried.swap(true, Ordering::SeqCst) {
+            return None;
+        }
+
+        let station = self.station.lock().unwrap().clone()?;
+        let mut client = Client::new("http://www.radio-browser.info");
+        match client.get_playable_station_url(station) {
+            Ok(url) => Some(url),
+            Err(err) => {
+                warn!("Could not resolve station url remotely: {}", err.to_string());
+                None
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum PlaylistFormat {
+    Pls,
+    M3u,
+    Xspf,
+    Asx,
+}
+
+impl PlaylistFormat {
+    fn from_mime_type(mime_type: &str) -> Option<Self> {
+        match mime_type {
+            "audio/x-scpls" | "audio/scpls" => Some(PlaylistFormat::Pls),
+            "audio/x-mpegurl" | "audio/mpegurl" | "application/x-mpegurl" | "application/vnd.apple.mpegurl" => Some(PlaylistFormat::M3u),
+            "application/xspf+xml" => Some(PlaylistFormat::Xspf),
+            "video/x-ms-asf" | "video/x-ms-asx" | "audio/x-ms-asx" | "audio/x-ms-wax" | "video/x-ms-wax" => Some(PlaylistFormat::Asx),
+            _ => None,
+        }
+    }
+
+    fn from_url(url: &Url) -> Option<Self> {
+        let path = url.path().to_lowercase();
+        if path.ends_with(".pls") {
+            Some(PlaylistFormat::Pls)
+        } else if path.ends_with(".m3u") || path.ends_with(".m3u8") {
+            Some(PlaylistFormat::M3u)
+        } else if path.ends_with(".xspf") {
+            Some(PlaylistFormat::Xspf)
+        } else if path.ends_with(".asx") || path.ends_with(".wax") {
+            Some(PlaylistFormat::Asx)
+        } else {
+            None
+        }
+    }
+
+    fn from_content(content: &str) -> Option<Self> {
+        // The byte order mark isn't whitespace for `trim_start`
+        let start = content.trim_start_matches('\u{feff}').trim_start().to_lowercase();
+        if start.starts_with("[playlist]") {
+            Some(PlaylistFormat::Pls)
+        } else if start.starts_with("#extm3u") {
+            Some(PlaylistFormat::M3u)
+        } else if start.starts_with("<asx") {
+            Some(PlaylistFormat::Asx)
+        } else if start.starts_with("<?xml") && start.contains("<playlist") {
+            Some(PlaylistFormat::Xspf)
+        } else if start.starts_with("<?xml") && start.contains("<asx") {
+            Some(PlaylistFormat::Asx)
+        } else {
+            None
+        }
+    }
+}
+
+/// Fetches the url, and returns the contained streams in order.
+/// If the url doesn't point to a playlist, the url itself is the only candidate.
+pub fn resolve_playlist(url: &str) -> Result<Vec<String>, ResolverError> {
+    let url = Url::parse(url)?;
+    let client = reqwest::Client::builder().timeout(Duration::from_secs(REQUEST_TIMEOUT_SECS)).build()?;
+
+    let mut candidates = Vec::new();
+    resolve_url(&client, url, 0, &mut candidates)?;
+
+    // Playlists often contain the same stream multiple times
+    let mut unique: Vec<String> = Vec::new();
+    for candidate in candidates {
+        if !unique.contains(&candidate) {
+            unique.push(candidate);
+        }
+    }
+
+    if unique.is_empty() {
+        return Err(ResolverError::Empty);
+    }
+    Ok(unique)
+}
+
+fn resolve_url(client: &reqwest::Client, url: Url, depth: u32, candidates: &mut Vec<String>) -> Result<(), ResolverError> {
+    let mut response = client.get(url.clone()).send()?.error_for_status()?;
+
+    // Redirects are followed by reqwest, relative entries are relative to the final url
+    let base = response.url().clone();
+    let mime_type = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.split(';').next().unwrap_or("").trim().to_lowercase())
+        .unwrap_or_default();
+
+    // It's the stream itself, don't download it
+    let known_format = PlaylistFormat::from_mime_type(&mime_type).or_else(|| PlaylistFormat::from_url(&base));
+    if known_format.is_none() && (mime_type.starts_with("audio/") || mime_type.starts_with("video/") || mime_type == "application/ogg") {
+        candidates.push(url.into_string());
+        return Ok(());
+    }
+
+    let mut content = String::new();
+    let mut bytes = Vec::new();
+    response.by_ref().take(MAX_PLAYLIST_SIZE).read_to_end(&mut bytes)?;
+    content.push_str(&String::from_utf8_lossy(&bytes));
+
+    let format = match known_format.or_else(|| PlaylistFormat::from_content(&content)) {
+        Some(format) => format,
+        None => {
+            candidates.push(url.into_string());
+            return Ok(());
+        }
+    };
+
+    // HLS playlists get handled by GStreamer itself
+    if format == PlaylistFormat::M3u && is_hls_playlist(&content) {
+        candidates.push(url.into_string());
+        return Ok(());
+    }
+
+    let entries = parse_playlist(format, &content, &base);
+    debug!("Parsed {:?} playlist {} with {} entries", format, base, entries.len());
+
+    for entry in entries {
+        // Only follow entries which are playlists themselves, everything else could be an endless stream
+        if PlaylistFormat::from_url(&entry).is_some() && depth < MAX_PLAYLIST_DEPTH {
+            if let Err(err) = resolve_url(client, entry.clone(), depth + 1, candidates) {
+                warn!("Could not resolve nested playlist {}: {}", entry, err);
+            }
+        } else {
+            candidates.push(entry.into_string());
+        }
+    }
+
+    Ok(())
+}
+
+/// The entries of the playlist, relative ones are relative to the url of the playlist (`base`).
+fn parse_playlist(format: PlaylistFormat, content: &str, base: &Url) -> Vec<Url> {
+    let entries = match format {
+        PlaylistFormat::Pls => parse_pls(content),
+        PlaylistFormat::M3u => parse_m3u(content),
+        PlaylistFormat::Xspf => parse_xspf(content),
+        PlaylistFormat::Asx => parse_asx(content),
+    };
+    entries.iter().filter_map(|entry| base.join(entry).ok()).collect()
+}
+
+fn is_hls_playlist(content: &str) -> bool {
+    content.lines().any(|line| line.starts_with("#EXT-X-"))
+}
+
+// [playlist]
+// File1=http://example.com/stream
+// Title1=Example
+fn parse_pls(content: &str) -> Vec<String> {
+    let mut entries: Vec<(u32, String)> = content
+        .lines()
+        .filter_map(|line| {
+            let mut split = line.trim().splitn(2, '=');
+            let key = split.next()?.trim().to_lowercase();
+            let value = split.next()?.trim();
+
+            if !key.starts_with("file") || value.is_empty() {
+                return None;
+            }
+            let index = key["file".len()..].parse::<u32>().ok()?;
+            Some((index, value.to_string()))
+        })
+        .collect();
+
+    entries.sort_by_key(|(index, _)| *index);
+    entries.into_iter().map(|(_, url)| url).collect()
+}
+
+// #EXTM3U
+// #EXTINF:-1,Example
+// http://example.com/stream
+fn parse_m3u(content: &str) -> Vec<String> {
+    content
+        .lines()
+        .map(|line| line.trim_start_matches('\u{feff}').trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| line.to_string())
+        .collect()
+}
+
+// <playlist><trackList><track><location>http://example.com/stream</location></track></trackList></playlist>
+fn parse_xspf(content: &str) -> Vec<String> {
+    let lowercase = content.to_ascii_lowercase();
+    let mut entries = Vec::new();
+    let mut pos = 0;
+
+    while let Some(start) = lowercase[pos..].find("<location>") {
+        let start = pos + start + "<location>".len();
+        let end = match lowercase[start..].find("</location>") {
+            Some(end) => start + end,
+            None => break,
+        };
+
+        let location = unescape_xml(content[start..end].trim());
+        if !location.is_empty() {
+            entries.push(location);
+        }
+        pos = end;
+    }
+
+    entries
+}
+
+// <asx version="3.0"><entry><ref href="http://example.com/stream" /></entry></asx>
+// ASX files are often not valid XML, so we're very tolerant here (case, quotes, whitespace).
+fn parse_asx(content: &str) -> Vec<String> {
+    let lowercase = content.to_ascii_lowercase();
+    let mut entries = Vec::new();
+    let mut pos = 0;
+
+    while let Some(start) = lowercase[pos..].find("<ref") {
+        let start = pos + start + "<ref".len();
+        let end = match lowercase[start..].find('>') {
+            Some(end) => start + end,
+            None => break,
+        };
+        pos = end;
+
+        let tag = &lowercase[start..end];
+        let href = match tag.find("href") {
+            Some(href) => start + href + "href".len(),
+            None => continue,
+        };
+
+        let value = content[href..end].trim_start().trim_start_matches('=').trim_start();
+        let quote = match value.chars().next() {
+            Some(quote) if quote == '"' || quote == '\'' => quote,
+            _ => continue,
+        };
+        if let Some(value) = value[1..].splitn(2, quote).next() {
+            let url = unescape_xml(value.trim());
+            if !url.is_empty() {
+                entries.push(url);
+            }
+        }
+    }
+
+    entries
+}
+
+fn unescape_xml(text: &str) -> String {
+    text.replace("&lt;", "<").replace("&gt;", ">").replace("&quot;", "\"").replace("&apos;", "'").replace("&amp;", "&")
+}
+
+quick_error! {
+    #[derive(Debug)]
+    pub enum ResolverError {
+        Io(err: std::io::Error) {
+            from()
+            description("io error")
+            display("I/O error: {}", err)
+            cause(err)
+        }
+        Http(err: reqwest::Error) {
+            from()
+            description("http error")
+            display("Network error: {}", err)
+            cause(err)
+        }
+        Url(err: reqwest::UrlError) {
+            from()
+            description("url error")
+            display("Invalid url: {}", err)
+            cause(err)
+        }
+        Empty {
+            description("empty playlist")
+            display("Playlist does not contain any streams")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pls_in_order_of_the_file_numbers() {
+        let content = "[playlist]\r\nNumberOfEntries=3\r\nFile3=http://example.com/c\r\nTitle3=C\r\nFile1=http://example.com/a\r\nFile2=\r\nLength1=-1\r\nVersion=2\r\n";
+        assert_eq!(parse_pls(content), vec!["http://example.com/a", "http://example.com/c"]);
+    }
+
+    #[test]
+    fn pls_with_gaps_and_odd_keys() {
+        let content = "\u{feff}[playlist]\nfile2 = http://example.com/b\nFILE5=http://example.com/e\nFileX=http://example.com/x\nFile=http://example.com/none\n";
+        assert_eq!(parse_pls(content), vec!["http://example.com/b", "http://example.com/e"]);
+    }
+
+    #[test]
+    fn pls_keeps_relative_entries() {
+        let content = "[playlist]\nFile1=stream.mp3\nFile2=/live/stream.aac\nFile3=http://mirror.example.org/stream\n";
+        assert_eq!(parse_pls(content), vec!["stream.mp3", "/live/stream.aac", "http://mirror.example.org/stream"]);
+
+        // They get resolved against the url of the playlist
+        let base = Url::parse("http://example.com/radio/listen.pls").unwrap();
+        let entries: Vec<String> = parse_playlist(PlaylistFormat::Pls, content, &base).into_iter().map(Url::into_string).collect();
+        assert_eq!(
+            entries,
+            vec!["http://example.com/radio/stream.mp3", "http://example.com/live/stream.aac", "http://mirror.example.org/stream"]
+        );
+    }
+
+    #[test]
+    fn m3u_with_bom_and_crlf() {
+        let content = "\u{feff}#EXTM3U\r\n#EXTINF:-1,Example Radio\r\nhttp://example.com/stream\r\n\r\n  relative/stream.ogg  \r\n# comment\r\n";
+        assert_eq!(parse_m3u(content), vec!["http://example.com/stream", "relative/stream.ogg"]);
+    }
+
+    #[test]
+    fn m3u_without_header() {
+        let content = "http://example.com/a\nhttp://example.com/b";
+        assert_eq!(parse_m3u(content), vec!["http://example.com/a", "http://example.com/b"]);
+    }
+
+    #[test]
+    fn xspf_locations() {
+        let content = r#"<?xml version="1.0" encoding="UTF-8"?>
+<playlist version="1" xmlns="http://xspf.org/ns/0/">
+  <trackList>
+    <track><title>Example</title><Location> http://example.com/stream?a=1&amp;b=2 </Location></track>
+    <track><location></location></track>
+    <track><location>stream.ogg</location></track>
+  </trackList>
+</playlist>"#;
+        assert_eq!(parse_xspf(content), vec!["http://example.com/stream?a=1&b=2", "stream.ogg"]);
+    }
+
+    #[test]
+    fn asx_refs() {
+        let content = "<ASX version=\"3.0\">\r\n<Entry><REF HREF=\"http://example.com/a\" /></Entry>\r\n<entry><ref href = 'mms://example.com/b'/></entry>\r\n<entry><ref href=http://example.com/unquoted /></entry>\r\n<entry><Ref href=\"relative.wma\"></Ref></entry>\r\n</ASX>";
+        assert_eq!(parse_asx(content), vec!["http://example.com/a", "mms://example.com/b", "relative.wma"]);
+    }
+
+    #[test]
+    fn format_from_content() {
+        assert_eq!(PlaylistFormat::from_content("\u{feff}[playlist]\r\nFile1=a"), Some(PlaylistFormat::Pls));
+        assert_eq!(PlaylistFormat::from_content("\u{feff}#EXTM3U\r\na"), Some(PlaylistFormat::M3u));
+        assert_eq!(PlaylistFormat::from_content("  <asx version=\"3.0\">"), Some(PlaylistFormat::Asx));
+        assert_eq!(PlaylistFormat::from_content("<?xml version=\"1.0\"?><playlist>"), Some(PlaylistFormat::Xspf));
+        assert_eq!(PlaylistFormat::from_content("<?xml version=\"1.0\"?><ASX>"), Some(PlaylistFormat::Asx));
+        assert_eq!(PlaylistFormat::from_content("ID3\u{3}"), None);
+    }
+
+    #[test]
+    fn hls_playlists_are_left_to_gstreamer() {
+        assert!(is_hls_playlist("#EXTM3U\n#EXT-X-VERSION:3\n#EXT-X-STREAM-INF:BANDWIDTH=128000\nchunklist.m3u8\n"));
+        assert!(!is_hls_playlist("#EXTM3U\n#EXTINF:-1,Example\nhttp://example.com/stream\n"));
+    }
+}
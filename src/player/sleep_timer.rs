@@ -0,0 +1,189 @@
+use glib::Sender;
+
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::app::Action;
+use crate::player::controller::ChromecastController;
+use crate::player::GstreamerBackend;
+use crate::settings::{Key, SettingsManager};
+
+const TICK_INTERVAL_MS: u32 = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SleepTimerMode {
+    Duration(Duration),
+    EndOfSong,
+}
+
+/// What the controllers show to the user.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SleepTimerState {
+    Inactive,
+    Remaining(Duration),
+    EndOfSong,
+    FadingOut,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Phase {
+    Inactive,
+    Countdown(Instant),
+    EndOfSong,
+    FadeOut(Instant),
+    // The stop action got sent, but the playback hasn't stopped yet
+    Finished,
+}
+
+/// Stops the playback after a given time, or at the end of the current song.
+/// Before the playback gets stopped, the volume fades out over `sleep-timer-fade` seconds.
+#[derive(Clone)]
+pub struct SleepTimer {
+    backend: Arc<Mutex<GstreamerBackend>>,
+    chromecast: ChromecastController,
+    sender: Sender<Action>,
+
+    phase: Rc<Cell<Phase>>,
+    ticking: Rc<Cell<bool>>,
+    state: Rc<Cell<SleepTimerState>>,
+    changed_callback: Rc<RefCell<Option<Box<Fn(&SleepTimerState)>>>>,
+}
+
+impl SleepTimer {
+    pub fn new(backend: Arc<Mutex<GstreamerBackend>>, chromecast: ChromecastController, sender: Sender<Action>) -> Self {
+        Self {
+            backend,
+            chromecast,
+            sender,
+            phase: Rc::new(Cell::new(Phase::Inactive)),
+            ticking: Rc::new(Cell::new(false)),
+            state: Rc::new(Cell::new(SleepTimerState::Inactive)),
+            changed_callback: Rc::new(RefCell::new(None)),
+        }
+    }
+
+    /// Gets called every time the displayed state changes (at most once per second).
+    pub fn connect_changed<F: Fn(&SleepTimerState) + 'static>(&self, callback: F) {
+        callback(&self.state.get());
+        *self.changed_callback.borrow_mut() = Some(Box::new(callback));
+    }
+
+    pub fn start(&self, mode: SleepTimerMode) {
+        debug!("Start sleep timer: {:?}", mode);
+        let phase = match mode {
+            SleepTimerMode::Duration(duration) => Phase::Countdown(Instant::now() + duration),
+            SleepTimerMode::EndOfSong => Phase::EndOfSong,
+        };
+
+        self.phase.set(phase);
+        self.set_fade(1.0);
+        self.update();
+    }
+
+    /// Adds `duration` to the remaining time. If the timer isn't counting down (anymore), it starts a new countdown.
+    pub fn extend(&self, duration: Duration) {
+        debug!("Extend sleep timer by {:?}", duration);
+        let phase = match self.phase.get() {
+            Phase::Countdown(deadline) => Phase::Countdown(deadline + duration),
+            _ => Phase::Countdown(Instant::now() + duration),
+        };
+
+        self.phase.set(phase);
+        self.set_fade(1.0);
+        self.update();
+    }
+
+    pub fn cancel(&self) {
+        if self.phase.get() == Phase::Inactive {
+            return;
+        }
+
+        debug!("Cancel sleep timer");
+        self.phase.set(Phase::Inactive);
+        self.set_fade(1.0);
+        self.update();
+    }
+
+    /// The song which the user hears has changed. With timeshift this is later than the title change of the stream.
+    pub fn song_changed(&self) {
+        if self.phase.get() == Phase::EndOfSong {
+            self.phase.set(Phase::FadeOut(Instant::now()));
+            self.update();
+        }
+    }
+
+    /// The playback got stopped. If we did that, the timer is done now.
+    pub fn playback_stopped(&self) {
+        if self.phase.get() == Phase::Finished {
+            self.cancel();
+        }
+    }
+
+    fn update(&self) {
+        let now = Instant::now();
+        let fade_duration = Duration::from_secs(SettingsManager::get_uint(Key::SleepTimerFade).into());
+
+        // Countdown is over -> start fading out
+        if let Phase::Countdown(deadline) = self.phase.get() {
+            if now >= deadline {
+                self.phase.set(Phase::FadeOut(now));
+            }
+        }
+
+        if let Phase::FadeOut(start) = self.phase.get() {
+            let elapsed = now.duration_since(start);
+            if elapsed >= fade_duration {
+                debug!("Sleep timer expired, stop playback");
+                self.phase.set(Phase::Finished);
+                self.sender.send(Action::PlaybackStop).unwrap();
+            } else {
+                // Quadratic curve, a linear one sounds like the volume drops suddenly at the end
+                let progress = elapsed.as_millis() as f64 / fade_duration.as_millis() as f64;
+                self.set_fade((1.0 - progress).powi(2));
+            }
+        }
+
+        let state = match self.phase.get() {
+            Phase::Inactive | Phase::Finished => SleepTimerState::Inactive,
+            // Round up, so the countdown never shows 0:00 while it's still running
+            Phase::Countdown(deadline) => SleepTimerState::Remaining(Duration::from_secs((deadline - now).as_millis() as u64 / 1000 + 1)),
+            Phase::EndOfSong => SleepTimerState::EndOfSong,
+            Phase::FadeOut(_) => SleepTimerState::FadingOut,
+        };
+        if self.state.get() != state {
+            self.state.set(state);
+            if let Some(callback) = self.changed_callback.borrow().as_ref() {
+                callback(&state);
+            }
+        }
+
+        self.ensure_ticking();
+    }
+
+    // The receiver plays the stream by itself, so it has to fade out as well
+    fn set_fade(&self, fade: f64) {
+        self.backend.lock().unwrap().set_fade(fade);
+        self.chromecast.set_fade(fade);
+    }
+
+    fn ensure_ticking(&self) {
+        let running = match self.phase.get() {
+            Phase::Inactive | Phase::Finished => false,
+            _ => true,
+        };
+        if !running || self.ticking.get() {
+            return;
+        }
+
+        self.ticking.set(true);
+        let timer = self.clone();
+        gtk::timeout_add(TICK_INTERVAL_MS, move || {
+            // One-shot timeout, `update` schedules the next one as long as the timer is running
+            timer.ticking.set(false);
+            timer.update();
+            glib::Continue(false)
+        });
+    }
+}
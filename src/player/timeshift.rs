@@ -0,0 +1,427 @@
+use gstreamer_app::{AppSink, AppSinkCallbacks, AppSrc, AppSrcCallbacks};
+
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::config;
+use crate::metadata::SongMetadata;
+use crate::settings::{Key, SettingsManager};
+
+// Raw audio format which gets stored in the timeshift buffer.
+// We store uncompressed audio, so that we can calculate the position of every sample.
+pub static TIMESHIFT_CAPS: &'static str = "audio/x-raw,format=S16LE,layout=interleaved,rate=44100,channels=2";
+const BYTES_PER_FRAME: u64 = 4; // 2 channels * 16 bit
+const BYTES_PER_SECOND: u64 = 44100 * BYTES_PER_FRAME;
+
+// How much data gets pushed into the playback pipeline at once (~100ms)
+const CHUNK_SIZE: usize = (BYTES_PER_SECOND / 10) as usize;
+
+//////////////////////////////////////////////////////////////////////////////////////////////////////////
+//                                                                                                      //
+//  # Timeshift                                                                                         //
+//                                                                                                      //
+//    ----------------              -----------------              -------------------                  //
+//   | appsink (live) | -- write -> | TimeshiftBuffer | -- read -> | appsrc (playback) |                //
+//    ----------------              -----------------              -------------------                  //
+//                                                                                                      //
+//  The live pipeline keeps writing into the buffer, even if the playback pipeline is paused.           //
+//  The appsrc tells us with "need-data" / "enough-data" whether it wants more data.                    //
+//                                                                                                      //
+//  Titles get remembered together with the live position where they changed, so the title which is     //
+//  currently audible can be shown. Recordings are taken from the live stream, so they use the live     //
+//  titles, and aren't affected by the delay.                                                           //
+//                                                                                                      //
+//////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Clone)]
+pub struct Timeshift {
+    buffer: Arc<Mutex<Option<TimeshiftBuffer>>>,
+    // Live position (bytes) where the title changed, the oldest first
+    titles: Arc<Mutex<VecDeque<(u64, SongMetadata)>>>,
+    need_data: Arc<AtomicBool>,
+    appsrc: AppSrc,
+}
+
+impl Timeshift {
+    pub fn new(appsink: &AppSink, appsrc: &AppSrc) -> Self {
+        let buffer: Arc<Mutex<Option<TimeshiftBuffer>>> = Arc::new(Mutex::new(None));
+        let need_data = Arc::new(AtomicBool::new(false));
+
+        // Write new live data into the buffer
+        let b = buffer.clone();
+        let nd = need_data.clone();
+        let src = appsrc.clone();
+        appsink.set_callbacks(
+            AppSinkCallbacks::new()
+                .new_sample(move |appsink| {
+                    let sample = match appsink.pull_sample() {
+                        Some(sample) => sample,
+                        None => return gstreamer::FlowReturn::Eos,
+                    };
+
+                    let mut buffer = b.lock().unwrap();
+                    if let (Some(buffer), Some(data)) = (buffer.as_mut(), sample.get_buffer()) {
+                        let map = data.map_readable().unwrap();
+                        if let Err(err) = buffer.write(map.as_slice()) {
+                            warn!("Could not write timeshift data: {}", err);
+                        }
+                        buffer.feed(&src, &nd);
+                    }
+                    gstreamer::FlowReturn::Ok
+                })
+                .build(),
+        );
+
+        // Playback pipeline wants to have more / no data anymore
+        let b = buffer.clone();
+        let nd = need_data.clone();
+        let nd2 = need_data.clone();
+        appsrc.set_callbacks(
+            AppSrcCallbacks::new()
+                .need_data(move |appsrc, _| {
+                    nd.store(true, Ordering::SeqCst);
+                    // The appsink callback feeds the appsrc anyway, if the buffer is currently locked
+                    if let Ok(mut buffer) = b.try_lock() {
+                        buffer.as_mut().map(|buffer| buffer.feed(appsrc, &nd));
+                    }
+                })
+                .enough_data(move |_| nd2.store(false, Ordering::SeqCst))
+                .build(),
+        );
+
+        Self {
+            buffer,
+            titles: Arc::new(Mutex::new(VecDeque::new())),
+            need_data,
+            appsrc: appsrc.clone(),
+        }
+    }
+
+    pub fn start(&self) {
+        let window = Duration::from_secs(u64::from(SettingsManager::get_uint(Key::TimeshiftWindow)) * 60);
+        match TimeshiftBuffer::new(window) {
+            Ok(buffer) => *self.buffer.lock().unwrap() = Some(buffer),
+            Err(err) => warn!("Could not create timeshift buffer: {}", err),
+        }
+    }
+
+    pub fn stop(&self) {
+        self.need_data.store(false, Ordering::SeqCst);
+        *self.buffer.lock().unwrap() = None;
+        self.titles.lock().unwrap().clear();
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.buffer.lock().unwrap().is_some()
+    }
+
+    /// Returns the delay to the live stream, and how much of the stream is buffered.
+    pub fn get_position(&self) -> Option<(Duration, Duration)> {
+        self.buffer.lock().unwrap().as_ref().map(|buffer| (buffer.get_delay(), buffer.get_buffered()))
+    }
+
+    /// Remembers that `song` starts at the current live position. Returns false if nothing gets buffered.
+    pub fn add_title(&self, song: SongMetadata) -> bool {
+        match self.buffer.lock().unwrap().as_ref() {
+            Some(buffer) => {
+                self.titles.lock().unwrap().push_back((buffer.write_pos, song));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The title at the playback position, `None` if the playback hasn't reached the first title yet.
+    pub fn get_played_title(&self) -> Option<SongMetadata> {
+        let buffer = self.buffer.lock().unwrap();
+        let buffer = buffer.as_ref()?;
+        let mut titles = self.titles.lock().unwrap();
+
+        // Titles whose data got overwritten can't be played again, except the one of the oldest data
+        let oldest_pos = buffer.write_pos - buffer.write_pos.min(buffer.capacity);
+        while titles.len() > 1 && titles[1].0 <= oldest_pos {
+            titles.pop_front();
+        }
+
+        titles.iter().rev().find(|(pos, _)| *pos <= buffer.read_pos).map(|(_, song)| song.clone())
+    }
+
+    pub fn set_delay(&self, delay: Duration) {
+        let mut buffer = self.buffer.lock().unwrap();
+        buffer.as_mut().map(|buffer| {
+            buffer.set_delay(delay);
+            buffer.feed(&self.appsrc, &self.need_data);
+        });
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////////////////////////////////
+//                                                                                                      //
+//  # Timeshift Buffer                                                                                  //
+//                                                                                                      //
+//  A ring buffer on disk, which contains the last minutes of the live stream.                          //
+//                                                                                                      //
+//            oldest data                       read_pos                            write_pos           //
+//   |------------|----------------------------------|-----------------------------------|              //
+//                 <--------------------------- buffered ------------------------------->               //
+//                                                    <------------- delay ------------->               //
+//                                                                                                      //
+//  The positions are absolute byte counters, the position inside the file is `pos % capacity`.         //
+//  If the reader falls too far behind, the oldest data gets overwritten and the reader gets moved.     //
+//                                                                                                      //
+//////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+pub struct TimeshiftBuffer {
+    file: File,
+    path: PathBuf,
+    capacity: u64,
+
+    write_pos: u64,
+    read_pos: u64,
+    // Amount of bytes pushed into the playback pipeline, used for the buffer timestamps
+    pushed: u64,
+}
+
+impl TimeshiftBuffer {
+    pub fn new(window: Duration) -> std::io::Result<Self> {
+        let path = Self::get_path();
+        let file = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(&path)?;
+        let capacity = Self::duration_to_bytes(window).max(BYTES_PER_SECOND);
+        debug!("Timeshift buffer: {:?} ({} bytes)", path, capacity);
+
+        Ok(Self {
+            file,
+            path,
+            capacity,
+            write_pos: 0,
+            read_pos: 0,
+            pushed: 0,
+        })
+    }
+
+    pub fn write(&mut self, data: &[u8]) -> std::io::Result<()> {
+        let mut data = data;
+
+        // Only the last `capacity` bytes would survive anyway
+        if data.len() as u64 > self.capacity {
+            let skip = data.len() - self.capacity as usize;
+            self.write_pos += skip as u64;
+            data = &data[skip..];
+        }
+
+        while !data.is_empty() {
+            let offset = self.write_pos % self.capacity;
+            let len = (data.len() as u64).min(self.capacity - offset) as usize;
+
+            self.file.seek(SeekFrom::Start(offset))?;
+            self.file.write_all(&data[..len])?;
+
+            self.write_pos += len as u64;
+            data = &data[len..];
+        }
+
+        // Move reader forward if we have overwritten data which was not played yet
+        if self.write_pos - self.read_pos > self.capacity {
+            self.read_pos = self.write_pos - self.capacity;
+        }
+
+        Ok(())
+    }
+
+    pub fn read(&mut self, max_len: usize) -> std::io::Result<Vec<u8>> {
+        let available = (self.write_pos - self.read_pos) as usize;
+        let mut result = vec![0; max_len.min(available)];
+
+        let mut done = 0;
+        while done < result.len() {
+            let offset = self.read_pos % self.capacity;
+            let len = ((result.len() - done) as u64).min(self.capacity - offset) as usize;
+
+            self.file.seek(SeekFrom::Start(offset))?;
+            self.file.read_exact(&mut result[done..done + len])?;
+
+            self.read_pos += len as u64;
+            done += len;
+        }
+
+        Ok(result)
+    }
+
+    /// How far the reader is behind the live stream.
+    pub fn get_delay(&self) -> Duration {
+        Self::bytes_to_duration(self.write_pos - self.read_pos)
+    }
+
+    /// How much of the live stream is available on disk.
+    pub fn get_buffered(&self) -> Duration {
+        Self::bytes_to_duration(self.write_pos.min(self.capacity))
+    }
+
+    /// Moves the reader, so that it is `delay` behind the live stream.
+    pub fn set_delay(&mut self, delay: Duration) {
+        let delay = Self::duration_to_bytes(delay).min(self.write_pos.min(self.capacity));
+        self.read_pos = self.write_pos - delay;
+        debug!("Timeshift delay: {:?}", self.get_delay());
+    }
+
+    /// Pushes data into the playback pipeline, as long as it needs data and we have something to read.
+    pub fn feed(&mut self, appsrc: &AppSrc, need_data: &AtomicBool) {
+        while need_data.load(Ordering::SeqCst) {
+            let data = match self.read(CHUNK_SIZE) {
+                Ok(data) => data,
+                Err(err) => {
+                    warn!("Could not read timeshift data: {}", err);
+                    return;
+                }
+            };
+
+            // We're live, there's nothing more to read yet
+            if data.is_empty() {
+                return;
+            }
+
+            let len = data.len() as u64;
+            let mut buffer = gstreamer::Buffer::from_mut_slice(data).unwrap();
+            {
+                let buffer = buffer.get_mut().unwrap();
+                buffer.set_pts(Self::bytes_to_clocktime(self.pushed));
+                buffer.set_duration(Self::bytes_to_clocktime(len));
+            }
+            self.pushed += len;
+
+            if appsrc.push_buffer(buffer) != gstreamer::FlowReturn::Ok {
+                return;
+            }
+        }
+    }
+
+    fn get_path() -> PathBuf {
+        let mut path = glib::get_user_cache_dir().unwrap();
+        path.push(config::NAME);
+        std::fs::create_dir_all(path.clone()).expect("Could not create path for timeshift buffer");
+
+        path.push("timeshift");
+        path
+    }
+
+    fn duration_to_bytes(duration: Duration) -> u64 {
+        let bytes = duration.as_secs() * BYTES_PER_SECOND + u64::from(duration.subsec_millis()) * BYTES_PER_SECOND / 1000;
+        // Never split a frame
+        bytes - (bytes % BYTES_PER_FRAME)
+    }
+
+    fn bytes_to_duration(bytes: u64) -> Duration {
+        Duration::from_millis(bytes * 1000 / BYTES_PER_SECOND)
+    }
+
+    fn bytes_to_clocktime(bytes: u64) -> gstreamer::ClockTime {
+        gstreamer::ClockTime::from_nseconds(bytes * 1_000_000_000 / BYTES_PER_SECOND)
+    }
+}
+
+impl Drop for TimeshiftBuffer {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Every test gets a file of its own, the tests run in parallel
+    fn buffer(name: &str, capacity: u64) -> TimeshiftBuffer {
+        let path = std::env::temp_dir().join(format!("timeshift-test-{}-{}", std::process::id(), name));
+        let file = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(&path).unwrap();
+        TimeshiftBuffer {
+            file,
+            path,
+            capacity,
+            write_pos: 0,
+            read_pos: 0,
+            pushed: 0,
+        }
+    }
+
+    fn bytes(range: std::ops::Range<u8>) -> Vec<u8> {
+        range.collect()
+    }
+
+    #[test]
+    fn read_wraps_around() {
+        let mut buffer = buffer("wrap", 16);
+        buffer.write(&bytes(0..10)).unwrap();
+        assert_eq!(buffer.read(10).unwrap(), bytes(0..10));
+
+        // The second write continues at the start of the file
+        buffer.write(&bytes(10..22)).unwrap();
+        assert_eq!(buffer.write_pos % buffer.capacity, 6);
+        assert_eq!(buffer.read(100).unwrap(), bytes(10..22));
+        assert_eq!(buffer.read(100).unwrap(), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn overwritten_data_moves_the_reader() {
+        let mut buffer = buffer("overwrite", 16);
+        buffer.write(&bytes(0..10)).unwrap();
+        buffer.write(&bytes(10..20)).unwrap();
+
+        // The first 4 bytes are gone
+        assert_eq!(buffer.read_pos, 4);
+        assert_eq!(buffer.read(100).unwrap(), bytes(4..20));
+    }
+
+    #[test]
+    fn write_more_than_capacity() {
+        let mut buffer = buffer("large", 16);
+        buffer.write(&bytes(0..40)).unwrap();
+
+        assert_eq!(buffer.write_pos, 40);
+        assert_eq!(buffer.read(100).unwrap(), bytes(24..40));
+    }
+
+    #[test]
+    fn position_and_buffered_length() {
+        let mut buffer = buffer("position", 2 * BYTES_PER_SECOND);
+        buffer.write(&vec![0; BYTES_PER_SECOND as usize]).unwrap();
+        assert_eq!(buffer.get_buffered(), Duration::from_secs(1));
+        assert_eq!(buffer.get_delay(), Duration::from_secs(1));
+
+        buffer.read(BYTES_PER_SECOND as usize / 2).unwrap();
+        assert_eq!(buffer.get_delay(), Duration::from_millis(500));
+
+        // Only the window is buffered, even if more has been written
+        buffer.write(&vec![0; 2 * BYTES_PER_SECOND as usize]).unwrap();
+        assert_eq!(buffer.get_buffered(), Duration::from_secs(2));
+        assert_eq!(buffer.get_delay(), Duration::from_secs(2));
+    }
+
+    #[test]
+    fn delay_gets_clamped() {
+        let mut buffer = buffer("clamp", 2 * BYTES_PER_SECOND);
+        buffer.write(&vec![0; BYTES_PER_SECOND as usize]).unwrap();
+
+        // Not more than what has been written so far
+        buffer.set_delay(Duration::from_secs(10));
+        assert_eq!(buffer.read_pos, 0);
+        assert_eq!(buffer.get_delay(), Duration::from_secs(1));
+
+        // Not more than the window
+        buffer.write(&vec![0; 3 * BYTES_PER_SECOND as usize]).unwrap();
+        buffer.set_delay(Duration::from_secs(10));
+        assert_eq!(buffer.get_delay(), Duration::from_secs(2));
+        assert_eq!(buffer.read_pos, buffer.write_pos - buffer.capacity);
+
+        buffer.set_delay(Duration::from_millis(250));
+        assert_eq!(buffer.get_delay(), Duration::from_millis(250));
+        assert_eq!(buffer.read_pos % BYTES_PER_FRAME, 0);
+
+        buffer.set_delay(Duration::from_secs(0));
+        assert_eq!(buffer.read(100).unwrap(), Vec::<u8>::new());
+    }
+}
@@ -0,0 +1,269 @@
+use glib::Sender;
+use gstreamer::prelude::*;
+use gstreamer::{Element, ElementFactory, Pad, PadProbeId, Pipeline, SeekFlags, SeekType, State, TagList, TagMergeMode, TagSetter};
+
+use std::cell::{Cell, RefCell};
+use std::fs;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::app::Action;
+use crate::filename_template::FilenameTemplate;
+use crate::player::recording_format::{RecordingError, RecordingFormat};
+use crate::song::Song;
+
+//////////////////////////////////////////////////////////////////////////////////////////////////////////
+//                                                                                                      //
+//  # Track Splitter                                                                                    //
+//                                                                                                      //
+//    ---------      -----------      --------------      ----------------      ---------------         //
+//   | filesrc | -> | decodebin | -> | audioconvert | -> | encoder, muxer | -> | filesink [1] |          //
+//    ---------      -----------      --------------      ----------------      ---------------         //
+//                                                                                                      //
+//  Every chapter of a continuous recording gets a pipeline of its own, one after another. The pipeline //
+//  seeks to the start of the chapter and stops at the start of the next one (segment stop -> EOS).     //
+//  Until the seek is done, the src pad of decodebin is blocked, so the beginning of the file never     //
+//  reaches the encoder.                                                                                //
+//                                                                                                      //
+//  [1] "NN Title.ext" in a folder named after the recording                                            //
+//                                                                                                      //
+//////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Clone)]
+pub struct TrackSplitter {
+    song: Song,
+    folder: PathBuf,
+    // The tracks keep the format of the recording, if we can encode it
+    format: RecordingFormat,
+
+    // Index of the chapter which is currently written
+    track: Rc<Cell<usize>>,
+    pipeline: Rc<RefCell<Option<Pipeline>>>,
+    // The src pad of decodebin and its block probe, which gets removed after the seek
+    blocked_pad: Arc<Mutex<Option<(Pad, PadProbeId)>>>,
+    // The first data waits at the blocked pad, so the file is ready for seeking
+    blocking: Arc<AtomicBool>,
+    seeked: Rc<Cell<bool>>,
+    finished: Rc<Cell<bool>>,
+    failed: Rc<Cell<bool>>,
+    sender: Sender<Action>,
+}
+
+impl TrackSplitter {
+    pub fn new(song: Song, sender: Sender<Action>) -> Self {
+        let mut folder = FilenameTemplate::get_music_dir();
+        folder.push(FilenameTemplate::sanitize(&song.title));
+        let folder = Song::get_unique_path_without_extension(folder);
+
+        let extension = song.path.extension().map(|extension| extension.to_string_lossy().to_string()).unwrap_or_default();
+        let format = RecordingFormat::from_extension(&extension).unwrap_or_else(RecordingFormat::load);
+
+        Self {
+            song,
+            folder,
+            format,
+            track: Rc::new(Cell::new(0)),
+            pipeline: Rc::new(RefCell::new(None)),
+            blocked_pad: Arc::new(Mutex::new(None)),
+            blocking: Arc::new(AtomicBool::new(false)),
+            seeked: Rc::new(Cell::new(false)),
+            finished: Rc::new(Cell::new(false)),
+            failed: Rc::new(Cell::new(false)),
+            sender,
+        }
+    }
+
+    /// Writes every chapter into a file of its own, in the background. `finished` gets called with the result.
+    pub fn start<F: Fn(bool) + 'static>(&self, finished: F) {
+        info!("Split \"{}\" into {} tracks ({:?})", self.song.title, self.song.chapters.len(), self.folder);
+        let result = fs::create_dir_all(&self.folder)
+            .map_err(|err| err.to_string())
+            .and_then(|_| self.start_track(0).map_err(|err| err.to_string()));
+        if let Err(err) = result {
+            warn!("Could not split recording: {}", err);
+            self.notify(false);
+            finished(false);
+            return;
+        }
+
+        let splitter = self.clone();
+        gtk::timeout_add(250, move || {
+            splitter.process_messages();
+            if splitter.finished.get() {
+                let success = !splitter.failed.get();
+                splitter.notify(success);
+                finished(success);
+            }
+            glib::Continue(!splitter.finished.get())
+        });
+    }
+
+    fn notify(&self, success: bool) {
+        let message = match success {
+            true => format!("Split \"{}\" into {} tracks.", self.song.title, self.song.chapters.len()),
+            false => format!("Could not split \"{}\" into tracks.", self.song.title),
+        };
+        self.sender.send(Action::ViewShowNotification(message)).unwrap();
+    }
+
+    fn process_messages(&self) {
+        let bus = match self.pipeline.borrow().as_ref() {
+            Some(pipeline) => pipeline.get_bus().unwrap(),
+            None => return,
+        };
+
+        // The file is ready, now we can jump to the chapter
+        if !self.seeked.get() && self.blocking.load(Ordering::SeqCst) {
+            self.seeked.set(true);
+            if let Err(err) = self.seek_to_track() {
+                warn!("Could not seek to track {}: {}", self.track.get() + 1, err);
+                self.fail();
+                return;
+            }
+        }
+
+        while let Some(message) = bus.pop() {
+            match message.view() {
+                gstreamer::MessageView::Eos(_) => {
+                    if let Some(pipeline) = self.pipeline.borrow_mut().take() {
+                        let _ = pipeline.set_state(State::Null);
+                    }
+
+                    let next = self.track.get() + 1;
+                    if next >= self.song.chapters.len() {
+                        self.finished.set(true);
+                    } else if let Err(err) = self.start_track(next) {
+                        warn!("Could not split track {}: {}", next + 1, err);
+                        self.fail();
+                    }
+                    return;
+                }
+                gstreamer::MessageView::Error(err) => {
+                    warn!("Could not split track {}: {}", self.track.get() + 1, err.get_error());
+                    self.fail();
+                    return;
+                }
+                _ => (),
+            }
+        }
+    }
+
+    fn fail(&self) {
+        if let Some(pipeline) = self.pipeline.borrow_mut().take() {
+            let _ = pipeline.set_state(State::Null);
+        }
+        self.failed.set(true);
+        self.finished.set(true);
+    }
+
+    fn start_track(&self, track: usize) -> Result<(), RecordingError> {
+        self.track.set(track);
+        self.seeked.set(false);
+        self.blocking.store(false, Ordering::SeqCst);
+        *self.blocked_pad.lock().unwrap() = None;
+
+        let pipeline = self.create_pipeline(track)?;
+        // Paused first, seeking needs the decoded file
+        pipeline.set_state(State::Paused).map_err(|err| RecordingError::Pipeline(err.to_string()))?;
+        *self.pipeline.borrow_mut() = Some(pipeline);
+        Ok(())
+    }
+
+    fn seek_to_track(&self) -> Result<(), RecordingError> {
+        let pipeline = self.pipeline.borrow().clone().unwrap();
+        let (pad, probe_id) = self.blocked_pad.lock().unwrap().take().unwrap();
+        let track = self.track.get();
+        let start = gstreamer::ClockTime::from_nseconds(self.song.chapters[track].start.as_nanos() as u64);
+        // The last track ends with the file
+        let (stop_type, stop) = match self.song.chapters.get(track + 1) {
+            Some(next) => (SeekType::Set, gstreamer::ClockTime::from_nseconds(next.start.as_nanos() as u64)),
+            None => (SeekType::None, gstreamer::ClockTime::none()),
+        };
+
+        // The pipeline can't be seeked before it's prerolled, so the seek goes to decodebin directly.
+        // The flush releases the blocked data, and everything after it starts at the chapter.
+        let seek = gstreamer::Event::new_seek(1.0, SeekFlags::FLUSH | SeekFlags::ACCURATE, SeekType::Set, start, stop_type, stop).build();
+        let seeked = pad.send_event(seek);
+        pad.remove_probe(probe_id);
+        if !seeked {
+            return Err(RecordingError::Pipeline("decodebin refused the seek".to_string()));
+        }
+        pipeline.set_state(State::Playing).map_err(|err| RecordingError::Pipeline(err.to_string()))?;
+        Ok(())
+    }
+
+    fn create_pipeline(&self, track: usize) -> Result<Pipeline, RecordingError> {
+        let chapter = &self.song.chapters[track];
+        let mut path = self.folder.clone();
+        path.push(format!("{:02} {}", track + 1, FilenameTemplate::sanitize(&chapter.get_display_title())));
+        FilenameTemplate::add_extension(&mut path, self.format.get_extension());
+
+        let filesrc = Self::make("filesrc")?;
+        filesrc.set_property("location", &self.song.path.to_string_lossy().to_string()).unwrap();
+        let decodebin = Self::make("decodebin")?;
+        let convert = Self::make("audioconvert")?;
+        let resample = Self::make("audioresample")?;
+        let filesink = Self::make("filesink")?;
+        filesink.set_property("location", &path.to_string_lossy().to_string()).unwrap();
+
+        let mut elements = vec![convert.clone(), resample];
+        elements.extend(self.format.create_elements()?);
+
+        // Every track gets the tags of its chapter, and the recording is the album
+        let mut tags = TagList::new();
+        {
+            let tags = tags.get_mut().unwrap();
+            let mode = TagMergeMode::Append;
+            tags.add::<gstreamer::tags::Title>(&chapter.title.as_str(), mode);
+            if let Some(artist) = chapter.artist.as_ref() {
+                tags.add::<gstreamer::tags::Artist>(&artist.as_str(), mode);
+            }
+            tags.add::<gstreamer::tags::Album>(&self.song.title.as_str(), mode);
+            tags.add::<gstreamer::tags::TrackNumber>(&(track as u32 + 1), mode);
+            tags.add::<gstreamer::tags::TrackCount>(&(self.song.chapters.len() as u32), mode);
+        }
+        for element in &elements {
+            if let Some(tag_setter) = element.dynamic_cast_ref::<TagSetter>() {
+                tag_setter.merge_tags(&tags, TagMergeMode::Replace);
+                tag_setter.set_tag_merge_mode(TagMergeMode::ReplaceAll);
+            }
+        }
+
+        let pipeline = Pipeline::new("splitter");
+        let mut chain: Vec<&Element> = elements.iter().collect();
+        chain.push(&filesink);
+        pipeline.add_many(&[&filesrc, &decodebin]).map_err(|err| RecordingError::Pipeline(err.to_string()))?;
+        pipeline.add_many(&chain).map_err(|err| RecordingError::Pipeline(err.to_string()))?;
+        filesrc.link(&decodebin).map_err(|err| RecordingError::Pipeline(err.to_string()))?;
+        Element::link_many(&chain).map_err(|err| RecordingError::Pipeline(err.to_string()))?;
+
+        // decodebin creates its pad, as soon as it knows the format of the file
+        let blocked_pad = self.blocked_pad.clone();
+        let blocking = self.blocking.clone();
+        decodebin.connect_pad_added(move |_, src_pad| {
+            let sink_pad = convert.get_static_pad("sink").expect("Failed to get static sink pad");
+            if sink_pad.is_linked() {
+                return;
+            }
+
+            // Events pass, so the caps get negotiated, but the data has to wait for the seek
+            let b = blocking.clone();
+            let probe_id = src_pad.add_probe(gstreamer::PadProbeType::BLOCK | gstreamer::PadProbeType::BUFFER, move |_, _| {
+                b.store(true, Ordering::SeqCst);
+                gstreamer::PadProbeReturn::Ok
+            });
+            if let Some(probe_id) = probe_id {
+                *blocked_pad.lock().unwrap() = Some((src_pad.clone(), probe_id));
+            }
+            let _ = src_pad.link(&sink_pad);
+        });
+
+        Ok(pipeline)
+    }
+
+    fn make(name: &str) -> Result<Element, RecordingError> {
+        ElementFactory::make(name, name).ok_or_else(|| RecordingError::MissingElement(name.to_string()))
+    }
+}
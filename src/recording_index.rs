@@ -0,0 +1,79 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::json_file::JsonFile;
+use crate::song::Song;
+
+lazy_static! {
+    static ref INDEX: JsonFile<Vec<IndexEntry>> = JsonFile::new("recording_index.json");
+}
+
+// Copies with less difference than this are equally good
+const BITRATE_TOLERANCE: u32 = 8;
+const DURATION_TOLERANCE_SECS: u64 = 2;
+
+/// A song which the user has saved.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IndexEntry {
+    pub key: String,
+    pub title: String,
+    pub artist: Option<String>,
+    pub path: PathBuf,
+    pub duration: Duration,
+    // Average bitrate in kbit/s
+    pub bitrate: u32,
+}
+
+impl IndexEntry {
+    /// A higher bitrate wins, otherwise the longer (more complete) copy.
+    pub fn is_worse_than(&self, song: &Song) -> bool {
+        let bitrate = song.get_bitrate();
+        if bitrate > self.bitrate + BITRATE_TOLERANCE {
+            return true;
+        }
+        bitrate + BITRATE_TOLERANCE >= self.bitrate && song.duration > self.duration + Duration::from_secs(DURATION_TOLERANCE_SECS)
+    }
+}
+
+/// Keeps track of the saved songs, so the same song doesn't get saved over and over again.
+pub struct RecordingIndex {}
+
+impl RecordingIndex {
+    /// Stations write the same song differently, e.g. "The Beatles - Let It Be" and "the beatles – let it be!".
+    pub fn get_key(title: &str, artist: &Option<String>) -> String {
+        format!("{}\n{}", artist.as_ref().map(|artist| Self::normalize(artist)).unwrap_or_default(), Self::normalize(title))
+    }
+
+    /// Lowercase words without punctuation, separated by single spaces.
+    pub fn normalize(text: &str) -> String {
+        let text: String = text.to_lowercase().chars().map(|c| if c.is_alphanumeric() { c } else { ' ' }).collect();
+        text.split_whitespace().collect::<Vec<&str>>().join(" ")
+    }
+
+    /// The saved copy of the song, if its file still exists.
+    pub fn find(title: &str, artist: &Option<String>) -> Option<IndexEntry> {
+        let key = Self::get_key(title, artist);
+        INDEX.with(|entries| entries.iter().find(|entry| entry.key == key && entry.path.exists()).cloned())
+    }
+
+    /// Remembers `path` as the saved copy of the song, instead of the previous one.
+    pub fn add(song: &Song, path: PathBuf) {
+        let key = Self::get_key(&song.title, &song.artist);
+        let result = INDEX.update(|entries| {
+            entries.retain(|entry| entry.key != key && entry.path.exists());
+            entries.push(IndexEntry {
+                key,
+                title: song.title.clone(),
+                artist: song.artist.clone(),
+                path,
+                duration: song.duration,
+                bitrate: song.get_bitrate(),
+            });
+            true
+        });
+
+        if let Err(err) = result {
+            warn!("Could not save recording index: {}", err);
+        }
+    }
+}
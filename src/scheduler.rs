@@ -0,0 +1,459 @@
+use chrono::{DateTime, Datelike, Local};
+use glib::Sender;
+use gstreamer::prelude::*;
+use gstreamer::{ElementFactory, State};
+use rustio::Station;
+use uuid::Uuid;
+
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::time::Instant;
+
+use crate::app::Action;
+use crate::config;
+use crate::json_file::JsonFile;
+use crate::player::headless_recorder::HeadlessRecorder;
+use crate::player::recording_mode::RecordingMode;
+
+lazy_static! {
+    static ref ALARMS: JsonFile<Vec<Alarm>> = JsonFile::new("alarms.json");
+    static ref RECORDINGS: JsonFile<Vec<ScheduledRecording>> = JsonFile::new("scheduled_recordings.json");
+}
+
+const CHECK_INTERVAL_SECS: u32 = 10;
+// Alarms which got missed by more than this (e.g. the computer was suspended) don't ring anymore
+const MAX_DELAY_MINUTES: i64 = 10;
+// How long we wait for the stream, before the fallback tone starts
+const FALLBACK_TIMEOUT_SECS: u32 = 30;
+// The fallback tone stops on its own after this time
+const TONE_TIMEOUT_SECS: u32 = 600;
+const RAMP_INTERVAL_MS: u32 = 500;
+
+pub static WEEKDAY_NAMES: [&str; 7] = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Alarm {
+    pub id: String,
+    pub station: Station,
+    pub hour: u32,
+    pub minute: u32,
+    // Days from Monday (0 = Monday, 6 = Sunday)
+    pub weekdays: Vec<u32>,
+    pub enabled: bool,
+    // Volume at the end of the ramp-up (0.0 - 1.0)
+    pub volume: f64,
+    // Duration of the ramp-up in seconds
+    pub ramp: u32,
+}
+
+impl Alarm {
+    pub fn new(station: Station) -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
+            station,
+            hour: 7,
+            minute: 0,
+            weekdays: vec![0, 1, 2, 3, 4],
+            enabled: true,
+            volume: 0.7,
+            ramp: 60,
+        }
+    }
+
+    /// The first time after `after`, at which the alarm rings.
+    pub fn get_next_occurrence(&self, after: DateTime<Local>) -> Option<DateTime<Local>> {
+        get_next_occurrence(&self.weekdays, self.hour, self.minute, after)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScheduledRecording {
+    pub id: String,
+    pub station: Station,
+    pub hour: u32,
+    pub minute: u32,
+    // An end before the start means the recording ends on the next day
+    pub end_hour: u32,
+    pub end_minute: u32,
+    // Days from Monday (0 = Monday, 6 = Sunday), on which the recording starts
+    pub weekdays: Vec<u32>,
+    pub mode: RecordingMode,
+    pub enabled: bool,
+}
+
+impl ScheduledRecording {
+    pub fn new(station: Station) -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
+            station,
+            hour: 20,
+            minute: 0,
+            end_hour: 22,
+            end_minute: 0,
+            weekdays: vec![4],
+            mode: RecordingMode::Continuous,
+            enabled: true,
+        }
+    }
+
+    pub fn get_duration(&self) -> chrono::Duration {
+        let start = i64::from(self.hour * 60 + self.minute);
+        let end = i64::from(self.end_hour * 60 + self.end_minute);
+        let minutes = if end > start { end - start } else { end - start + 24 * 60 };
+        chrono::Duration::minutes(minutes)
+    }
+
+    /// The next start of the recording after `after`.
+    pub fn get_next_occurrence(&self, after: DateTime<Local>) -> Option<DateTime<Local>> {
+        get_next_occurrence(&self.weekdays, self.hour, self.minute, after)
+    }
+
+    /// If the recording should be running at `now`, the time when it ends.
+    fn get_current_end(&self, now: DateTime<Local>) -> Option<DateTime<Local>> {
+        let duration = self.get_duration();
+        let start = self.get_next_occurrence(now - duration)?;
+        if start <= now {
+            Some(start + duration)
+        } else {
+            None
+        }
+    }
+}
+
+/// The first time after `after`, which is on one of the `weekdays` at `hour`:`minute`.
+fn get_next_occurrence(weekdays: &[u32], hour: u32, minute: u32, after: DateTime<Local>) -> Option<DateTime<Local>> {
+    // One week + today, so every weekday is included at least once
+    for day in 0..8 {
+        let date = after.date() + chrono::Duration::days(day);
+        if !weekdays.contains(&date.weekday().num_days_from_monday()) {
+            continue;
+        }
+
+        // The time can be missing because of a DST change
+        match date.and_hms_opt(hour, minute, 0) {
+            Some(time) if time > after => return Some(time),
+            _ => (),
+        }
+    }
+    None
+}
+
+/// The next enabled alarm which is going to ring, and when.
+pub fn get_next_alarm(alarms: &[Alarm]) -> Option<(Alarm, DateTime<Local>)> {
+    let now = Local::now();
+    alarms
+        .iter()
+        .filter(|alarm| alarm.enabled)
+        .filter_map(|alarm| alarm.get_next_occurrence(now).map(|time| (alarm.clone(), time)))
+        .min_by_key(|(_, time)| *time)
+}
+
+//////////////////////////////////////////////////////////////////////////////////////////////////////////
+//                                                                                                      //
+//  # Scheduler                                                                                         //
+//                                                                                                      //
+//  Checks every few seconds whether an alarm is due. If so:                                            //
+//                                                                                                      //
+//    1. The alarm volume gets set to 0, and the station starts playing (PlaybackSetStation / Start)    //
+//    2. The alarm volume ramps up to the volume of the alarm. It overrides the volume of the user,     //
+//       which gets restored as soon as the alarm is silenced (PlaybackSetAlarmVolume).                 //
+//    3. After FALLBACK_TIMEOUT_SECS, the App checks if the stream is actually playing                  //
+//       (SchedulerCheckAlarm). If not, the bundled alarm tone gets played instead.                     //
+//                                                                                                      //
+//  Stopping / pausing the playback silences the alarm (SchedulerStopAlarm).                            //
+//                                                                                                      //
+//  Scheduled recordings don't touch the playback. Each of them gets its own HeadlessRecorder,          //
+//  which runs as long as the current time is between the start and the end of the recording.           //
+//                                                                                                      //
+//////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+pub struct Scheduler {
+    alarms: Rc<RefCell<Vec<Alarm>>>,
+    // Alarms between the last check and now are due
+    last_check: Rc<Cell<DateTime<Local>>>,
+    // Alarm which is currently ringing
+    ringing: Rc<RefCell<Option<Alarm>>>,
+    tone: AlarmTone,
+
+    recordings: Rc<RefCell<Vec<ScheduledRecording>>>,
+    // Running recorders by the id of their scheduled recording, with the time when they end
+    recorders: Rc<RefCell<HashMap<String, (HeadlessRecorder, DateTime<Local>)>>>,
+
+    sender: Sender<Action>,
+}
+
+impl Scheduler {
+    pub fn new(sender: Sender<Action>) -> Self {
+        let alarms = ALARMS.with(|alarms| alarms.clone());
+        let recordings = RECORDINGS.with(|recordings| recordings.clone());
+
+        let scheduler = Self {
+            alarms: Rc::new(RefCell::new(alarms)),
+            last_check: Rc::new(Cell::new(Local::now())),
+            ringing: Rc::new(RefCell::new(None)),
+            tone: AlarmTone::new(),
+            recordings: Rc::new(RefCell::new(recordings)),
+            recorders: Rc::new(RefCell::new(HashMap::new())),
+            sender,
+        };
+
+        scheduler.setup_timer();
+        scheduler
+    }
+
+    pub fn get_alarms(&self) -> Vec<Alarm> {
+        self.alarms.borrow().clone()
+    }
+
+    pub fn set_alarms(&self, alarms: Vec<Alarm>) {
+        debug!("Set {} alarm(s)", alarms.len());
+        *self.alarms.borrow_mut() = alarms;
+
+        if let Err(error) = ALARMS.set(self.alarms.borrow().clone()) {
+            let message = format!("Could not write alarms: {}", error.to_string());
+            self.sender.send(Action::ViewShowNotification(message)).unwrap();
+        }
+    }
+
+    pub fn get_recordings(&self) -> Vec<ScheduledRecording> {
+        self.recordings.borrow().clone()
+    }
+
+    pub fn set_recordings(&self, recordings: Vec<ScheduledRecording>) {
+        debug!("Set {} scheduled recording(s)", recordings.len());
+        *self.recordings.borrow_mut() = recordings;
+
+        if let Err(error) = RECORDINGS.set(self.recordings.borrow().clone()) {
+            let message = format!("Could not write scheduled recordings: {}", error.to_string());
+            self.sender.send(Action::ViewShowNotification(message)).unwrap();
+        }
+
+        // The changed recording could be running right now
+        Self::update_recordings(&self.recordings, &self.recorders, &self.sender);
+    }
+
+    /// The station of the ringing alarm doesn't play, so we play the bundled alarm tone instead.
+    pub fn play_fallback_tone(&self) {
+        if let Some(alarm) = self.ringing.borrow().as_ref() {
+            warn!("Station \"{}\" doesn't play, use the alarm tone instead", alarm.station.name);
+            self.tone.set_volume(alarm.volume);
+            self.tone.play();
+        }
+    }
+
+    pub fn is_ringing(&self) -> bool {
+        self.ringing.borrow().is_some()
+    }
+
+    /// Silences the ringing alarm (ramp-up and fallback tone).
+    pub fn stop_ringing(&self) {
+        if self.ringing.borrow_mut().take().is_some() {
+            debug!("Stop ringing alarm");
+            self.sender.send(Action::PlaybackSetAlarmVolume(None)).unwrap();
+        }
+        self.tone.stop();
+    }
+
+    /// Saves the files of the running recordings. The main loop isn't running anymore at this point,
+    /// so we iterate it ourselves until every recorder has received its EOS (or has given up on it).
+    pub fn shutdown(&self) {
+        // Nothing new gets started anymore (only in memory, the file stays untouched)
+        self.recordings.borrow_mut().clear();
+
+        let recorders: Vec<HeadlessRecorder> = self.recorders.borrow_mut().drain().map(|(_, (recorder, _))| recorder).collect();
+        for recorder in &recorders {
+            recorder.stop();
+        }
+
+        let context = glib::MainContext::default();
+        while recorders.iter().any(|recorder| !recorder.is_finished()) {
+            context.iteration(true);
+        }
+    }
+
+    fn setup_timer(&self) {
+        let alarms = self.alarms.clone();
+        let last_check = self.last_check.clone();
+        let ringing = self.ringing.clone();
+        let tone = self.tone.clone();
+        let recordings = self.recordings.clone();
+        let recorders = self.recorders.clone();
+        let sender = self.sender.clone();
+        gtk::timeout_add_seconds(CHECK_INTERVAL_SECS, move || {
+            Self::update_recordings(&recordings, &recorders, &sender);
+
+            let now = Local::now();
+            let due = alarms
+                .borrow()
+                .iter()
+                .filter(|alarm| alarm.enabled)
+                .filter_map(|alarm| alarm.get_next_occurrence(last_check.get()).map(|time| (alarm.clone(), time)))
+                .filter(|(_, time)| *time <= now)
+                .min_by_key(|(_, time)| *time);
+            last_check.set(now);
+
+            if let Some((alarm, time)) = due {
+                if now.signed_duration_since(time) > chrono::Duration::minutes(MAX_DELAY_MINUTES) {
+                    warn!("Missed alarm at {}, it's too late now", time);
+                } else {
+                    Self::ring(alarm, &ringing, &tone, &sender);
+                }
+            }
+            glib::Continue(true)
+        });
+    }
+
+    /// Starts the recordings which should be running now, and stops the ones which are over.
+    fn update_recordings(recordings: &RefCell<Vec<ScheduledRecording>>, recorders: &RefCell<HashMap<String, (HeadlessRecorder, DateTime<Local>)>>, sender: &Sender<Action>) {
+        let now = Local::now();
+
+        // Recordings which got removed or disabled in the meantime are over too
+        recorders.borrow_mut().retain(|id, (recorder, end)| {
+            let scheduled = recordings.borrow().iter().any(|recording| &recording.id == id && recording.enabled);
+            if now >= *end || !scheduled {
+                recorder.stop();
+                return false;
+            }
+            true
+        });
+
+        for recording in recordings.borrow().iter().filter(|recording| recording.enabled) {
+            if recorders.borrow().contains_key(&recording.id) {
+                continue;
+            }
+
+            if let Some(end) = recording.get_current_end(now) {
+                let recorder = HeadlessRecorder::new(recording.station.clone(), recording.mode, sender.clone());
+                recorder.start();
+                recorders.borrow_mut().insert(recording.id.clone(), (recorder, end));
+            }
+        }
+    }
+
+    fn ring(alarm: Alarm, ringing: &Rc<RefCell<Option<Alarm>>>, tone: &AlarmTone, sender: &Sender<Action>) {
+        info!("Alarm \"{}\" rings", alarm.station.name);
+        tone.stop();
+        *ringing.borrow_mut() = Some(alarm.clone());
+
+        sender.send(Action::PlaybackSetAlarmVolume(Some(0.0))).unwrap();
+        sender.send(Action::PlaybackSetStation(alarm.station.clone())).unwrap();
+        sender.send(Action::PlaybackStart).unwrap();
+        sender.send(Action::ViewRaise).unwrap();
+
+        // Volume ramp-up, it stops as soon as the alarm gets silenced
+        let start = Instant::now();
+        let id = alarm.id.clone();
+        let r = ringing.clone();
+        let t = tone.clone();
+        let s = sender.clone();
+        gtk::timeout_add(RAMP_INTERVAL_MS, move || {
+            if r.borrow().as_ref().map(|alarm| &alarm.id) != Some(&id) {
+                return glib::Continue(false);
+            }
+
+            let progress = if alarm.ramp == 0 {
+                1.0
+            } else {
+                (start.elapsed().as_millis() as f64 / (f64::from(alarm.ramp) * 1000.0)).min(1.0)
+            };
+            let volume = alarm.volume * progress;
+            if t.is_playing() {
+                t.set_volume(volume);
+            } else {
+                s.send(Action::PlaybackSetAlarmVolume(Some(volume))).unwrap();
+            }
+            glib::Continue(progress < 1.0)
+        });
+
+        // Check if the stream works, otherwise we fall back to the alarm tone
+        let sender = sender.clone();
+        gtk::timeout_add_seconds(FALLBACK_TIMEOUT_SECS, move || {
+            sender.send(Action::SchedulerCheckAlarm).unwrap();
+            glib::Continue(false)
+        });
+    }
+}
+
+/// Bundled alarm tone, which gets played in a loop if the station doesn't work.
+#[derive(Clone)]
+struct AlarmTone {
+    playbin: Option<gstreamer::Element>,
+    // Incremented every time the tone gets started, so an old timeout can't stop a new tone
+    generation: Rc<Cell<u32>>,
+}
+
+impl AlarmTone {
+    fn new() -> Self {
+        let mut path = PathBuf::from(config::PKGDATADIR);
+        path.push("sounds");
+        path.push("alarm.wav");
+
+        let playbin = ElementFactory::make("playbin", "alarm_tone");
+        match playbin.as_ref() {
+            Some(playbin) => {
+                playbin.set_property("uri", &format!("file://{}", path.to_str().unwrap())).unwrap();
+
+                // Play the tone in a loop
+                let bus = playbin.get_bus().unwrap();
+                let p = playbin.clone();
+                gtk::timeout_add(250, move || {
+                    while let Some(message) = bus.pop() {
+                        match message.view() {
+                            gstreamer::MessageView::Eos(_) => {
+                                let _ = p.seek_simple(gstreamer::SeekFlags::FLUSH, gstreamer::ClockTime::from_seconds(0));
+                            }
+                            gstreamer::MessageView::Error(err) => warn!("Could not play alarm tone: {}", err.get_error()),
+                            _ => (),
+                        }
+                    }
+                    glib::Continue(true)
+                });
+            }
+            None => warn!("Could not create playbin for the alarm tone"),
+        }
+
+        Self {
+            playbin,
+            generation: Rc::new(Cell::new(0)),
+        }
+    }
+
+    fn play(&self) {
+        let playbin = match self.playbin.as_ref() {
+            Some(playbin) => playbin,
+            None => return,
+        };
+
+        let _ = playbin.set_state(State::Playing);
+
+        let generation = self.generation.get() + 1;
+        self.generation.set(generation);
+        let tone = self.clone();
+        gtk::timeout_add_seconds(TONE_TIMEOUT_SECS, move || {
+            if tone.generation.get() == generation {
+                tone.stop();
+            }
+            glib::Continue(false)
+        });
+    }
+
+    fn stop(&self) {
+        if let Some(playbin) = self.playbin.as_ref() {
+            let _ = playbin.set_state(State::Null);
+        }
+    }
+
+    fn is_playing(&self) -> bool {
+        self.playbin
+            .as_ref()
+            .map_or(false, |playbin| playbin.get_state(gstreamer::ClockTime::from_seconds(0)).1 == State::Playing)
+    }
+
+    fn set_volume(&self, volume: f64) {
+        if let Some(playbin) = self.playbin.as_ref() {
+            playbin.set_property("volume", &volume).unwrap();
+        }
+    }
+}
@@ -0,0 +1,125 @@
+use gio::prelude::*;
+
+use crate::config;
+
+// All keys are defined in data/de.haeckerfelix.Shortwave.gschema.xml.in
+#[derive(Debug, Clone, Copy)]
+pub enum Key {
+    TimeshiftWindow,
+    Volume,
+    AudioOutput,
+    EqualizerEnabled,
+    EqualizerPreset,
+    EqualizerBands,
+    NightMode,
+    LoudnessNormalization,
+    SleepTimerFade,
+    RecordingMode,
+    RecordingFormat,
+    RecordingQuality,
+    RecordingBitrate,
+    RecordingPassthrough,
+    RecordingMinimumDuration,
+    RecordingMaximumDuration,
+    RecordingSkipPartial,
+    RecordingPreroll,
+    RecordingDirectory,
+    RecordingMaxSongs,
+    RecordingMaxSize,
+    RecordingMaxAge,
+    RecordingMinFreeSpace,
+    RecordingSkipSaved,
+    RecordingFilenameTemplate,
+    AutoSaveAllSongs,
+    AutoSaveArtists,
+    AutoSaveMinimumBitrate,
+    AutoSaveMinimumDuration,
+    TitleFilterBuiltin,
+    TitleFilterPatterns,
+}
+
+impl Key {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Key::TimeshiftWindow => "timeshift-window",
+            Key::Volume => "volume",
+            Key::AudioOutput => "audio-output",
+            Key::EqualizerEnabled => "equalizer-enabled",
+            Key::EqualizerPreset => "equalizer-preset",
+            Key::EqualizerBands => "equalizer-bands",
+            Key::NightMode => "night-mode",
+            Key::LoudnessNormalization => "loudness-normalization",
+            Key::SleepTimerFade => "sleep-timer-fade",
+            Key::RecordingMode => "recording-mode",
+            Key::RecordingFormat => "recording-format",
+            Key::RecordingQuality => "recording-quality",
+            Key::RecordingBitrate => "recording-bitrate",
+            Key::RecordingPassthrough => "recording-passthrough",
+            Key::RecordingMinimumDuration => "recording-minimum-duration",
+            Key::RecordingMaximumDuration => "recording-maximum-duration",
+            Key::RecordingSkipPartial => "recording-skip-partial",
+            Key::RecordingPreroll => "recording-preroll",
+            Key::RecordingDirectory => "recording-directory",
+            Key::RecordingMaxSongs => "recording-max-songs",
+            Key::RecordingMaxSize => "recording-max-size",
+            Key::RecordingMaxAge => "recording-max-age",
+            Key::RecordingMinFreeSpace => "recording-min-free-space",
+            Key::RecordingSkipSaved => "recording-skip-saved",
+            Key::RecordingFilenameTemplate => "recording-filename-template",
+            Key::AutoSaveAllSongs => "auto-save-all-songs",
+            Key::AutoSaveArtists => "auto-save-artists",
+            Key::AutoSaveMinimumBitrate => "auto-save-minimum-bitrate",
+            Key::AutoSaveMinimumDuration => "auto-save-minimum-duration",
+            Key::TitleFilterBuiltin => "title-filter-builtin",
+            Key::TitleFilterPatterns => "title-filter-patterns",
+        }
+    }
+}
+
+pub struct SettingsManager {}
+
+impl SettingsManager {
+    pub fn get_settings() -> gio::Settings {
+        gio::Settings::new(config::APP_ID)
+    }
+
+    pub fn get_uint(key: Key) -> u32 {
+        Self::get_settings().get_uint(key.as_str())
+    }
+
+    pub fn set_uint(key: Key, value: u32) {
+        if !Self::get_settings().set_uint(key.as_str(), value) {
+            warn!("Could not set setting \"{}\"", key.as_str());
+        }
+    }
+
+    pub fn get_double(key: Key) -> f64 {
+        Self::get_settings().get_double(key.as_str())
+    }
+
+    pub fn set_double(key: Key, value: f64) {
+        if !Self::get_settings().set_double(key.as_str(), value) {
+            warn!("Could not set setting \"{}\"", key.as_str());
+        }
+    }
+
+    pub fn get_boolean(key: Key) -> bool {
+        Self::get_settings().get_boolean(key.as_str())
+    }
+
+    pub fn set_boolean(key: Key, value: bool) {
+        if !Self::get_settings().set_boolean(key.as_str(), value) {
+            warn!("Could not set setting \"{}\"", key.as_str());
+        }
+    }
+
+    pub fn get_string(key: Key) -> String {
+        Self::get_settings().get_string(key.as_str()).map(|value| value.to_string()).unwrap_or_default()
+    }
+
+    pub fn set_string(key: Key, value: &str) {
+        if !Self::get_settings().set_string(key.as_str(), value) {
+            warn!("Could not set setting \"{}\"", key.as_str());
+        }
+    }
+}
@@ -1,51 +1,231 @@
+use chrono::{DateTime, Local, TimeZone};
+
+use std::collections::HashSet;
+use std::ffi::{OsStr, OsString};
 use std::fs;
-use std::path::PathBuf;
+use std::io;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 
+use crate::filename_template::FilenameTemplate;
+use crate::metadata::SongMetadata;
+use crate::recording_index::RecordingIndex;
+
+// Cue sheet timestamps have two digits for the minutes
+const MAX_CUE_MINUTES: u128 = 99;
+
 #[derive(Clone, Serialize, Deserialize)]
 pub struct Song {
     pub title: String,
+    #[serde(default)]
+    pub artist: Option<String>,
+    #[serde(default)]
+    pub station: Option<String>,
     pub path: PathBuf,
     pub duration: Duration,
+    // Unix timestamp (seconds) of the recording start, 0 for songs from older versions
+    #[serde(default)]
+    pub timestamp: i64,
+    // Only continuous recordings have chapters, one for every song
+    #[serde(default)]
+    pub chapters: Vec<Chapter>,
+    // Copied into the music folder by an auto-save rule
+    #[serde(default)]
+    pub auto_saved: bool,
+}
+
+/// A song within a continuous recording.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Chapter {
+    pub start: Duration,
+    pub title: String,
+    #[serde(default)]
+    pub artist: Option<String>,
+}
+
+impl Chapter {
+    /// "Artist - Title", or only the title if we don't know the artist.
+    pub fn get_display_title(&self) -> String {
+        match self.artist.as_ref() {
+            Some(artist) => format!("{} - {}", artist, self.title),
+            None => self.title.clone(),
+        }
+    }
 }
 
 impl Song {
-    pub fn new(title: &str, path: PathBuf, duration: Duration) -> Self {
-        debug!("New song: \"{}\", {:?}", title, path);
+    pub fn new(metadata: &SongMetadata, path: PathBuf, duration: Duration) -> Self {
+        debug!("New song: \"{}\", {:?}", metadata.get_display_title(), path);
 
         Self {
-            title: title.to_string(),
+            title: metadata.title.clone(),
+            artist: metadata.artist.clone(),
+            station: None,
             path,
             duration,
+            timestamp: Local::now().timestamp(),
+            chapters: Vec::new(),
+            auto_saved: false,
+        }
+    }
+
+    /// "Artist - Title", or only the title if we don't know the artist.
+    pub fn get_display_title(&self) -> String {
+        match self.artist.as_ref() {
+            Some(artist) => format!("{} - {}", artist, self.title),
+            None => self.title.clone(),
+        }
+    }
+
+    /// When the recording has started. Older songs use the date of their file.
+    pub fn get_date(&self) -> DateTime<Local> {
+        if self.timestamp != 0 {
+            return Local.timestamp(self.timestamp, 0);
+        }
+        fs::metadata(&self.path)
+            .and_then(|metadata| metadata.modified())
+            .map(DateTime::<Local>::from)
+            .unwrap_or_else(|_| Local::now())
+    }
+
+    /// Average bitrate of the file in kbit/s.
+    pub fn get_bitrate(&self) -> u32 {
+        let size = fs::metadata(&self.path).map(|metadata| metadata.len()).unwrap_or(0);
+        match self.duration.as_millis() {
+            0 => 0,
+            millis => (u128::from(size) * 8 / millis) as u32,
+        }
+    }
+
+    /// Copies the song into the music folder, named by the file name template.
+    /// Existing files never get overwritten, the song gets a name of its own instead.
+    pub fn save(&self) -> io::Result<PathBuf> {
+        let path = FilenameTemplate::get_path(self);
+        if let Some(folder) = path.parent() {
+            fs::create_dir_all(folder)?;
+        }
+        self.save_as(path.clone())?;
+
+        if self.chapters.is_empty() {
+            RecordingIndex::add(self, path.clone());
         }
+        Ok(path)
     }
 
     pub fn save_as(&self, path: PathBuf) -> std::io::Result<()> {
         debug!("Save song \"{}\" as {:?}", self.title, path);
 
-        fs::copy(&self.path, path)?;
-        Ok(())
-    }
-
-    pub fn simplify_title(t: String) -> String {
-        // remove special chars from title
-        // if anybody knows a better way to do this, feel free to open a MR on GitLab :)
-        let mut title = t.replace("/", "");
-        title = title.replace("\\", "");
-        title = title.replace(":", "");
-        title = title.replace("<", "");
-        title = title.replace(">", "");
-        title = title.replace("\"", "");
-        title = title.replace("|", "");
-        title = title.replace("?", "");
-        title = title.replace("*", "");
-        title = title.replace(".", "");
-        title
+        fs::copy(&self.path, &path)?;
+        self.write_chapter_files(&path)
+    }
+
+    /// Most formats can't store chapters, so they get saved next to the file at `path` as well:
+    /// as OGM chapter list (e.g. for MKVToolNix) and as cue sheet (most music players).
+    pub fn write_chapter_files(&self, path: &Path) -> std::io::Result<()> {
+        if self.chapters.is_empty() {
+            return Ok(());
+        }
+
+        fs::write(path.with_extension("chapters.txt"), self.get_chapter_list())?;
+        fs::write(path.with_extension("cue"), self.get_cue_sheet(path))
+    }
+
+    fn get_chapter_list(&self) -> String {
+        let mut list = String::new();
+        for (i, chapter) in self.chapters.iter().enumerate() {
+            let start = chapter.start.as_millis();
+            let timestamp = format!("{:02}:{:02}:{:02}.{:03}", start / 3_600_000, start / 60_000 % 60, start / 1000 % 60, start % 1000);
+            list += &format!("CHAPTER{:02}={}\nCHAPTER{:02}NAME={}\n", i + 1, timestamp, i + 1, chapter.get_display_title());
+        }
+        list
+    }
+
+    fn get_cue_sheet(&self, path: &Path) -> String {
+        let file_name = path.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+        // Cue sheets only know uncompressed files and MP3. Lossless files are WAVE by convention (e.g. FLAC),
+        // the other compressed formats get treated like MP3.
+        let file_type = match path.extension().and_then(|extension| extension.to_str()) {
+            Some("flac") | Some("wav") => "WAVE",
+            _ => "MP3",
+        };
+
+        let mut sheet = format!("TITLE {}\nFILE {} {}\n", Self::quote_cue(&self.title), Self::quote_cue(&file_name), file_type);
+        for (i, chapter) in self.chapters.iter().enumerate() {
+            // Minutes, seconds and frames (75 per second)
+            let start = chapter.start.as_millis();
+            if start / 60_000 > MAX_CUE_MINUTES {
+                // The chapter list has the rest of them
+                warn!("Cue sheet of \"{}\" ends after {} chapters, it can't go beyond {} minutes", self.title, i, MAX_CUE_MINUTES);
+                break;
+            }
+            let timestamp = format!("{:02}:{:02}:{:02}", start / 60_000, start / 1000 % 60, start % 1000 * 75 / 1000);
+            sheet += &format!("  TRACK {:02} AUDIO\n", i + 1);
+            sheet += &format!("    TITLE {}\n", Self::quote_cue(&chapter.title));
+            if let Some(artist) = chapter.artist.as_ref() {
+                sheet += &format!("    PERFORMER {}\n", Self::quote_cue(artist));
+            }
+            sheet += &format!("    INDEX 01 {}\n", timestamp);
+        }
+        sheet
+    }
+
+    // Cue sheets can't escape quotes
+    fn quote_cue(text: &str) -> String {
+        format!("\"{}\"", text.replace("\"", "'"))
+    }
+
+    /// Appends " (2)", " (3)", ... to the file name, until there's no file with this name.
+    /// Files with other extensions count too.
+    pub fn get_unique_path(path: PathBuf) -> PathBuf {
+        let name = match path.file_stem() {
+            Some(name) => name.to_string_lossy().to_string(),
+            None => return path,
+        };
+        let extension = path.extension().map(|extension| extension.to_string_lossy().to_string());
+        Self::get_unique_name(path, &name, extension.as_ref().map(String::as_str))
+    }
+
+    /// Same as `get_unique_path`, for folders and for files whose extension gets added later (e.g. by the RecorderBin).
+    /// Dots in their names don't start an extension.
+    pub fn get_unique_path_without_extension(path: PathBuf) -> PathBuf {
+        let name = match path.file_name() {
+            Some(name) => name.to_string_lossy().to_string(),
+            None => return path,
+        };
+        Self::get_unique_name(path, &name, None)
+    }
+
+    fn get_unique_name(path: PathBuf, name: &str, extension: Option<&str>) -> PathBuf {
+        // The folder gets listed once, instead of once for every number.
+        // Names with dots can have a stem which isn't the name, so both of them are taken.
+        let mut taken: HashSet<OsString> = HashSet::new();
+        if let Some(entries) = path.parent().and_then(|folder| fs::read_dir(folder).ok()) {
+            for entry in entries.filter_map(|entry| entry.ok()) {
+                let entry_path = entry.path();
+                taken.extend(entry_path.file_stem().map(|stem| stem.to_os_string()));
+                taken.insert(entry.file_name());
+            }
+        }
+
+        let mut unique_name = name.to_string();
+        let mut number = 1;
+        while taken.contains(OsStr::new(&unique_name)) {
+            number += 1;
+            unique_name = format!("{} ({})", name, number);
+        }
+
+        let mut unique_path = path;
+        match extension {
+            Some(extension) => unique_path.set_file_name(format!("{}.{}", unique_name, extension)),
+            None => unique_path.set_file_name(unique_name),
+        }
+        unique_path
     }
 }
 
+// The same song can be recorded more than once, but every recording has a file of its own
 impl PartialEq for Song {
     fn eq(&self, other: &Song) -> bool {
-        self.title == other.title
+        self.path == other.path
     }
 }
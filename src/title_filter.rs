@@ -0,0 +1,218 @@
+use rustio::Station;
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use crate::json_file::JsonFile;
+use crate::metadata::{SongMetadata, PATTERN_IGNORE};
+use crate::settings::{Key, SettingsManager};
+
+lazy_static! {
+    static ref STATION_FILTERS: JsonFile<HashMap<String, String>> = JsonFile::new("title_filters.json");
+    static ref PATTERN_CACHE: Mutex<PatternCache> = Mutex::new(PatternCache::default());
+}
+
+// Changes every time the patterns get changed, the cache is outdated then. 0 is never used, so the cache starts outdated.
+static PATTERNS_GENERATION: AtomicUsize = AtomicUsize::new(1);
+
+// Placeholder for the name of the station, a lot of stations send it between two songs
+static PATTERN_STATION: &str = "{station}";
+
+// Patterns get separated by semicolons, commas are too common in song titles
+static PATTERN_SEPARATOR: char = ';';
+
+// Only titles which are very unlikely to be songs, "Jingle Bells" is still a song
+static BUILTIN_PATTERNS: [&str; 10] = [
+    "{station}",
+    "Advertisement{*}",
+    "Advert",
+    "Commercial",
+    "Commercial Break{*}",
+    "Ad Break{*}",
+    "{*}adswizz{*}",
+    "Werbung",
+    "Jingle",
+    "Station ID",
+];
+
+// Every title gets checked, the patterns get split up only once
+#[derive(Default)]
+struct PatternCache {
+    generation: usize,
+    // The patterns of the settings, including the builtin ones
+    patterns: Vec<String>,
+    // Filled as the stations get played
+    station_patterns: HashMap<String, Vec<String>>,
+}
+
+/// Recognizes stream titles which aren't songs, e.g. ads, jingles or the name of the station.
+///
+/// Patterns match the whole title, case insensitive. `{*}` matches anything, `{station}` the name of the station.
+pub struct TitleFilter {}
+
+impl TitleFilter {
+    pub fn is_filtered(song: &SongMetadata, station: Option<&Station>) -> bool {
+        let mut cache = PATTERN_CACHE.lock().unwrap();
+        let generation = PATTERNS_GENERATION.load(Ordering::SeqCst);
+        if cache.generation != generation {
+            *cache = PatternCache {
+                generation,
+                patterns: Self::load_patterns(),
+                station_patterns: HashMap::new(),
+            };
+        }
+        if let Some(station) = station {
+            if !cache.station_patterns.contains_key(&station.id) {
+                let station_patterns = Self::get_station_patterns(&station.id).map(|patterns| Self::split(&patterns)).unwrap_or_default();
+                cache.station_patterns.insert(station.id.clone(), station_patterns);
+            }
+        }
+
+        let station_patterns = station.and_then(|station| cache.station_patterns.get(&station.id));
+        let station_name = station.map(|station| station.name.as_str()).unwrap_or_default();
+        let filtered = cache
+            .patterns
+            .iter()
+            .chain(station_patterns.into_iter().flatten())
+            .any(|pattern| Self::matches(pattern, &song.stream_title, station_name) || Self::matches(pattern, &song.title, station_name));
+        if filtered {
+            debug!("Filtered title: \"{}\"", song.stream_title);
+        }
+        filtered
+    }
+
+    /// The patterns of the settings have changed, they get loaded again with the next title.
+    pub fn reload() {
+        PATTERNS_GENERATION.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn load_patterns() -> Vec<String> {
+        let mut patterns = Self::split(&SettingsManager::get_string(Key::TitleFilterPatterns));
+        if SettingsManager::get_boolean(Key::TitleFilterBuiltin) {
+            patterns.extend(BUILTIN_PATTERNS.iter().map(|pattern| pattern.to_string()));
+        }
+        patterns
+    }
+
+    fn split(patterns: &str) -> Vec<String> {
+        patterns
+            .split(PATTERN_SEPARATOR)
+            .map(|pattern| pattern.trim().to_string())
+            .filter(|pattern| !pattern.is_empty())
+            .collect()
+    }
+
+    fn matches(pattern: &str, title: &str, station_name: &str) -> bool {
+        let title = title.trim().to_lowercase();
+        if title.is_empty() {
+            return false;
+        }
+
+        // Without a station there's no name to compare with
+        if pattern.contains(PATTERN_STATION) && station_name.is_empty() {
+            return false;
+        }
+        let pattern = pattern.to_lowercase().replace(PATTERN_STATION, &station_name.to_lowercase());
+
+        // The parts between the wildcards have to appear in this order, the first at the start and the last at the end
+        let parts: Vec<&str> = pattern.split(PATTERN_IGNORE).collect();
+        let (first, last) = (parts[0], parts[parts.len() - 1]);
+        if parts.len() == 1 {
+            return title == first;
+        }
+        if !title.starts_with(first) || !title[first.len()..].ends_with(last) {
+            return false;
+        }
+
+        let mut rest = &title[first.len()..title.len() - last.len()];
+        for part in &parts[1..parts.len() - 1] {
+            match rest.find(part) {
+                Some(index) => rest = &rest[index + part.len()..],
+                None => return false,
+            }
+        }
+        true
+    }
+
+    /// The patterns of the station, separated by semicolons.
+    pub fn get_station_patterns(station_id: &str) -> Option<String> {
+        STATION_FILTERS.with(|patterns| patterns.get(station_id).cloned())
+    }
+
+    pub fn set_station_patterns(station_id: &str, patterns: Option<String>) {
+        let result = STATION_FILTERS.update(|station_patterns| {
+            match patterns.filter(|patterns| !patterns.trim().is_empty()) {
+                Some(patterns) => station_patterns.insert(station_id.to_string(), patterns),
+                None => station_patterns.remove(station_id),
+            };
+            true
+        });
+
+        if let Err(err) = result {
+            warn!("Could not save title filters: {}", err);
+        }
+        Self::reload();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn whole_title() {
+        assert!(TitleFilter::matches("Advert", "Advert", ""));
+        assert!(TitleFilter::matches("Advert", "  Advert ", ""));
+        assert!(!TitleFilter::matches("Advert", "Advertisement", ""));
+        assert!(!TitleFilter::matches("Jingle", "Jingle Bells", ""));
+        assert!(!TitleFilter::matches("Advert", "", ""));
+    }
+
+    #[test]
+    fn wildcards() {
+        // Leading
+        assert!(TitleFilter::matches("{*}Break", "Commercial Break", ""));
+        assert!(TitleFilter::matches("{*}Break", "Break", ""));
+        assert!(!TitleFilter::matches("{*}Break", "Break Free", ""));
+
+        // Trailing
+        assert!(TitleFilter::matches("Ad Break{*}", "Ad Break (30s)", ""));
+        assert!(!TitleFilter::matches("Ad Break{*}", "Bad Break", ""));
+
+        // In the middle, the parts have to appear in order
+        assert!(TitleFilter::matches("Station{*}ID", "Station 1 ID", ""));
+        assert!(TitleFilter::matches("{*}ads{*}wizz{*}", "x ads y wizz z", ""));
+        assert!(!TitleFilter::matches("{*}wizz{*}ads{*}", "x ads y wizz z", ""));
+        // The start and the end can't overlap
+        assert!(!TitleFilter::matches("ab{*}ba", "aba", ""));
+
+        assert!(TitleFilter::matches("{*}", "Anything", ""));
+    }
+
+    #[test]
+    fn station_name() {
+        assert!(TitleFilter::matches("{station}", "Radio Paradise", "Radio Paradise"));
+        assert!(TitleFilter::matches("{station} - {*}", "Radio Paradise - Listener Supported", "Radio Paradise"));
+        assert!(!TitleFilter::matches("{station}", "Radio Paradise", "Radio Swiss Jazz"));
+        // Without a station nothing matches, instead of every empty name
+        assert!(!TitleFilter::matches("{station}{*}", "Radio Paradise", ""));
+    }
+
+    #[test]
+    fn case_folding() {
+        assert!(TitleFilter::matches("advertisement{*}", "ADVERTISEMENT: Buy now", ""));
+        assert!(TitleFilter::matches("{STATION}", "radio paradise", "Radio Paradise"));
+        assert!(TitleFilter::matches("{station}", "RADIO PARADISE", "radio paradise"));
+    }
+
+    #[test]
+    fn non_ascii_titles() {
+        assert!(TitleFilter::matches("Werbung", "WERBUNG", ""));
+        assert!(TitleFilter::matches("{*}Größe{*}", "Die GRÖSSE Größe Ärger", ""));
+        assert!(TitleFilter::matches("Ärger{*}", "ärger über alles", ""));
+        assert!(TitleFilter::matches("{*}日本{*}", "ニュース 日本 ラジオ", ""));
+        assert!(!TitleFilter::matches("Ä{*}", "A über alles", ""));
+        assert!(TitleFilter::matches("{station}", "Радио Рекорд", "радио рекорд"));
+    }
+}
@@ -0,0 +1,203 @@
+use chrono::{Datelike, Local};
+use glib::Sender;
+use gtk::prelude::*;
+use libhandy::Dialog;
+use rustio::Station;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::app::Action;
+use crate::scheduler::{self, Alarm, WEEKDAY_NAMES};
+
+#[derive(Clone)]
+pub struct AlarmDialog {
+    pub widget: Dialog,
+    alarm_listbox: gtk::ListBox,
+    next_alarm_label: gtk::Label,
+
+    alarms: Rc<RefCell<Vec<Alarm>>>,
+    stations: Rc<Vec<Station>>,
+    sender: Sender<Action>,
+}
+
+impl AlarmDialog {
+    pub fn new(sender: Sender<Action>, alarms: Vec<Alarm>, stations: Vec<Station>, window: &gtk::Window) -> Self {
+        let builder = gtk::Builder::new_from_resource("/de/haeckerfelix/Shortwave/gtk/alarm_dialog.ui");
+        let widget: Dialog = builder.get_object("alarm_dialog").unwrap();
+        let alarm_listbox: gtk::ListBox = builder.get_object("alarm_listbox").unwrap();
+        let next_alarm_label: gtk::Label = builder.get_object("next_alarm_label").unwrap();
+        let add_button: gtk::Button = builder.get_object("add_button").unwrap();
+
+        // Alarms can only wake you up with stations from the library
+        add_button.set_sensitive(!stations.is_empty());
+        widget.set_transient_for(window);
+
+        let dialog = Self {
+            widget,
+            alarm_listbox,
+            next_alarm_label,
+            alarms: Rc::new(RefCell::new(alarms)),
+            stations: Rc::new(stations),
+            sender,
+        };
+
+        let d = dialog.clone();
+        add_button.connect_clicked(move |_| {
+            let alarm = Alarm::new(d.stations[0].clone());
+            d.alarms.borrow_mut().push(alarm.clone());
+            d.add_row(&alarm);
+            d.save();
+        });
+
+        for alarm in dialog.alarms.borrow().iter() {
+            dialog.add_row(alarm);
+        }
+        dialog.update_next_alarm();
+        dialog
+    }
+
+    pub fn show(&self) {
+        self.widget.set_visible(true);
+    }
+
+    fn add_row(&self, alarm: &Alarm) {
+        let builder = gtk::Builder::new_from_resource("/de/haeckerfelix/Shortwave/gtk/alarm_row.ui");
+        let row: gtk::ListBoxRow = builder.get_object("alarm_row").unwrap();
+        let hour_spinbutton: gtk::SpinButton = builder.get_object("hour_spinbutton").unwrap();
+        let minute_spinbutton: gtk::SpinButton = builder.get_object("minute_spinbutton").unwrap();
+        let station_combobox: gtk::ComboBoxText = builder.get_object("station_combobox").unwrap();
+        let enabled_switch: gtk::Switch = builder.get_object("enabled_switch").unwrap();
+        let remove_button: gtk::Button = builder.get_object("remove_button").unwrap();
+        let volume_scale: gtk::Scale = builder.get_object("volume_scale").unwrap();
+        let ramp_spinbutton: gtk::SpinButton = builder.get_object("ramp_spinbutton").unwrap();
+
+        // Keep the station of the alarm selectable, even if it got removed from the library
+        let mut stations: Vec<Station> = self.stations.to_vec();
+        if !stations.iter().any(|station| station.id == alarm.station.id) {
+            stations.insert(0, alarm.station.clone());
+        }
+        for station in &stations {
+            station_combobox.append(Some(&station.id), &station.name);
+        }
+        let stations = Rc::new(stations);
+
+        // The signals get connected after the values are set, so we don't need an `updating` flag here
+        hour_spinbutton.set_value(f64::from(alarm.hour));
+        minute_spinbutton.set_value(f64::from(alarm.minute));
+        station_combobox.set_active_id(Some(&alarm.station.id));
+        enabled_switch.set_active(alarm.enabled);
+        volume_scale.set_value(alarm.volume);
+        ramp_spinbutton.set_value(f64::from(alarm.ramp));
+
+        let id = alarm.id.clone();
+        let d = self.clone();
+        hour_spinbutton.connect_value_changed(move |spinbutton| {
+            let hour = spinbutton.get_value_as_int() as u32;
+            d.update_alarm(&id, |alarm| alarm.hour = hour);
+        });
+
+        let id = alarm.id.clone();
+        let d = self.clone();
+        minute_spinbutton.connect_value_changed(move |spinbutton| {
+            let minute = spinbutton.get_value_as_int() as u32;
+            d.update_alarm(&id, |alarm| alarm.minute = minute);
+        });
+
+        // Show leading zeros, like a clock does
+        let format_time = |spinbutton: &gtk::SpinButton| {
+            spinbutton.set_text(&format!("{:02}", spinbutton.get_value_as_int()));
+            gtk::Inhibit(true)
+        };
+        hour_spinbutton.connect_output(format_time);
+        minute_spinbutton.connect_output(format_time);
+
+        let id = alarm.id.clone();
+        let d = self.clone();
+        station_combobox.connect_changed(move |combobox| {
+            let active = combobox.get_active_id().map(|id| id.to_string());
+            if let Some(station) = stations.iter().find(|station| Some(&station.id) == active.as_ref()) {
+                d.update_alarm(&id, |alarm| alarm.station = station.clone());
+            }
+        });
+
+        let id = alarm.id.clone();
+        let d = self.clone();
+        enabled_switch.connect_state_set(move |_, state| {
+            d.update_alarm(&id, |alarm| alarm.enabled = state);
+            gtk::Inhibit(false)
+        });
+
+        let id = alarm.id.clone();
+        let d = self.clone();
+        volume_scale.connect_value_changed(move |scale| {
+            let volume = scale.get_value();
+            d.update_alarm(&id, |alarm| alarm.volume = volume);
+        });
+
+        let id = alarm.id.clone();
+        let d = self.clone();
+        ramp_spinbutton.connect_value_changed(move |spinbutton| {
+            let ramp = spinbutton.get_value_as_int() as u32;
+            d.update_alarm(&id, |alarm| alarm.ramp = ramp);
+        });
+
+        for day in 0..WEEKDAY_NAMES.len() as u32 {
+            let button: gtk::ToggleButton = builder.get_object(&format!("weekday_button_{}", day)).unwrap();
+            button.set_active(alarm.weekdays.contains(&day));
+
+            let id = alarm.id.clone();
+            let d = self.clone();
+            button.connect_toggled(move |button| {
+                let active = button.get_active();
+                d.update_alarm(&id, |alarm| {
+                    alarm.weekdays.retain(|weekday| *weekday != day);
+                    if active {
+                        alarm.weekdays.push(day);
+                        alarm.weekdays.sort();
+                    }
+                });
+            });
+        }
+
+        let id = alarm.id.clone();
+        let d = self.clone();
+        let r = row.clone();
+        remove_button.connect_clicked(move |_| {
+            d.alarms.borrow_mut().retain(|alarm| alarm.id != id);
+            d.alarm_listbox.remove(&r);
+            d.save();
+        });
+
+        self.alarm_listbox.add(&row);
+    }
+
+    fn update_alarm<F: FnOnce(&mut Alarm)>(&self, id: &str, update: F) {
+        if let Some(alarm) = self.alarms.borrow_mut().iter_mut().find(|alarm| alarm.id == id) {
+            update(alarm);
+        }
+        self.save();
+    }
+
+    fn save(&self) {
+        self.sender.send(Action::SchedulerSetAlarms(self.alarms.borrow().clone())).unwrap();
+        self.update_next_alarm();
+    }
+
+    fn update_next_alarm(&self) {
+        let text = match scheduler::get_next_alarm(&self.alarms.borrow()) {
+            Some((alarm, time)) => {
+                let day = if time.date() == Local::today() {
+                    "Today".to_string()
+                } else if time.date() == Local::today().succ() {
+                    "Tomorrow".to_string()
+                } else {
+                    WEEKDAY_NAMES[time.weekday().num_days_from_monday() as usize].to_string()
+                };
+                format!("Next alarm: {} {} with \"{}\"", day, time.format("%H:%M"), alarm.station.name)
+            }
+            None => "No alarm scheduled".to_string(),
+        };
+        self.next_alarm_label.set_text(&text);
+    }
+}
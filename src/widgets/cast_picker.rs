@@ -0,0 +1,157 @@
+use glib::Sender;
+use gtk::prelude::*;
+
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+use crate::app::Action;
+use crate::player::audio_output::AudioOutput;
+use crate::player::chromecast::{self, CastReceiver};
+
+#[derive(Clone)]
+pub struct CastPicker {
+    pub widget: gtk::MenuButton,
+    popover: gtk::Popover,
+    audio_output_combobox: gtk::ComboBoxText,
+    // The combobox gets updated, don't handle it as user input
+    updating_outputs: Rc<Cell<bool>>,
+    receiver_listbox: gtk::ListBox,
+    receiver_label: gtk::Label,
+    search_spinner: gtk::Spinner,
+
+    // Found receivers, in the same order as the listbox rows (without the local row)
+    receivers: Rc<RefCell<Vec<CastReceiver>>>,
+    discovery_sender: Sender<CastReceiver>,
+    sender: Sender<Action>,
+}
+
+impl CastPicker {
+    pub fn new(sender: Sender<Action>) -> Self {
+        let builder = gtk::Builder::new_from_resource("/de/haeckerfelix/Shortwave/gtk/cast_picker.ui");
+        let widget: gtk::MenuButton = builder.get_object("cast_picker").unwrap();
+        let popover: gtk::Popover = builder.get_object("cast_popover").unwrap();
+        let audio_output_combobox: gtk::ComboBoxText = builder.get_object("audio_output_combobox").unwrap();
+        let receiver_listbox: gtk::ListBox = builder.get_object("receiver_listbox").unwrap();
+        let receiver_label: gtk::Label = builder.get_object("receiver_label").unwrap();
+        let search_spinner: gtk::Spinner = builder.get_object("search_spinner").unwrap();
+
+        // Every search sends its results here
+        let (discovery_sender, discovery_receiver) = glib::MainContext::channel(glib::PRIORITY_DEFAULT);
+
+        let picker = Self {
+            widget,
+            popover,
+            audio_output_combobox,
+            updating_outputs: Rc::new(Cell::new(false)),
+            receiver_listbox,
+            receiver_label,
+            search_spinner,
+            receivers: Rc::new(RefCell::new(Vec::new())),
+            discovery_sender,
+            sender,
+        };
+
+        picker.setup_signals(discovery_receiver);
+        picker
+    }
+
+    /// Shows the receiver which currently plays the audio, `None` is the local computer.
+    pub fn set_active_receiver(&self, receiver: Option<&CastReceiver>) {
+        match receiver {
+            Some(receiver) => self.receiver_label.set_text(&receiver.name),
+            None => self.receiver_label.set_text("This computer"),
+        }
+    }
+
+    /// Updates the list of local audio output devices. `selected` is the id of the chosen one.
+    pub fn set_audio_outputs(&self, outputs: &[AudioOutput], selected: &str) {
+        self.updating_outputs.set(true);
+
+        self.audio_output_combobox.remove_all();
+        self.audio_output_combobox.append(Some(""), "Automatic");
+        for output in outputs {
+            self.audio_output_combobox.append(Some(output.id.as_str()), &output.name);
+        }
+
+        // The selected device is currently not available
+        if !self.audio_output_combobox.set_active_id(Some(selected)) {
+            self.audio_output_combobox.set_active_id(Some(""));
+        }
+
+        self.updating_outputs.set(false);
+    }
+
+    fn setup_signals(&self, discovery_receiver: glib::Receiver<CastReceiver>) {
+        let updating_outputs = self.updating_outputs.clone();
+        let sender = self.sender.clone();
+        self.audio_output_combobox.connect_changed(move |combobox| {
+            if updating_outputs.get() {
+                return;
+            }
+            if let Some(id) = combobox.get_active_id() {
+                sender.send(Action::PlaybackSetAudioOutput(id.to_string())).unwrap();
+            }
+        });
+
+        // Search for receivers every time the picker gets opened
+        let receiver_listbox = self.receiver_listbox.clone();
+        let search_spinner = self.search_spinner.clone();
+        let receivers = self.receivers.clone();
+        let discovery_sender = self.discovery_sender.clone();
+        self.popover.connect_show(move |_| {
+            // Remove all previously found receivers, but keep the local row
+            for row in receiver_listbox.get_children().iter().skip(1) {
+                receiver_listbox.remove(row);
+            }
+            receivers.borrow_mut().clear();
+
+            chromecast::discover(discovery_sender.clone());
+            search_spinner.start();
+
+            let search_spinner = search_spinner.clone();
+            gtk::timeout_add_seconds(chromecast::DISCOVERY_TIMEOUT_SECS as u32, move || {
+                search_spinner.stop();
+                glib::Continue(false)
+            });
+        });
+
+        let receiver_listbox = self.receiver_listbox.clone();
+        let receivers = self.receivers.clone();
+        discovery_receiver.attach(None, move |cast_receiver: CastReceiver| {
+            // Devices can answer multiple times
+            if receivers.borrow().contains(&cast_receiver) {
+                return glib::Continue(true);
+            }
+
+            let label = gtk::Label::new(Some(cast_receiver.name.as_str()));
+            label.set_halign(gtk::Align::Start);
+            label.set_margin_start(6);
+            label.set_margin_end(6);
+            label.set_margin_top(6);
+            label.set_margin_bottom(6);
+            let row = gtk::ListBoxRow::new();
+            row.add(&label);
+            row.show_all();
+
+            receiver_listbox.add(&row);
+            receivers.borrow_mut().push(cast_receiver);
+            glib::Continue(true)
+        });
+
+        let search_spinner = self.search_spinner.clone();
+        self.popover.connect_closed(move |_| search_spinner.stop());
+
+        let receivers = self.receivers.clone();
+        let popover = self.popover.clone();
+        let sender = self.sender.clone();
+        self.receiver_listbox.connect_row_activated(move |_, row| {
+            let receiver = match row.get_index() {
+                0 => None,
+                index => receivers.borrow().get(index as usize - 1).cloned(),
+            };
+
+            sender.send(Action::PlaybackSetCastReceiver(receiver)).unwrap();
+            popover.popdown();
+        });
+    }
+}
@@ -0,0 +1,167 @@
+use glib::Sender;
+use gtk::prelude::*;
+
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+use crate::app::Action;
+use crate::player::effects::{Effects, EqualizerPreset, BAND_FREQUENCIES, BAND_GAIN_MAX, BAND_GAIN_MIN};
+
+pub struct EffectsPopover {
+    pub widget: gtk::Popover,
+    equalizer_switch: gtk::Switch,
+    preset_combobox: gtk::ComboBoxText,
+    night_mode_switch: gtk::Switch,
+    band_scales: Rc<Vec<gtk::Scale>>,
+
+    effects: Rc<RefCell<Effects>>,
+    // The widgets get updated, don't handle it as user input
+    updating: Rc<Cell<bool>>,
+    sender: Sender<Action>,
+}
+
+impl EffectsPopover {
+    pub fn new(sender: Sender<Action>) -> Self {
+        let builder = gtk::Builder::new_from_resource("/de/haeckerfelix/Shortwave/gtk/effects_popover.ui");
+        let widget: gtk::Popover = builder.get_object("effects_popover").unwrap();
+        let equalizer_switch: gtk::Switch = builder.get_object("equalizer_switch").unwrap();
+        let preset_combobox: gtk::ComboBoxText = builder.get_object("preset_combobox").unwrap();
+        let night_mode_switch: gtk::Switch = builder.get_object("night_mode_switch").unwrap();
+        let bands_box: gtk::Box = builder.get_object("bands_box").unwrap();
+
+        for preset in EqualizerPreset::all() {
+            preset_combobox.append(Some(preset.as_str()), preset.get_title());
+        }
+
+        // One vertical slider per band, with the frequency below
+        let mut band_scales = Vec::new();
+        for frequency in BAND_FREQUENCIES.iter() {
+            let scale = gtk::Scale::new_with_range(gtk::Orientation::Vertical, BAND_GAIN_MIN, BAND_GAIN_MAX, 0.5);
+            scale.set_inverted(true);
+            scale.set_draw_value(false);
+            scale.set_size_request(-1, 140);
+            scale.add_mark(0.0, gtk::PositionType::Right, None);
+
+            let label = gtk::Label::new(Some(*frequency));
+            label.get_style_context().add_class("small");
+            label.get_style_context().add_class("dim-label");
+
+            let band_box = gtk::Box::new(gtk::Orientation::Vertical, 3);
+            band_box.pack_start(&scale, true, true, 0);
+            band_box.pack_start(&label, false, false, 0);
+            band_box.show_all();
+            bands_box.add(&band_box);
+
+            band_scales.push(scale);
+        }
+
+        let popover = Self {
+            widget,
+            equalizer_switch,
+            preset_combobox,
+            night_mode_switch,
+            band_scales: Rc::new(band_scales),
+            effects: Rc::new(RefCell::new(Effects::load())),
+            updating: Rc::new(Cell::new(false)),
+            sender,
+        };
+
+        popover.update_widgets();
+        popover.setup_signals();
+        popover
+    }
+
+    fn update_widgets(&self) {
+        Self::update(&self.effects.borrow(), &self.equalizer_switch, &self.preset_combobox, &self.band_scales, &self.updating);
+
+        self.updating.set(true);
+        self.night_mode_switch.set_active(self.effects.borrow().night_mode);
+        self.updating.set(false);
+    }
+
+    fn update(effects: &Effects, equalizer_switch: &gtk::Switch, preset_combobox: &gtk::ComboBoxText, band_scales: &[gtk::Scale], updating: &Cell<bool>) {
+        updating.set(true);
+        equalizer_switch.set_active(effects.equalizer);
+        preset_combobox.set_active_id(Some(effects.preset.as_str()));
+        preset_combobox.set_sensitive(effects.equalizer);
+        for (scale, gain) in band_scales.iter().zip(effects.bands.iter()) {
+            scale.set_value(*gain);
+            scale.set_sensitive(effects.equalizer);
+        }
+        updating.set(false);
+    }
+
+    fn setup_signals(&self) {
+        // Equalizer on / off
+        let effects = self.effects.clone();
+        let preset_combobox = self.preset_combobox.clone();
+        let band_scales = self.band_scales.clone();
+        let updating = self.updating.clone();
+        let sender = self.sender.clone();
+        self.equalizer_switch.connect_state_set(move |switch, state| {
+            if !updating.get() {
+                effects.borrow_mut().equalizer = state;
+                Self::update(&effects.borrow(), switch, &preset_combobox, &band_scales, &updating);
+                sender.send(Action::PlaybackSetEffects(effects.borrow().clone())).unwrap();
+            }
+            gtk::Inhibit(false)
+        });
+
+        // Preset selected
+        let effects = self.effects.clone();
+        let equalizer_switch = self.equalizer_switch.clone();
+        let band_scales = self.band_scales.clone();
+        let updating = self.updating.clone();
+        let sender = self.sender.clone();
+        self.preset_combobox.connect_changed(move |combobox| {
+            if updating.get() {
+                return;
+            }
+
+            let preset = EqualizerPreset::from_str(&combobox.get_active_id().map(|id| id.to_string()).unwrap_or_default());
+            {
+                let mut effects = effects.borrow_mut();
+                effects.preset = preset;
+                effects.bands = preset.get_bands().unwrap_or_else(Effects::get_custom_bands);
+            }
+            Self::update(&effects.borrow(), &equalizer_switch, combobox, &band_scales, &updating);
+            sender.send(Action::PlaybackSetEffects(effects.borrow().clone())).unwrap();
+        });
+
+        // Band changed -> it's a custom preset now
+        for (i, scale) in self.band_scales.iter().enumerate() {
+            let effects = self.effects.clone();
+            let preset_combobox = self.preset_combobox.clone();
+            let updating = self.updating.clone();
+            let sender = self.sender.clone();
+            scale.connect_value_changed(move |scale| {
+                if updating.get() {
+                    return;
+                }
+
+                {
+                    let mut effects = effects.borrow_mut();
+                    effects.preset = EqualizerPreset::Custom;
+                    effects.bands[i] = scale.get_value();
+                }
+                updating.set(true);
+                preset_combobox.set_active_id(Some(EqualizerPreset::Custom.as_str()));
+                updating.set(false);
+
+                sender.send(Action::PlaybackSetEffects(effects.borrow().clone())).unwrap();
+            });
+        }
+
+        // Night mode on / off
+        let effects = self.effects.clone();
+        let updating = self.updating.clone();
+        let sender = self.sender.clone();
+        self.night_mode_switch.connect_state_set(move |_, state| {
+            if !updating.get() {
+                effects.borrow_mut().night_mode = state;
+                sender.send(Action::PlaybackSetEffects(effects.borrow().clone())).unwrap();
+            }
+            gtk::Inhibit(false)
+        });
+    }
+}
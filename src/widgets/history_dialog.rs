@@ -0,0 +1,112 @@
+use glib::Sender;
+use gtk::prelude::*;
+use libhandy::Dialog;
+
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use crate::app::Action;
+use crate::history::{History, HistoryEntry};
+use crate::widgets::history_row::HistoryRow;
+
+// Thousands of rows would make the search sluggish, older songs can still be found by searching
+const MAX_ROWS: usize = 200;
+
+#[derive(Clone)]
+pub struct HistoryDialog {
+    pub widget: Dialog,
+    history_listbox: gtk::ListBox,
+    status_label: gtk::Label,
+
+    entries: Rc<Vec<HistoryEntry>>,
+    sender: Sender<Action>,
+}
+
+impl HistoryDialog {
+    pub fn new(sender: Sender<Action>, window: &gtk::Window) -> Self {
+        let builder = gtk::Builder::new_from_resource("/de/haeckerfelix/Shortwave/gtk/history_dialog.ui");
+        let widget: Dialog = builder.get_object("history_dialog").unwrap();
+        let history_listbox: gtk::ListBox = builder.get_object("history_listbox").unwrap();
+        let status_label: gtk::Label = builder.get_object("status_label").unwrap();
+        let search_entry: gtk::SearchEntry = builder.get_object("search_entry").unwrap();
+        let export_csv_button: gtk::Button = builder.get_object("export_csv_button").unwrap();
+        let export_json_button: gtk::Button = builder.get_object("export_json_button").unwrap();
+
+        let entries = History::get_entries();
+        export_csv_button.set_sensitive(!entries.is_empty());
+        export_json_button.set_sensitive(!entries.is_empty());
+        widget.set_transient_for(window);
+
+        let dialog = Self {
+            widget,
+            history_listbox,
+            status_label,
+            entries: Rc::new(entries),
+            sender,
+        };
+
+        let d = dialog.clone();
+        search_entry.connect_search_changed(move |entry| {
+            let text = entry.get_text().map(|text| text.to_string()).unwrap_or_default();
+            d.update_rows(&text);
+        });
+
+        let d = dialog.clone();
+        export_csv_button.connect_clicked(move |_| d.export("history.csv", History::export_csv));
+
+        let d = dialog.clone();
+        export_json_button.connect_clicked(move |_| d.export("history.json", History::export_json));
+
+        dialog.update_rows("");
+        dialog
+    }
+
+    pub fn show(&self) {
+        self.widget.set_visible(true);
+    }
+
+    fn update_rows(&self, text: &str) {
+        for row in self.history_listbox.get_children() {
+            self.history_listbox.remove(&row);
+        }
+
+        // Newest songs first
+        let matches: Vec<&HistoryEntry> = self.entries.iter().rev().filter(|entry| entry.matches(text)).collect();
+        for entry in matches.iter().take(MAX_ROWS) {
+            let row = HistoryRow::new(entry, true);
+            self.history_listbox.add(&row.widget);
+        }
+
+        let text = if self.entries.is_empty() {
+            "No songs heard yet".to_string()
+        } else if matches.is_empty() {
+            "No songs found".to_string()
+        } else if matches.len() > MAX_ROWS {
+            format!("Showing the latest {} of {} songs", MAX_ROWS, matches.len())
+        } else {
+            format!("{} songs", matches.len())
+        };
+        self.status_label.set_text(&text);
+        self.history_listbox.set_visible(!matches.is_empty());
+    }
+
+    // The whole history gets exported, not only the search results
+    fn export<F>(&self, name: &str, write: F)
+    where
+        F: Fn(&[HistoryEntry], PathBuf) -> std::io::Result<()>,
+    {
+        let export_dialog = gtk::FileChooserNative::new("Export history", &self.widget, gtk::FileChooserAction::Save, "Export", "Cancel");
+        export_dialog.set_current_name(name);
+        export_dialog.set_do_overwrite_confirmation(true);
+        if gtk::ResponseType::from(export_dialog.run()) == gtk::ResponseType::Accept {
+            let path = export_dialog.get_file().unwrap().get_path().unwrap();
+            debug!("Export path: {:?}", path);
+            let message = match write(&self.entries, path) {
+                Ok(()) => format!("Successfully exported {} songs.", self.entries.len()),
+                Err(error) => format!("Could not export history: {}", error.to_string()),
+            };
+            self.sender.send(Action::ViewShowNotification(message)).unwrap();
+        }
+        export_dialog.destroy();
+    }
+}
@@ -0,0 +1,32 @@
+use gtk::prelude::*;
+use libhandy::{ActionRow, ActionRowExt};
+
+use crate::history::HistoryEntry;
+
+pub struct HistoryRow {
+    pub widget: ActionRow,
+}
+
+impl HistoryRow {
+    /// `show_station` can be disabled, if all rows belong to the same station anyway.
+    pub fn new(entry: &HistoryEntry, show_station: bool) -> Self {
+        let widget = ActionRow::new();
+        widget.set_title(&entry.get_display_title());
+        let time = entry.get_time().format("%Y-%m-%d %H:%M").to_string();
+        match show_station {
+            true => widget.set_subtitle(&format!("{} · {}", entry.station_name, time)),
+            false => widget.set_subtitle(&time),
+        }
+        widget.set_icon_name("");
+
+        if entry.recorded {
+            let recorded_image = gtk::Image::new_from_icon_name("media-record-symbolic", gtk::IconSize::__Unknown(4));
+            recorded_image.set_tooltip_text(Some("Recorded"));
+            recorded_image.get_style_context().add_class("dim-label");
+            widget.add_action(&recorded_image);
+        }
+
+        widget.show_all();
+        Self { widget }
+    }
+}
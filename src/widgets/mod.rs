@@ -1,4 +1,11 @@
+pub mod alarm_dialog;
+pub mod cast_picker;
+pub mod effects_popover;
+pub mod history_dialog;
+pub mod history_row;
 pub mod notification;
+pub mod recording_schedule_dialog;
+pub mod settings_dialog;
 pub mod song_listbox;
 pub mod song_row;
 pub mod station_dialog;
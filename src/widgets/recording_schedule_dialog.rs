@@ -0,0 +1,231 @@
+use chrono::{Datelike, Local};
+use glib::Sender;
+use gtk::prelude::*;
+use libhandy::Dialog;
+use rustio::Station;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::app::Action;
+use crate::player::headless_recorder::HeadlessRecorder;
+use crate::player::recording_mode::RecordingMode;
+use crate::scheduler::{ScheduledRecording, WEEKDAY_NAMES};
+
+#[derive(Clone)]
+pub struct RecordingScheduleDialog {
+    pub widget: Dialog,
+    recording_listbox: gtk::ListBox,
+    next_recording_label: gtk::Label,
+
+    recordings: Rc<RefCell<Vec<ScheduledRecording>>>,
+    stations: Rc<Vec<Station>>,
+    sender: Sender<Action>,
+}
+
+impl RecordingScheduleDialog {
+    pub fn new(sender: Sender<Action>, recordings: Vec<ScheduledRecording>, stations: Vec<Station>, window: &gtk::Window) -> Self {
+        let builder = gtk::Builder::new_from_resource("/de/haeckerfelix/Shortwave/gtk/recording_schedule_dialog.ui");
+        let widget: Dialog = builder.get_object("recording_schedule_dialog").unwrap();
+        let recording_listbox: gtk::ListBox = builder.get_object("recording_listbox").unwrap();
+        let next_recording_label: gtk::Label = builder.get_object("next_recording_label").unwrap();
+        let add_button: gtk::Button = builder.get_object("add_button").unwrap();
+
+        // Only stations from the library can be recorded
+        add_button.set_sensitive(!stations.is_empty());
+        widget.set_transient_for(window);
+
+        let dialog = Self {
+            widget,
+            recording_listbox,
+            next_recording_label,
+            recordings: Rc::new(RefCell::new(recordings)),
+            stations: Rc::new(stations),
+            sender,
+        };
+
+        let d = dialog.clone();
+        add_button.connect_clicked(move |_| {
+            let recording = ScheduledRecording::new(d.stations[0].clone());
+            d.recordings.borrow_mut().push(recording.clone());
+            d.add_row(&recording);
+            d.save();
+        });
+
+        for recording in dialog.recordings.borrow().iter() {
+            dialog.add_row(recording);
+        }
+        dialog.update_next_recording();
+        dialog
+    }
+
+    pub fn show(&self) {
+        self.widget.set_visible(true);
+    }
+
+    fn add_row(&self, recording: &ScheduledRecording) {
+        let builder = gtk::Builder::new_from_resource("/de/haeckerfelix/Shortwave/gtk/recording_schedule_row.ui");
+        let row: gtk::ListBoxRow = builder.get_object("recording_row").unwrap();
+        let hour_spinbutton: gtk::SpinButton = builder.get_object("hour_spinbutton").unwrap();
+        let minute_spinbutton: gtk::SpinButton = builder.get_object("minute_spinbutton").unwrap();
+        let end_hour_spinbutton: gtk::SpinButton = builder.get_object("end_hour_spinbutton").unwrap();
+        let end_minute_spinbutton: gtk::SpinButton = builder.get_object("end_minute_spinbutton").unwrap();
+        let station_combobox: gtk::ComboBoxText = builder.get_object("station_combobox").unwrap();
+        let mode_combobox: gtk::ComboBoxText = builder.get_object("mode_combobox").unwrap();
+        let enabled_switch: gtk::Switch = builder.get_object("enabled_switch").unwrap();
+        let remove_button: gtk::Button = builder.get_object("remove_button").unwrap();
+
+        // Keep the station of the recording selectable, even if it got removed from the library
+        let mut stations: Vec<Station> = self.stations.to_vec();
+        if !stations.iter().any(|station| station.id == recording.station.id) {
+            stations.insert(0, recording.station.clone());
+        }
+        for station in &stations {
+            station_combobox.append(Some(&station.id), &station.name);
+        }
+        let stations = Rc::new(stations);
+
+        // The signals get connected after the values are set, so we don't need an `updating` flag here
+        hour_spinbutton.set_value(f64::from(recording.hour));
+        minute_spinbutton.set_value(f64::from(recording.minute));
+        end_hour_spinbutton.set_value(f64::from(recording.end_hour));
+        end_minute_spinbutton.set_value(f64::from(recording.end_minute));
+        station_combobox.set_active_id(Some(&recording.station.id));
+        mode_combobox.set_active_id(Some(recording.mode.as_str()));
+        enabled_switch.set_active(recording.enabled);
+
+        let id = recording.id.clone();
+        let d = self.clone();
+        hour_spinbutton.connect_value_changed(move |spinbutton| {
+            let hour = spinbutton.get_value_as_int() as u32;
+            d.update_recording(&id, |recording| recording.hour = hour);
+        });
+
+        let id = recording.id.clone();
+        let d = self.clone();
+        minute_spinbutton.connect_value_changed(move |spinbutton| {
+            let minute = spinbutton.get_value_as_int() as u32;
+            d.update_recording(&id, |recording| recording.minute = minute);
+        });
+
+        let id = recording.id.clone();
+        let d = self.clone();
+        end_hour_spinbutton.connect_value_changed(move |spinbutton| {
+            let hour = spinbutton.get_value_as_int() as u32;
+            d.update_recording(&id, |recording| recording.end_hour = hour);
+        });
+
+        let id = recording.id.clone();
+        let d = self.clone();
+        end_minute_spinbutton.connect_value_changed(move |spinbutton| {
+            let minute = spinbutton.get_value_as_int() as u32;
+            d.update_recording(&id, |recording| recording.end_minute = minute);
+        });
+
+        // Show leading zeros, like a clock does
+        let format_time = |spinbutton: &gtk::SpinButton| {
+            spinbutton.set_text(&format!("{:02}", spinbutton.get_value_as_int()));
+            gtk::Inhibit(true)
+        };
+        hour_spinbutton.connect_output(format_time);
+        minute_spinbutton.connect_output(format_time);
+        end_hour_spinbutton.connect_output(format_time);
+        end_minute_spinbutton.connect_output(format_time);
+
+        let id = recording.id.clone();
+        let d = self.clone();
+        station_combobox.connect_changed(move |combobox| {
+            let active = combobox.get_active_id().map(|id| id.to_string());
+            if let Some(station) = stations.iter().find(|station| Some(&station.id) == active.as_ref()) {
+                d.update_recording(&id, |recording| recording.station = station.clone());
+            }
+        });
+
+        let id = recording.id.clone();
+        let d = self.clone();
+        mode_combobox.connect_changed(move |combobox| {
+            let mode = match combobox.get_active_id().map(|id| id.to_string()).unwrap_or_default().as_str() {
+                "per-song" => RecordingMode::PerSong,
+                _ => RecordingMode::Continuous,
+            };
+            d.update_recording(&id, |recording| recording.mode = mode);
+        });
+
+        let id = recording.id.clone();
+        let d = self.clone();
+        enabled_switch.connect_state_set(move |_, state| {
+            d.update_recording(&id, |recording| recording.enabled = state);
+            gtk::Inhibit(false)
+        });
+
+        for day in 0..WEEKDAY_NAMES.len() as u32 {
+            let button: gtk::ToggleButton = builder.get_object(&format!("weekday_button_{}", day)).unwrap();
+            button.set_active(recording.weekdays.contains(&day));
+
+            let id = recording.id.clone();
+            let d = self.clone();
+            button.connect_toggled(move |button| {
+                let active = button.get_active();
+                d.update_recording(&id, |recording| {
+                    recording.weekdays.retain(|weekday| *weekday != day);
+                    if active {
+                        recording.weekdays.push(day);
+                        recording.weekdays.sort();
+                    }
+                });
+            });
+        }
+
+        let id = recording.id.clone();
+        let d = self.clone();
+        let r = row.clone();
+        remove_button.connect_clicked(move |_| {
+            d.recordings.borrow_mut().retain(|recording| recording.id != id);
+            d.recording_listbox.remove(&r);
+            d.save();
+        });
+
+        self.recording_listbox.add(&row);
+    }
+
+    fn update_recording<F: FnOnce(&mut ScheduledRecording)>(&self, id: &str, update: F) {
+        if let Some(recording) = self.recordings.borrow_mut().iter_mut().find(|recording| recording.id == id) {
+            update(recording);
+        }
+        self.save();
+    }
+
+    fn save(&self) {
+        self.sender.send(Action::SchedulerSetRecordings(self.recordings.borrow().clone())).unwrap();
+        self.update_next_recording();
+    }
+
+    fn update_next_recording(&self) {
+        let now = Local::now();
+        let next = self
+            .recordings
+            .borrow()
+            .iter()
+            .filter(|recording| recording.enabled)
+            .filter_map(|recording| recording.get_next_occurrence(now).map(|time| (recording.clone(), time)))
+            .min_by_key(|(_, time)| *time);
+
+        let text = match next {
+            Some((recording, time)) => {
+                let day = if time.date() == Local::today() {
+                    "Today".to_string()
+                } else if time.date() == Local::today().succ() {
+                    "Tomorrow".to_string()
+                } else {
+                    WEEKDAY_NAMES[time.weekday().num_days_from_monday() as usize].to_string()
+                };
+                let end = time + recording.get_duration();
+                format!("Next recording: {} {} – {} \"{}\"", day, time.format("%H:%M"), end.format("%H:%M"), recording.station.name)
+            }
+            None => "No recording scheduled".to_string(),
+        };
+
+        let directory = HeadlessRecorder::get_recording_dir();
+        self.next_recording_label.set_text(&format!("{}\nRecordings get saved in {}", text, directory.to_string_lossy()));
+    }
+}
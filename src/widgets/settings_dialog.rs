@@ -0,0 +1,325 @@
+use glib::Sender;
+use gtk::prelude::*;
+use libhandy::Dialog;
+
+use crate::app::Action;
+use crate::filename_template::FilenameTemplate;
+use crate::player::recording_format::RecordingFormat;
+use crate::player::recording_mode::RecordingMode;
+use crate::player::recording_storage::RecordingStorage;
+use crate::settings::{Key, SettingsManager};
+
+#[derive(Clone)]
+pub struct SettingsDialog {
+    pub widget: Dialog,
+    mode_combobox: gtk::ComboBoxText,
+    passthrough_switch: gtk::Switch,
+    format_combobox: gtk::ComboBoxText,
+    quality_label: gtk::Label,
+    quality_scale: gtk::Scale,
+    bitrate_label: gtk::Label,
+    bitrate_spinbutton: gtk::SpinButton,
+    skip_partial_switch: gtk::Switch,
+    minimum_duration_spinbutton: gtk::SpinButton,
+    maximum_duration_spinbutton: gtk::SpinButton,
+    preroll_spinbutton: gtk::SpinButton,
+    skip_saved_switch: gtk::Switch,
+    auto_save_all_switch: gtk::Switch,
+    auto_save_artists_entry: gtk::Entry,
+    auto_save_bitrate_spinbutton: gtk::SpinButton,
+    auto_save_duration_spinbutton: gtk::SpinButton,
+    location_button: gtk::FileChooserButton,
+    location_reset_button: gtk::Button,
+    max_songs_spinbutton: gtk::SpinButton,
+    max_size_spinbutton: gtk::SpinButton,
+    max_age_spinbutton: gtk::SpinButton,
+    min_free_space_spinbutton: gtk::SpinButton,
+    filename_template_entry: gtk::Entry,
+    filename_example_label: gtk::Label,
+    storage_info_label: gtk::Label,
+    title_filter_builtin_switch: gtk::Switch,
+    title_filter_entry: gtk::Entry,
+    loudness_switch: gtk::Switch,
+
+    sender: Sender<Action>,
+}
+
+impl SettingsDialog {
+    pub fn new(sender: Sender<Action>, window: &gtk::Window) -> Self {
+        let builder = gtk::Builder::new_from_resource("/de/haeckerfelix/Shortwave/gtk/settings_dialog.ui");
+        let widget: Dialog = builder.get_object("settings_dialog").unwrap();
+        let mode_combobox: gtk::ComboBoxText = builder.get_object("mode_combobox").unwrap();
+        let passthrough_switch: gtk::Switch = builder.get_object("passthrough_switch").unwrap();
+        let format_combobox: gtk::ComboBoxText = builder.get_object("format_combobox").unwrap();
+        let quality_label: gtk::Label = builder.get_object("quality_label").unwrap();
+        let quality_scale: gtk::Scale = builder.get_object("quality_scale").unwrap();
+        let bitrate_label: gtk::Label = builder.get_object("bitrate_label").unwrap();
+        let bitrate_spinbutton: gtk::SpinButton = builder.get_object("bitrate_spinbutton").unwrap();
+        let skip_partial_switch: gtk::Switch = builder.get_object("skip_partial_switch").unwrap();
+        let minimum_duration_spinbutton: gtk::SpinButton = builder.get_object("minimum_duration_spinbutton").unwrap();
+        let maximum_duration_spinbutton: gtk::SpinButton = builder.get_object("maximum_duration_spinbutton").unwrap();
+        let preroll_spinbutton: gtk::SpinButton = builder.get_object("preroll_spinbutton").unwrap();
+        let skip_saved_switch: gtk::Switch = builder.get_object("skip_saved_switch").unwrap();
+        let auto_save_all_switch: gtk::Switch = builder.get_object("auto_save_all_switch").unwrap();
+        let auto_save_artists_entry: gtk::Entry = builder.get_object("auto_save_artists_entry").unwrap();
+        let auto_save_bitrate_spinbutton: gtk::SpinButton = builder.get_object("auto_save_bitrate_spinbutton").unwrap();
+        let auto_save_duration_spinbutton: gtk::SpinButton = builder.get_object("auto_save_duration_spinbutton").unwrap();
+        let location_button: gtk::FileChooserButton = builder.get_object("location_button").unwrap();
+        let location_reset_button: gtk::Button = builder.get_object("location_reset_button").unwrap();
+        let max_songs_spinbutton: gtk::SpinButton = builder.get_object("max_songs_spinbutton").unwrap();
+        let max_size_spinbutton: gtk::SpinButton = builder.get_object("max_size_spinbutton").unwrap();
+        let max_age_spinbutton: gtk::SpinButton = builder.get_object("max_age_spinbutton").unwrap();
+        let min_free_space_spinbutton: gtk::SpinButton = builder.get_object("min_free_space_spinbutton").unwrap();
+        let filename_template_entry: gtk::Entry = builder.get_object("filename_template_entry").unwrap();
+        let filename_example_label: gtk::Label = builder.get_object("filename_example_label").unwrap();
+        let storage_info_label: gtk::Label = builder.get_object("storage_info_label").unwrap();
+        let title_filter_builtin_switch: gtk::Switch = builder.get_object("title_filter_builtin_switch").unwrap();
+        let title_filter_entry: gtk::Entry = builder.get_object("title_filter_entry").unwrap();
+        let loudness_switch: gtk::Switch = builder.get_object("loudness_switch").unwrap();
+        widget.set_transient_for(window);
+
+        for mode in RecordingMode::all() {
+            mode_combobox.append(Some(mode.as_str()), mode.get_title());
+        }
+        for format in RecordingFormat::all() {
+            if format.is_available() {
+                format_combobox.append(Some(format.as_str()), format.get_title());
+            } else {
+                format_combobox.append(Some(format.as_str()), &format!("{} (not installed)", format.get_title()));
+            }
+        }
+
+        let dialog = Self {
+            widget,
+            mode_combobox,
+            passthrough_switch,
+            format_combobox,
+            quality_label,
+            quality_scale,
+            bitrate_label,
+            bitrate_spinbutton,
+            skip_partial_switch,
+            minimum_duration_spinbutton,
+            maximum_duration_spinbutton,
+            preroll_spinbutton,
+            skip_saved_switch,
+            auto_save_all_switch,
+            auto_save_artists_entry,
+            auto_save_bitrate_spinbutton,
+            auto_save_duration_spinbutton,
+            location_button,
+            location_reset_button,
+            max_songs_spinbutton,
+            max_size_spinbutton,
+            max_age_spinbutton,
+            min_free_space_spinbutton,
+            filename_template_entry,
+            filename_example_label,
+            storage_info_label,
+            title_filter_builtin_switch,
+            title_filter_entry,
+            loudness_switch,
+            sender,
+        };
+
+        // The signals get connected after the values are set, so we don't need an `updating` flag here
+        dialog.mode_combobox.set_active_id(Some(RecordingMode::load(None).as_str()));
+        dialog.passthrough_switch.set_active(SettingsManager::get_boolean(Key::RecordingPassthrough));
+        let format = RecordingFormat::load();
+        dialog.format_combobox.set_active_id(Some(format.as_str()));
+        dialog.quality_scale.set_value(SettingsManager::get_double(Key::RecordingQuality));
+        dialog.bitrate_spinbutton.set_value(f64::from(SettingsManager::get_uint(Key::RecordingBitrate)));
+        dialog.update_format(format);
+        dialog.skip_partial_switch.set_active(SettingsManager::get_boolean(Key::RecordingSkipPartial));
+        dialog.minimum_duration_spinbutton.set_value(f64::from(SettingsManager::get_uint(Key::RecordingMinimumDuration)));
+        // The setting uses seconds, like the minimum
+        dialog.maximum_duration_spinbutton.set_value(f64::from(SettingsManager::get_uint(Key::RecordingMaximumDuration) / 60));
+        dialog.preroll_spinbutton.set_value(SettingsManager::get_double(Key::RecordingPreroll));
+        dialog.skip_saved_switch.set_active(SettingsManager::get_boolean(Key::RecordingSkipSaved));
+        dialog.auto_save_all_switch.set_active(SettingsManager::get_boolean(Key::AutoSaveAllSongs));
+        dialog.auto_save_artists_entry.set_text(&SettingsManager::get_string(Key::AutoSaveArtists));
+        dialog.auto_save_bitrate_spinbutton.set_value(f64::from(SettingsManager::get_uint(Key::AutoSaveMinimumBitrate)));
+        dialog.auto_save_duration_spinbutton.set_value(f64::from(SettingsManager::get_uint(Key::AutoSaveMinimumDuration)));
+        let location = SettingsManager::get_string(Key::RecordingDirectory);
+        if !location.is_empty() {
+            dialog.location_button.set_filename(&location);
+        }
+        dialog.max_songs_spinbutton.set_value(f64::from(SettingsManager::get_uint(Key::RecordingMaxSongs)));
+        dialog.max_size_spinbutton.set_value(f64::from(SettingsManager::get_uint(Key::RecordingMaxSize)));
+        dialog.max_age_spinbutton.set_value(f64::from(SettingsManager::get_uint(Key::RecordingMaxAge)));
+        dialog.min_free_space_spinbutton.set_value(f64::from(SettingsManager::get_uint(Key::RecordingMinFreeSpace)));
+        dialog.filename_template_entry.set_text(&SettingsManager::get_string(Key::RecordingFilenameTemplate));
+        dialog.update_storage_info();
+        dialog.update_filename_example();
+        dialog.title_filter_builtin_switch.set_active(SettingsManager::get_boolean(Key::TitleFilterBuiltin));
+        dialog.title_filter_entry.set_text(&SettingsManager::get_string(Key::TitleFilterPatterns));
+        dialog.loudness_switch.set_active(SettingsManager::get_boolean(Key::LoudnessNormalization));
+
+        dialog.setup_signals();
+        dialog
+    }
+
+    pub fn show(&self) {
+        self.widget.set_visible(true);
+    }
+
+    fn setup_signals(&self) {
+        let sender = self.sender.clone();
+        self.mode_combobox.connect_changed(move |combobox| {
+            let mode = RecordingMode::from_str(&combobox.get_active_id().map(|id| id.to_string()).unwrap_or_default());
+            SettingsManager::set_string(Key::RecordingMode, mode.as_str());
+            sender.send(Action::PlaybackUpdateRecordingMode).unwrap();
+        });
+
+        self.passthrough_switch.connect_state_set(|_, state| {
+            SettingsManager::set_boolean(Key::RecordingPassthrough, state);
+            gtk::Inhibit(false)
+        });
+
+        let d = self.clone();
+        let sender = self.sender.clone();
+        self.format_combobox.connect_changed(move |combobox| {
+            let format = RecordingFormat::from_str(&combobox.get_active_id().map(|id| id.to_string()).unwrap_or_default());
+
+            // Recording would fail right away, so keep the previous format
+            if !format.is_available() {
+                let previous = RecordingFormat::load();
+                if format != previous {
+                    let message = format!("{} cannot be used, the GStreamer plugin for it is not installed", format.get_title());
+                    sender.send(Action::ViewShowNotification(message)).unwrap();
+                    combobox.set_active_id(Some(previous.as_str()));
+                }
+                return;
+            }
+
+            SettingsManager::set_string(Key::RecordingFormat, format.as_str());
+            d.update_format(format);
+            d.update_filename_example();
+        });
+
+        self.quality_scale.connect_value_changed(|scale| {
+            SettingsManager::set_double(Key::RecordingQuality, scale.get_value());
+        });
+
+        self.bitrate_spinbutton.connect_value_changed(|spinbutton| {
+            SettingsManager::set_uint(Key::RecordingBitrate, spinbutton.get_value_as_int() as u32);
+        });
+
+        self.skip_partial_switch.connect_state_set(|_, state| {
+            SettingsManager::set_boolean(Key::RecordingSkipPartial, state);
+            gtk::Inhibit(false)
+        });
+
+        self.minimum_duration_spinbutton.connect_value_changed(|spinbutton| {
+            SettingsManager::set_uint(Key::RecordingMinimumDuration, spinbutton.get_value_as_int() as u32);
+        });
+
+        self.maximum_duration_spinbutton.connect_value_changed(|spinbutton| {
+            SettingsManager::set_uint(Key::RecordingMaximumDuration, spinbutton.get_value_as_int() as u32 * 60);
+        });
+
+        self.preroll_spinbutton.connect_value_changed(|spinbutton| {
+            SettingsManager::set_double(Key::RecordingPreroll, spinbutton.get_value());
+        });
+
+        self.skip_saved_switch.connect_state_set(|_, state| {
+            SettingsManager::set_boolean(Key::RecordingSkipSaved, state);
+            gtk::Inhibit(false)
+        });
+
+        self.auto_save_all_switch.connect_state_set(|_, state| {
+            SettingsManager::set_boolean(Key::AutoSaveAllSongs, state);
+            gtk::Inhibit(false)
+        });
+
+        self.auto_save_artists_entry.connect_changed(|entry| {
+            let artists = entry.get_text().map(|text| text.to_string()).unwrap_or_default();
+            SettingsManager::set_string(Key::AutoSaveArtists, &artists);
+        });
+
+        self.auto_save_bitrate_spinbutton.connect_value_changed(|spinbutton| {
+            SettingsManager::set_uint(Key::AutoSaveMinimumBitrate, spinbutton.get_value_as_int() as u32);
+        });
+
+        self.auto_save_duration_spinbutton.connect_value_changed(|spinbutton| {
+            SettingsManager::set_uint(Key::AutoSaveMinimumDuration, spinbutton.get_value_as_int() as u32);
+        });
+
+        let d = self.clone();
+        self.location_button.connect_file_set(move |button| {
+            if let Some(location) = button.get_filename() {
+                SettingsManager::set_string(Key::RecordingDirectory, &location.to_string_lossy());
+                d.update_storage_info();
+            }
+        });
+
+        let d = self.clone();
+        self.location_reset_button.connect_clicked(move |_| {
+            SettingsManager::set_string(Key::RecordingDirectory, "");
+            d.location_button.unselect_all();
+            d.update_storage_info();
+        });
+
+        self.max_songs_spinbutton.connect_value_changed(|spinbutton| {
+            SettingsManager::set_uint(Key::RecordingMaxSongs, spinbutton.get_value_as_int() as u32);
+        });
+
+        self.max_size_spinbutton.connect_value_changed(|spinbutton| {
+            SettingsManager::set_uint(Key::RecordingMaxSize, spinbutton.get_value_as_int() as u32);
+        });
+
+        self.max_age_spinbutton.connect_value_changed(|spinbutton| {
+            SettingsManager::set_uint(Key::RecordingMaxAge, spinbutton.get_value_as_int() as u32);
+        });
+
+        self.min_free_space_spinbutton.connect_value_changed(|spinbutton| {
+            SettingsManager::set_uint(Key::RecordingMinFreeSpace, spinbutton.get_value_as_int() as u32);
+        });
+
+        let d = self.clone();
+        self.filename_template_entry.connect_changed(move |entry| {
+            let template = entry.get_text().map(|text| text.to_string()).unwrap_or_default();
+            SettingsManager::set_string(Key::RecordingFilenameTemplate, &template);
+            d.update_filename_example();
+        });
+
+        self.title_filter_builtin_switch.connect_state_set(|_, state| {
+            SettingsManager::set_boolean(Key::TitleFilterBuiltin, state);
+            gtk::Inhibit(false)
+        });
+
+        self.title_filter_entry.connect_changed(|entry| {
+            let patterns = entry.get_text().map(|text| text.to_string()).unwrap_or_default();
+            SettingsManager::set_string(Key::TitleFilterPatterns, &patterns);
+        });
+
+        // The player follows the setting right away
+        self.loudness_switch.connect_state_set(|_, state| {
+            SettingsManager::set_boolean(Key::LoudnessNormalization, state);
+            gtk::Inhibit(false)
+        });
+    }
+
+    fn update_storage_info(&self) {
+        let directory = RecordingStorage::get_directory();
+        self.location_reset_button.set_sensitive(!SettingsManager::get_string(Key::RecordingDirectory).is_empty());
+        self.storage_info_label.set_text(&format!(
+            "Songs get recorded to {}. Files in this folder which are not in the song list get removed. The limits apply to the next song.",
+            directory.to_string_lossy()
+        ));
+    }
+
+    fn update_filename_example(&self) {
+        let template = SettingsManager::get_string(Key::RecordingFilenameTemplate);
+        let path = FilenameTemplate::get_example(&template, RecordingFormat::load().get_extension());
+        self.filename_example_label.set_text(&format!("Example: {}", path.to_string_lossy()));
+    }
+
+    // Only show the setting which gets used by the selected format
+    fn update_format(&self, format: RecordingFormat) {
+        self.quality_label.set_visible(!format.uses_bitrate());
+        self.quality_scale.set_visible(!format.uses_bitrate());
+        self.bitrate_label.set_visible(format.uses_bitrate());
+        self.bitrate_spinbutton.set_visible(format.uses_bitrate());
+    }
+}
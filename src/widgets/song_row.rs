@@ -1,33 +1,72 @@
 use chrono::NaiveTime;
+use gio::prelude::*;
 use glib::Sender;
 use gtk::prelude::*;
 use libhandy::{ActionRow, ActionRowExt};
 use open;
 
-use std::path::PathBuf;
+use std::fs;
+use std::io;
 
 use crate::app::Action;
+use crate::player::recording_format::RecordingFormat;
+use crate::player::track_splitter::TrackSplitter;
+use crate::recording_index::{IndexEntry, RecordingIndex};
 use crate::song::Song;
 
 pub struct SongRow {
     pub widget: ActionRow,
     song: Song,
+    // The user has saved this song before
+    saved_copy: Option<IndexEntry>,
     button_stack: gtk::Stack,
     save_button: gtk::Button,
     open_button: gtk::Button,
+    split_button: gtk::Button,
+
+    sender: Sender<Action>,
 }
 
 impl SongRow {
-    pub fn new(_sender: Sender<Action>, song: Song) -> Self {
+    pub fn new(sender: Sender<Action>, song: Song) -> Self {
+        // Sessions aren't songs, so there's nothing to compare them with
+        let saved_copy = match song.chapters.is_empty() {
+            true => RecordingIndex::find(&song.title, &song.artist),
+            false => None,
+        };
+
         let widget = ActionRow::new();
         widget.set_title(&song.title);
-        widget.set_subtitle(&Self::format_duration(song.duration.as_secs()));
+        let mut details: Vec<String> = song.artist.iter().cloned().collect();
+        details.push(Self::format_duration(song.duration.as_secs()));
+        match saved_copy {
+            // The badge already tells it
+            _ if song.auto_saved => (),
+            Some(ref copy) if copy.is_worse_than(&song) => details.push("Already saved, this copy is better".to_string()),
+            Some(_) => details.push("Already saved".to_string()),
+            None => (),
+        }
+        widget.set_subtitle(&details.join(" · "));
         widget.set_icon_name("");
 
+        if song.auto_saved {
+            let badge = gtk::Label::new(Some("Auto-saved"));
+            badge.set_tooltip_text(Some("Copied into the music folder by an auto-save rule"));
+            badge.set_valign(gtk::Align::Center);
+            badge.get_style_context().add_class("badge");
+            widget.add_action(&badge);
+        }
+
         let button_stack = gtk::Stack::new();
         widget.add_action(&button_stack);
 
         let save_button = gtk::Button::new();
+        let tooltip = match saved_copy {
+            Some(ref copy) if copy.is_worse_than(&song) => "Replace the saved copy",
+            Some(_) => "Save another copy",
+            None => "Save",
+        };
+        save_button.set_tooltip_text(Some(tooltip));
         save_button.set_relief(gtk::ReliefStyle::None);
         save_button.set_valign(gtk::Align::Center);
         let save_image = gtk::Image::new_from_icon_name("document-save-symbolic", gtk::IconSize::__Unknown(4));
@@ -41,14 +80,27 @@ impl SongRow {
         open_button.add(&open_image);
         button_stack.add_named(&open_button, "open");
 
+        // Continuous recordings can be split up at their title changes
+        let split_button = gtk::Button::new();
+        split_button.set_tooltip_text(Some("Split into tracks"));
+        split_button.set_relief(gtk::ReliefStyle::None);
+        split_button.set_valign(gtk::Align::Center);
+        let split_image = gtk::Image::new_from_icon_name("edit-cut-symbolic", gtk::IconSize::__Unknown(4));
+        split_button.add(&split_image);
+        widget.add_action(&split_button);
+
         widget.show_all();
+        split_button.set_visible(!song.chapters.is_empty());
 
         let row = Self {
             widget,
             song,
+            saved_copy,
             button_stack,
             save_button,
             open_button,
+            split_button,
+            sender,
         };
 
         row.setup_signals();
@@ -57,29 +109,80 @@ impl SongRow {
 
     fn setup_signals(&self) {
         let song = self.song.clone();
+        let saved_copy = self.saved_copy.clone();
         let widget = self.widget.clone();
         let button_stack = self.button_stack.clone();
         self.save_button.connect_clicked(move |_| {
-            let mut path = PathBuf::from(glib::get_user_special_dir(glib::UserDirectory::Music).unwrap());
-            path.push(&Song::simplify_title(song.title.clone()));
-            match song.save_as(path) {
-                Ok(()) => {
-                    widget.set_subtitle("Saved");
+            let result = match saved_copy {
+                Some(ref copy) if copy.is_worse_than(&song) => Self::replace_copy(&song, copy).map(|_| "Replaced the saved copy"),
+                _ => song.save().map(|_| "Saved"),
+            };
+            match result {
+                Ok(text) => {
+                    widget.set_subtitle(text);
                     button_stack.set_visible_child_name("open");
                 }
                 Err(err) => widget.set_subtitle(&err.to_string()),
             };
         });
 
+        let song = self.song.clone();
+        let widget = self.widget.clone();
+        let sender = self.sender.clone();
+        self.split_button.connect_clicked(move |button| {
+            button.set_sensitive(false);
+            widget.set_subtitle(&format!("Splitting into {} tracks…", song.chapters.len()));
+
+            let button = button.clone();
+            let widget = widget.clone();
+            let tracks = song.chapters.len();
+            TrackSplitter::new(song.clone(), sender.clone()).start(move |success| {
+                match success {
+                    true => widget.set_subtitle(&format!("Split into {} tracks", tracks)),
+                    false => widget.set_subtitle("Could not split into tracks"),
+                }
+                button.set_sensitive(true);
+            });
+        });
+
         let song = self.song.clone();
         self.open_button.connect_clicked(move |_| {
-            open::that(song.path.clone()).expect("Could not play song");
+            // Prefer the default application for the mime type of the recording format
+            let format = song.path.extension().and_then(|extension| RecordingFormat::from_extension(&extension.to_string_lossy()));
+            let app_info = format.and_then(|format| gio::AppInfo::get_default_for_type(format.get_mime_type(), false));
+            match app_info {
+                Some(app_info) => {
+                    let file = gio::File::new_for_path(&song.path);
+                    if let Err(err) = app_info.launch(&[file], None::<&gio::AppLaunchContext>) {
+                        warn!("Could not play song: {}", err);
+                    }
+                }
+                None => {
+                    open::that(song.path.clone()).expect("Could not play song");
+                }
+            }
         });
     }
 
+    // The song takes the place of the saved copy, only the extension can be a different one
+    fn replace_copy(song: &Song, copy: &IndexEntry) -> io::Result<()> {
+        let mut path = copy.path.clone();
+        if let Some(extension) = song.path.extension() {
+            path.set_extension(extension);
+        }
+        let path = if path != copy.path && path.exists() { Song::get_unique_path(path) } else { path };
+        song.save_as(path.clone())?;
+
+        if path != copy.path {
+            fs::remove_file(&copy.path)?;
+        }
+        RecordingIndex::add(song, path);
+        Ok(())
+    }
+
     // stolen from gnome-podcasts
     // https://gitlab.gnome.org/haecker-felix/podcasts/blob/2f8a6a91f87d7fa335a954bbaf2f70694f32f6dd/podcasts-gtk/src/widgets/player.rs#L168
-    fn format_duration(seconds: u64) -> String {
+    pub fn format_duration(seconds: u64) -> String {
         let time = NaiveTime::from_num_seconds_from_midnight(seconds as u32, 0);
 
         if seconds >= 3600 {
@@ -4,7 +4,17 @@ use libhandy::Dialog;
 use rustio::Station;
 
 use crate::app::Action;
+use crate::auto_save::AutoSave;
+use crate::history::History;
 use crate::library::Library;
+use crate::metadata::MetadataParser;
+use crate::player::recording_mode::RecordingMode;
+use crate::settings::{Key, SettingsManager};
+use crate::title_filter::TitleFilter;
+use crate::widgets::history_row::HistoryRow;
+
+// Number of songs in the "Recently Heard" list
+const HISTORY_ROWS: usize = 10;
 
 pub struct StationDialog {
     pub widget: Dialog,
@@ -16,6 +26,10 @@ pub struct StationDialog {
     homepage_label: gtk::Label,
     tags_label: gtk::Label,
     language_label: gtk::Label,
+    metadata_pattern_entry: gtk::Entry,
+    recording_mode_combobox: gtk::ComboBoxText,
+    auto_save_switch: gtk::Switch,
+    title_filter_entry: gtk::Entry,
 
     builder: gtk::Builder,
     sender: Sender<Action>,
@@ -32,6 +46,10 @@ impl StationDialog {
         let homepage_label: gtk::Label = builder.get_object("homepage_label").unwrap();
         let tags_label: gtk::Label = builder.get_object("tags_label").unwrap();
         let language_label: gtk::Label = builder.get_object("language_label").unwrap();
+        let metadata_pattern_entry: gtk::Entry = builder.get_object("metadata_pattern_entry").unwrap();
+        let recording_mode_combobox: gtk::ComboBoxText = builder.get_object("recording_mode_combobox").unwrap();
+        let auto_save_switch: gtk::Switch = builder.get_object("auto_save_switch").unwrap();
+        let title_filter_entry: gtk::Entry = builder.get_object("title_filter_entry").unwrap();
 
         // Show correct library action
         let library_action_stack: gtk::Stack = builder.get_object("library_action_stack").unwrap();
@@ -52,6 +70,10 @@ impl StationDialog {
             homepage_label,
             tags_label,
             language_label,
+            metadata_pattern_entry,
+            recording_mode_combobox,
+            auto_save_switch,
+            title_filter_entry,
             builder,
             sender,
         };
@@ -78,6 +100,32 @@ impl StationDialog {
         if self.station.language != "" {
             self.language_label.set_text(&self.station.language);
         }
+        if let Some(pattern) = MetadataParser::get_pattern(&self.station.id) {
+            self.metadata_pattern_entry.set_text(&pattern);
+        }
+
+        // "default" follows the global mode of the preferences
+        let default_mode = RecordingMode::from_str(&SettingsManager::get_string(Key::RecordingMode));
+        self.recording_mode_combobox.append(Some("default"), &format!("Default ({})", default_mode.get_title()));
+        for mode in RecordingMode::all() {
+            self.recording_mode_combobox.append(Some(mode.as_str()), mode.get_title());
+        }
+        let mode_id = RecordingMode::get_station_mode(&self.station.id).map_or("default", |mode| mode.as_str());
+        self.recording_mode_combobox.set_active_id(Some(mode_id));
+        self.auto_save_switch.set_active(AutoSave::is_station_enabled(&self.station.id));
+        if let Some(patterns) = TitleFilter::get_station_patterns(&self.station.id) {
+            self.title_filter_entry.set_text(&patterns);
+        }
+
+        // Only shown if we've already heard something on this station
+        let history_box: gtk::Box = self.builder.get_object("history_box").unwrap();
+        let history_listbox: gtk::ListBox = self.builder.get_object("history_listbox").unwrap();
+        let entries = History::get_station_entries(&self.station.id, HISTORY_ROWS);
+        for entry in &entries {
+            let row = HistoryRow::new(entry, false);
+            history_listbox.add(&row.widget);
+        }
+        history_box.set_visible(!entries.is_empty());
     }
 
     pub fn show(&self) {
@@ -104,5 +152,38 @@ impl StationDialog {
             sender.send(Action::LibraryAddStations(vec![station.clone()])).unwrap();
             library_action_stack.set_visible_child_name("library-remove");
         });
+
+        // metadata_pattern_entry
+        let station_id = self.station.id.clone();
+        self.metadata_pattern_entry.connect_changed(move |entry| {
+            let pattern = entry.get_text().map(|text| text.to_string());
+            MetadataParser::set_pattern(&station_id, pattern);
+        });
+
+        // recording_mode_combobox
+        let station_id = self.station.id.clone();
+        let sender = self.sender.clone();
+        self.recording_mode_combobox.connect_changed(move |combobox| {
+            let mode = match combobox.get_active_id().map(|id| id.to_string()) {
+                Some(ref id) if id != "default" => Some(RecordingMode::from_str(id)),
+                _ => None,
+            };
+            RecordingMode::set_station_mode(&station_id, mode);
+            sender.send(Action::PlaybackUpdateRecordingMode).unwrap();
+        });
+
+        // auto_save_switch
+        let station_id = self.station.id.clone();
+        self.auto_save_switch.connect_state_set(move |_, state| {
+            AutoSave::set_station_enabled(&station_id, state);
+            gtk::Inhibit(false)
+        });
+
+        // title_filter_entry
+        let station_id = self.station.id.clone();
+        self.title_filter_entry.connect_changed(move |entry| {
+            let patterns = entry.get_text().map(|text| text.to_string());
+            TitleFilter::set_station_patterns(&station_id, patterns);
+        });
     }
 }